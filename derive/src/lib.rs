@@ -1,6 +1,8 @@
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
-use quote::{quote, quote_spanned};
+use quote::{format_ident, quote, quote_spanned};
+use syn::ext::IdentExt;
+use syn::meta::ParseNestedMeta;
 use syn::parse::Parse;
 use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
@@ -18,389 +20,576 @@ pub fn derive_validation(input: TokenStream) -> TokenStream {
 fn impl_validate(input: &DeriveInput) -> syn::Result<TokenStream2> {
     let name = &input.ident;
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let state = parse_state(input)?;
 
-    let fields = match &input.data {
-        Data::Struct(data) => match &data.fields {
-            Fields::Named(fields) => &fields.named,
-            _ => {
-                return Err(syn::Error::new_spanned(
-                    input,
-                    "Validate can only be derived for structs with named fields",
-                ));
+    let mut constraints_impl = TokenStream2::new();
+    let (sync_body, async_body) = match &input.data {
+        Data::Struct(data) => {
+            let fields = parse_fields(&data.fields)?;
+            let pattern = destructure(quote! { Self }, &fields);
+            let sync = generate_fields(&fields, Mode::Sync)?;
+            let async_ = generate_fields(&fields, Mode::Async)?;
+
+            let arms = fields.iter().map(|field| {
+                let name = &field.name;
+                let constraints = generate_constraints(field);
+                quote! { #name => #constraints, }
+            });
+            constraints_impl = quote! {
+                impl #impl_generics crate::validate::Constraints for #name #ty_generics #where_clause {
+                    fn constraints(field: &str) -> crate::validate::FieldConstraints {
+                        match field {
+                            #(#arms)*
+                            _ => crate::validate::FieldConstraints::default(),
+                        }
+                    }
+                }
+            };
+
+            (
+                quote! { let #pattern = *self; #sync },
+                quote! { let #pattern = *self; #async_ },
+            )
+        }
+        Data::Enum(data) => {
+            let mut sync_arms = Vec::new();
+            let mut async_arms = Vec::new();
+
+            for variant in &data.variants {
+                let variant_ident = &variant.ident;
+                let fields = parse_fields(&variant.fields)?;
+                let pattern = destructure(quote! { Self::#variant_ident }, &fields);
+                let sync = generate_fields(&fields, Mode::Sync)?;
+                let async_ = generate_fields(&fields, Mode::Async)?;
+                sync_arms.push(quote! { #pattern => { #sync } });
+                async_arms.push(quote! { #pattern => { #async_ } });
             }
-        },
-        _ => {
+
+            (
+                quote! { match *self { #(#sync_arms)* } },
+                quote! { match *self { #(#async_arms)* } },
+            )
+        }
+        Data::Union(_) => {
             return Err(syn::Error::new_spanned(
                 input,
-                "Validate can only be derived for structs",
+                "Validate cannot be derived for unions",
             ));
         }
     };
 
-    let mut field_validations = Vec::new();
+    Ok(quote! {
+        impl #impl_generics crate::validate::Validate for #name #ty_generics #where_clause {
+            fn validate(&self) -> ::std::result::Result<(), crate::validate::ValidationErrors> {
+                let mut errors = crate::validate::ValidationErrors::new();
+                #sync_body
+                errors.into_result()
+            }
+        }
 
-    for field in fields {
-        let field_ident = field.ident.as_ref().unwrap();
-        let field_name = field_ident.to_string();
+        impl #impl_generics crate::validate::ValidateAsync<#state> for #name #ty_generics #where_clause {
+            async fn validate_async(
+                &self,
+                state: &#state,
+            ) -> ::std::result::Result<(), crate::validate::ValidationErrors> {
+                let _ = state;
+                let mut errors = crate::validate::ValidationErrors::new();
+                #async_body
+                errors.into_result()
+            }
+        }
 
-        for attr in &field.attrs {
-            if !attr.path().is_ident("validate") {
-                continue;
+        #constraints_impl
+    })
+}
+
+/// The state handed to `async_custom` validators, which is `AppState` unless
+/// the type says otherwise with `#[validate(state = "Type")]`.
+fn parse_state(input: &DeriveInput) -> syn::Result<syn::Type> {
+    let mut state = None;
+
+    for attr in &input.attrs {
+        if !attr.path().is_ident("validate") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("state") {
+                let value: Expr = meta.value()?.parse()?;
+                state = Some(syn::parse_str(&expr_to_string(&value)?)?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown container attribute"))
             }
+        })?;
+    }
+
+    Ok(state.unwrap_or_else(|| syn::parse_quote! { crate::state::AppState }))
+}
+
+/// A struct or variant field along with the validators attached to it.
+struct FieldInfo {
+    member: syn::Member,
+    binding: syn::Ident,
+    name: String,
+    span: proc_macro2::Span,
+    optional: bool,
+    validators: Vec<Validator>,
+}
+
+fn parse_fields(fields: &Fields) -> syn::Result<Vec<FieldInfo>> {
+    let mut infos = Vec::new();
 
-            let validators = parse_validate_attr(attr)?;
-            for validator in validators {
-                let tokens =
-                    generate_validation(&validator, field_ident, &field_name, field.span())?;
-                field_validations.push(tokens);
+    for (index, field) in fields.iter().enumerate() {
+        let (member, name) = match &field.ident {
+            Some(ident) => (syn::Member::Named(ident.clone()), ident.unraw().to_string()),
+            None => (
+                syn::Member::Unnamed(syn::Index::from(index)),
+                index.to_string(),
+            ),
+        };
+
+        let mut validators = Vec::new();
+        for attr in &field.attrs {
+            if attr.path().is_ident("validate") {
+                validators.extend(parse_validate_attr(attr)?);
             }
         }
+
+        infos.push(FieldInfo {
+            member,
+            binding: format_ident!("__{}", name),
+            name,
+            span: field.span(),
+            optional: is_option(&field.ty),
+            validators,
+        });
     }
 
-    Ok(quote! {
-        impl #impl_generics crate::validate::Validate for #name #ty_generics #where_clause {
-            fn validate(&self) -> ::std::result::Result<(), crate::validate::ValidationErrors> {
-                let mut errors = crate::validate::ValidationErrors::new();
-                #(#field_validations)*
-                errors.into_result()
+    Ok(infos)
+}
+
+/// Builds a pattern binding every field by reference, which works the same
+/// for named structs, tuple structs and all kinds of enum variants.
+fn destructure(path: TokenStream2, fields: &[FieldInfo]) -> TokenStream2 {
+    let bindings = fields.iter().map(|field| {
+        let member = &field.member;
+        let binding = &field.binding;
+        quote! { #member: ref #binding }
+    });
+
+    quote! { #path { #(#bindings,)* .. } }
+}
+
+fn generate_fields(fields: &[FieldInfo], mode: Mode) -> syn::Result<TokenStream2> {
+    let mut validations = Vec::new();
+
+    for field in fields {
+        let binding = &field.binding;
+        let name = &field.name;
+        let target = Target {
+            value: quote! { #binding },
+            key: quote! { #name },
+        };
+
+        for validator in &field.validators {
+            if let Some(tokens) = generate_validation(validator, &target, fields, field.span, mode)?
+            {
+                validations.push(tokens);
             }
         }
-    })
+    }
+
+    Ok(quote! { #(#validations)* })
 }
 
 // ---------------------------------------------------------------------------
 // Attribute parsing
 // ---------------------------------------------------------------------------
 
+/// Replacements for the error a validator reports: `code` changes which
+/// catalog entry describes it, and `message` replaces the text outright.
+#[derive(Debug, Default)]
+struct Overrides {
+    code: Option<String>,
+    message: Option<String>,
+}
+
+impl Overrides {
+    fn set(&mut self, key: &str, value: &Expr) -> syn::Result<()> {
+        let value = Some(expr_to_string(value)?);
+        match key {
+            "code" => self.code = value,
+            _ => self.message = value,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 enum Validator {
     Email {
-        message: Option<String>,
+        overrides: Overrides,
     },
     Url {
-        message: Option<String>,
+        overrides: Overrides,
     },
     Ip {
-        message: Option<String>,
+        overrides: Overrides,
     },
     NonControlCharacter {
-        message: Option<String>,
+        overrides: Overrides,
     },
     Required {
-        message: Option<String>,
+        overrides: Overrides,
+    },
+    /// One of the domain validators, such as `username` or `repo_name`,
+    /// which report their own error.
+    Domain {
+        name: String,
+        overrides: Overrides,
     },
     Length {
         min: Option<Expr>,
         max: Option<Expr>,
         equal: Option<Expr>,
-        message: Option<String>,
+        overrides: Overrides,
     },
     Range {
         min: Option<Expr>,
         max: Option<Expr>,
         exclusive_min: Option<Expr>,
         exclusive_max: Option<Expr>,
-        message: Option<String>,
+        overrides: Overrides,
     },
     Contains {
         pattern: String,
-        message: Option<String>,
+        overrides: Overrides,
     },
     DoesNotContain {
         pattern: String,
-        message: Option<String>,
+        overrides: Overrides,
     },
     MustMatch {
         other: String,
-        message: Option<String>,
+        overrides: Overrides,
     },
     Regex {
         path: Expr,
-        message: Option<String>,
+        overrides: Overrides,
     },
     Nested,
     Custom {
         function: syn::Path,
-        message: Option<String>,
+        overrides: Overrides,
     },
+    AsyncCustom {
+        function: syn::Path,
+        overrides: Overrides,
+    },
+    Each(Vec<Validator>),
 }
 
 fn parse_validate_attr(attr: &syn::Attribute) -> syn::Result<Vec<Validator>> {
     let mut validators = Vec::new();
 
-    attr.parse_nested_meta(|meta| {
-        let ident = meta
-            .path
-            .get_ident()
-            .ok_or_else(|| syn::Error::new_spanned(&meta.path, "expected validator name"))?;
-        let name = ident.to_string();
+    attr.parse_nested_meta(|meta| parse_validator(&meta, &mut validators))?;
 
-        match name.as_str() {
-            "email" => {
-                let message = parse_optional_message(&meta)?;
-                validators.push(Validator::Email { message });
-            }
-            "url" => {
-                let message = parse_optional_message(&meta)?;
-                validators.push(Validator::Url { message });
-            }
-            "ip" => {
-                let message = parse_optional_message(&meta)?;
-                validators.push(Validator::Ip { message });
-            }
-            "non_control_character" => {
-                let message = parse_optional_message(&meta)?;
-                validators.push(Validator::NonControlCharacter { message });
-            }
-            "required" => {
-                let message = parse_optional_message(&meta)?;
-                validators.push(Validator::Required { message });
-            }
-            "nested" => {
-                validators.push(Validator::Nested);
-            }
-            "length" => {
-                let mut min = None;
-                let mut max = None;
-                let mut equal = None;
-                let mut message = None;
+    Ok(validators)
+}
 
-                parse_kv_args(&meta, |key, value| {
-                    match key.as_str() {
-                        "min" => min = Some(value),
-                        "max" => max = Some(value),
-                        "equal" => equal = Some(value),
-                        "message" => {
-                            message = Some(expr_to_string(&value)?);
-                        }
-                        _ => {
-                            return Err(syn::Error::new_spanned(
-                                &value,
-                                format!("unknown length parameter: {}", key),
-                            ));
-                        }
-                    }
-                    Ok(())
-                })?;
+fn parse_validator(meta: &ParseNestedMeta, validators: &mut Vec<Validator>) -> syn::Result<()> {
+    let ident = meta
+        .path
+        .get_ident()
+        .ok_or_else(|| syn::Error::new_spanned(&meta.path, "expected validator name"))?;
+    let name = ident.to_string();
 
-                validators.push(Validator::Length {
-                    min,
-                    max,
-                    equal,
-                    message,
-                });
-            }
-            "range" => {
-                let mut min = None;
-                let mut max = None;
-                let mut exclusive_min = None;
-                let mut exclusive_max = None;
-                let mut message = None;
-
-                parse_kv_args(&meta, |key, value| {
-                    match key.as_str() {
-                        "min" => min = Some(value),
-                        "max" => max = Some(value),
-                        "exclusive_min" => exclusive_min = Some(value),
-                        "exclusive_max" => exclusive_max = Some(value),
-                        "message" => {
-                            message = Some(expr_to_string(&value)?);
-                        }
-                        _ => {
-                            return Err(syn::Error::new_spanned(
-                                &value,
-                                format!("unknown range parameter: {}", key),
-                            ));
-                        }
-                    }
-                    Ok(())
-                })?;
+    match name.as_str() {
+        "email" => {
+            let overrides = parse_overrides(meta)?;
+            validators.push(Validator::Email { overrides });
+        }
+        "url" => {
+            let overrides = parse_overrides(meta)?;
+            validators.push(Validator::Url { overrides });
+        }
+        "ip" => {
+            let overrides = parse_overrides(meta)?;
+            validators.push(Validator::Ip { overrides });
+        }
+        "non_control_character" => {
+            let overrides = parse_overrides(meta)?;
+            validators.push(Validator::NonControlCharacter { overrides });
+        }
+        "required" => {
+            let overrides = parse_overrides(meta)?;
+            validators.push(Validator::Required { overrides });
+        }
+        "username" | "repo_name" | "git_ref_name" | "filename" | "ssh_public_key" => {
+            let overrides = parse_overrides(meta)?;
+            validators.push(Validator::Domain { name, overrides });
+        }
+        "nested" => {
+            validators.push(Validator::Nested);
+        }
+        "each" => {
+            let mut inner = Vec::new();
+            meta.parse_nested_meta(|meta| parse_validator(&meta, &mut inner))?;
 
-                validators.push(Validator::Range {
-                    min,
-                    max,
-                    exclusive_min,
-                    exclusive_max,
-                    message,
-                });
+            if inner
+                .iter()
+                .any(|validator| matches!(validator, Validator::MustMatch { .. }))
+            {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "must_match cannot be used inside each",
+                ));
             }
-            "contains" => {
-                if meta.input.peek(Token![=]) {
-                    let value: MetaNameValue = syn::parse2(quote! { #ident = }.into()).unwrap();
-                    // parse `= "..."`
-                    meta.input.parse::<Token![=]>()?;
-                    let lit: Lit = meta.input.parse()?;
-                    let pattern = lit_to_string(&lit)?;
-                    validators.push(Validator::Contains {
-                        pattern,
-                        message: None,
-                    });
-                    _ = value;
-                } else {
-                    let mut pattern = String::new();
-                    let mut message = None;
-
-                    parse_kv_args(&meta, |key, value| {
-                        match key.as_str() {
-                            "pattern" => pattern = expr_to_string(&value)?,
-                            "message" => message = Some(expr_to_string(&value)?),
-                            _ => {
-                                return Err(syn::Error::new_spanned(
-                                    &value,
-                                    format!("unknown contains parameter: {}", key),
-                                ));
-                            }
-                        }
-                        Ok(())
-                    })?;
 
-                    validators.push(Validator::Contains { pattern, message });
-                }
-            }
-            "does_not_contain" => {
-                if meta.input.peek(Token![=]) {
-                    meta.input.parse::<Token![=]>()?;
-                    let lit: Lit = meta.input.parse()?;
-                    let pattern = lit_to_string(&lit)?;
-                    validators.push(Validator::DoesNotContain {
-                        pattern,
-                        message: None,
-                    });
-                } else {
-                    let mut pattern = String::new();
-                    let mut message = None;
-
-                    parse_kv_args(&meta, |key, value| {
-                        match key.as_str() {
-                            "pattern" => pattern = expr_to_string(&value)?,
-                            "message" => message = Some(expr_to_string(&value)?),
-                            _ => {
-                                return Err(syn::Error::new_spanned(
-                                    &value,
-                                    format!("unknown does_not_contain parameter: {}", key),
-                                ));
-                            }
-                        }
-                        Ok(())
-                    })?;
+            validators.push(Validator::Each(inner));
+        }
+        "length" => {
+            let mut min = None;
+            let mut max = None;
+            let mut equal = None;
+            let mut overrides = Overrides::default();
 
-                    validators.push(Validator::DoesNotContain { pattern, message });
+            parse_kv_args(meta, |key, value| {
+                match key.as_str() {
+                    "min" => min = Some(value),
+                    "max" => max = Some(value),
+                    "equal" => equal = Some(value),
+                    "code" | "message" => overrides.set(&key, &value)?,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            &value,
+                            format!("unknown length parameter: {}", key),
+                        ));
+                    }
                 }
-            }
-            "must_match" => {
-                let mut other = String::new();
-                let mut message = None;
+                Ok(())
+            })?;
 
-                parse_kv_args(&meta, |key, value| {
-                    match key.as_str() {
-                        "other" => other = expr_to_string(&value)?,
-                        "message" => message = Some(expr_to_string(&value)?),
-                        _ => {
-                            return Err(syn::Error::new_spanned(
-                                &value,
-                                format!("unknown must_match parameter: {}", key),
-                            ));
-                        }
-                    }
-                    Ok(())
-                })?;
+            validators.push(Validator::Length {
+                min,
+                max,
+                equal,
+                overrides,
+            });
+        }
+        "range" => {
+            let mut min = None;
+            let mut max = None;
+            let mut exclusive_min = None;
+            let mut exclusive_max = None;
+            let mut overrides = Overrides::default();
 
-                if other.is_empty() {
-                    return Err(syn::Error::new(
-                        ident.span(),
-                        "must_match requires `other` parameter",
-                    ));
+            parse_kv_args(meta, |key, value| {
+                match key.as_str() {
+                    "min" => min = Some(value),
+                    "max" => max = Some(value),
+                    "exclusive_min" => exclusive_min = Some(value),
+                    "exclusive_max" => exclusive_max = Some(value),
+                    "code" | "message" => overrides.set(&key, &value)?,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            &value,
+                            format!("unknown range parameter: {}", key),
+                        ));
+                    }
                 }
+                Ok(())
+            })?;
 
-                validators.push(Validator::MustMatch { other, message });
-            }
-            "regex" => {
-                let mut path = None;
-                let mut message = None;
+            validators.push(Validator::Range {
+                min,
+                max,
+                exclusive_min,
+                exclusive_max,
+                overrides,
+            });
+        }
+        "contains" => {
+            if meta.input.peek(Token![=]) {
+                let value: MetaNameValue = syn::parse2(quote! { #ident = }.into()).unwrap();
+                // parse `= "..."`
+                meta.input.parse::<Token![=]>()?;
+                let lit: Lit = meta.input.parse()?;
+                let pattern = lit_to_string(&lit)?;
+                validators.push(Validator::Contains {
+                    pattern,
+                    overrides: Overrides::default(),
+                });
+                _ = value;
+            } else {
+                let mut pattern = String::new();
+                let mut overrides = Overrides::default();
 
-                parse_kv_args(&meta, |key, value| {
+                parse_kv_args(meta, |key, value| {
                     match key.as_str() {
-                        "path" => path = Some(value),
-                        "message" => message = Some(expr_to_string(&value)?),
+                        "pattern" => pattern = expr_to_string(&value)?,
+                        "code" | "message" => overrides.set(&key, &value)?,
                         _ => {
                             return Err(syn::Error::new_spanned(
                                 &value,
-                                format!("unknown regex parameter: {}", key),
+                                format!("unknown contains parameter: {}", key),
                             ));
                         }
                     }
                     Ok(())
                 })?;
 
-                let path = path.ok_or_else(|| {
-                    syn::Error::new(ident.span(), "regex requires `path` parameter")
-                })?;
-
-                validators.push(Validator::Regex { path, message });
+                validators.push(Validator::Contains { pattern, overrides });
             }
-            "custom" => {
-                let mut function: Option<syn::Path> = None;
-                let mut message = None;
+        }
+        "does_not_contain" => {
+            if meta.input.peek(Token![=]) {
+                meta.input.parse::<Token![=]>()?;
+                let lit: Lit = meta.input.parse()?;
+                let pattern = lit_to_string(&lit)?;
+                validators.push(Validator::DoesNotContain {
+                    pattern,
+                    overrides: Overrides::default(),
+                });
+            } else {
+                let mut pattern = String::new();
+                let mut overrides = Overrides::default();
 
-                parse_kv_args(&meta, |key, value| {
+                parse_kv_args(meta, |key, value| {
                     match key.as_str() {
-                        "function" => {
-                            let s = expr_to_string(&value)?;
-                            function = Some(syn::parse_str(&s)?);
-                        }
-                        "message" => message = Some(expr_to_string(&value)?),
+                        "pattern" => pattern = expr_to_string(&value)?,
+                        "code" | "message" => overrides.set(&key, &value)?,
                         _ => {
                             return Err(syn::Error::new_spanned(
                                 &value,
-                                format!("unknown custom parameter: {}", key),
+                                format!("unknown does_not_contain parameter: {}", key),
                             ));
                         }
                     }
                     Ok(())
                 })?;
 
-                let function = function.ok_or_else(|| {
-                    syn::Error::new(ident.span(), "custom requires `function` parameter")
-                })?;
-
-                validators.push(Validator::Custom { function, message });
+                validators.push(Validator::DoesNotContain { pattern, overrides });
             }
-            other => {
+        }
+        "must_match" => {
+            let mut other = String::new();
+            let mut overrides = Overrides::default();
+
+            parse_kv_args(meta, |key, value| {
+                match key.as_str() {
+                    "other" => other = expr_to_string(&value)?,
+                    "code" | "message" => overrides.set(&key, &value)?,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            &value,
+                            format!("unknown must_match parameter: {}", key),
+                        ));
+                    }
+                }
+                Ok(())
+            })?;
+
+            if other.is_empty() {
                 return Err(syn::Error::new(
                     ident.span(),
-                    format!("unknown validator: {}", other),
+                    "must_match requires `other` parameter",
                 ));
             }
+
+            validators.push(Validator::MustMatch { other, overrides });
         }
+        "regex" => {
+            let mut path = None;
+            let mut overrides = Overrides::default();
 
-        Ok(())
-    })?;
+            parse_kv_args(meta, |key, value| {
+                match key.as_str() {
+                    "path" => path = Some(value),
+                    "code" | "message" => overrides.set(&key, &value)?,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            &value,
+                            format!("unknown regex parameter: {}", key),
+                        ));
+                    }
+                }
+                Ok(())
+            })?;
 
-    Ok(validators)
-}
+            let path = path
+                .ok_or_else(|| syn::Error::new(ident.span(), "regex requires `path` parameter"))?;
 
-fn parse_optional_message(meta: &syn::meta::ParseNestedMeta) -> syn::Result<Option<String>> {
-    if meta.input.peek(syn::token::Paren) {
-        let mut message = None;
-        parse_kv_args(meta, |key, value| {
-            if key == "message" {
-                message = Some(expr_to_string(&value)?);
+            validators.push(Validator::Regex { path, overrides });
+        }
+        "custom" | "async_custom" => {
+            let mut function: Option<syn::Path> = None;
+            let mut overrides = Overrides::default();
+
+            parse_kv_args(meta, |key, value| {
+                match key.as_str() {
+                    "function" => {
+                        let s = expr_to_string(&value)?;
+                        function = Some(syn::parse_str(&s)?);
+                    }
+                    "code" | "message" => overrides.set(&key, &value)?,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            &value,
+                            format!("unknown {} parameter: {}", name, key),
+                        ));
+                    }
+                }
+                Ok(())
+            })?;
+
+            let function = function.ok_or_else(|| {
+                syn::Error::new(
+                    ident.span(),
+                    format!("{} requires `function` parameter", name),
+                )
+            })?;
+
+            if name == "custom" {
+                validators.push(Validator::Custom {
+                    function,
+                    overrides,
+                });
             } else {
-                return Err(syn::Error::new_spanned(
-                    &value,
-                    format!("unexpected parameter: {}", key),
-                ));
+                validators.push(Validator::AsyncCustom {
+                    function,
+                    overrides,
+                });
             }
-            Ok(())
+        }
+        other => {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!("unknown validator: {}", other),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_overrides(meta: &ParseNestedMeta) -> syn::Result<Overrides> {
+    let mut overrides = Overrides::default();
+    if meta.input.peek(syn::token::Paren) {
+        parse_kv_args(meta, |key, value| match key.as_str() {
+            "code" | "message" => overrides.set(&key, &value),
+            _ => Err(syn::Error::new_spanned(
+                &value,
+                format!("unexpected parameter: {}", key),
+            )),
         })?;
-        Ok(message)
-    } else {
-        Ok(None)
     }
+    Ok(overrides)
 }
 
 fn parse_kv_args(
-    meta: &syn::meta::ParseNestedMeta,
+    meta: &ParseNestedMeta,
     mut handler: impl FnMut(String, Expr) -> syn::Result<()>,
 ) -> syn::Result<()> {
     let content;
@@ -441,74 +630,146 @@ fn lit_to_string(lit: &Lit) -> syn::Result<String> {
 // Code generation
 // ---------------------------------------------------------------------------
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Sync,
+    Async,
+}
+
+/// The value being validated and the key its errors are reported under.
+/// `value` is an expression evaluating to a reference.
+struct Target {
+    value: TokenStream2,
+    key: TokenStream2,
+}
+
 fn generate_validation(
     validator: &Validator,
-    field_ident: &syn::Ident,
-    field_name: &str,
+    target: &Target,
+    fields: &[FieldInfo],
+    span: proc_macro2::Span,
+    mode: Mode,
+) -> syn::Result<Option<TokenStream2>> {
+    let Target { value, key } = target;
+    match validator {
+        Validator::Nested => Ok(Some(match mode {
+            Mode::Sync => quote_spanned! { span =>
+                errors.merge_self(#key, crate::validate::Validate::validate(#value));
+            },
+            Mode::Async => quote_spanned! { span =>
+                errors.merge_self(
+                    #key,
+                    crate::validate::ValidateAsync::validate_async(#value, state).await,
+                );
+            },
+        })),
+        Validator::Each(inner) => {
+            let item = Target {
+                value: quote! { __item },
+                key: quote! { __key.clone() },
+            };
+
+            let mut checks = Vec::new();
+            for validator in inner {
+                if let Some(tokens) = generate_validation(validator, &item, fields, span, mode)? {
+                    checks.push(tokens);
+                }
+            }
+
+            if checks.is_empty() {
+                return Ok(None);
+            }
+
+            Ok(Some(quote_spanned! { span =>
+                for (__index, __item) in #value.iter().enumerate() {
+                    let __key = ::std::format!("{}.{}", #key, __index);
+                    #(#checks)*
+                }
+            }))
+        }
+        Validator::AsyncCustom {
+            function,
+            overrides,
+        } => {
+            if mode == Mode::Sync {
+                return Ok(None);
+            }
+
+            let recode = recode(overrides);
+            Ok(Some(quote_spanned! { span =>
+                if let Err(err) = #function(#value, state).await {
+                    #recode
+                    errors.add(#key, err);
+                }
+            }))
+        }
+        _ => match mode {
+            Mode::Sync => generate_check(validator, target, fields, span).map(Some),
+            Mode::Async => Ok(None),
+        },
+    }
+}
+
+fn generate_check(
+    validator: &Validator,
+    target: &Target,
+    fields: &[FieldInfo],
     span: proc_macro2::Span,
 ) -> syn::Result<TokenStream2> {
+    let Target { value, key } = target;
     match validator {
-        Validator::Email { message } => {
-            let msg = error_message(message, "email", "invalid email address");
+        Validator::Email { overrides } => {
+            let error = new_error("email", overrides, Vec::new());
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateEmail::validate_email(&self.#field_ident) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("email")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateEmail::validate_email(#value) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::Url { message } => {
-            let msg = error_message(message, "url", "invalid URL");
+        Validator::Url { overrides } => {
+            let error = new_error("url", overrides, Vec::new());
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateUrl::validate_url(&self.#field_ident) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("url")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateUrl::validate_url(#value) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::Ip { message } => {
-            let msg = error_message(message, "ip", "invalid IP address");
+        Validator::Ip { overrides } => {
+            let error = new_error("ip", overrides, Vec::new());
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateIp::validate_ip(&self.#field_ident) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("ip")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateIp::validate_ip(#value) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::NonControlCharacter { message } => {
-            let msg = error_message(
-                message,
-                "non_control_character",
-                "contains control characters",
-            );
+        Validator::NonControlCharacter { overrides } => {
+            let error = new_error("non_control_character", overrides, Vec::new());
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateNonControlCharacter::validate_non_control_character(&self.#field_ident) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("non_control_character")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateNonControlCharacter::validate_non_control_character(#value) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::Required { message } => {
-            let msg = error_message(message, "required", "field is required");
+        Validator::Required { overrides } => {
+            let error = new_error("required", overrides, Vec::new());
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateRequired::validate_required(&self.#field_ident) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("required")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateRequired::validate_required(#value) {
+                    errors.add(#key, #error);
+                }
+            })
+        }
+        Validator::Domain { name, overrides } => {
+            let camel: String = name
+                .split('_')
+                .map(|word| word[..1].to_uppercase() + &word[1..])
+                .collect();
+            let trait_ident = format_ident!("Validate{}", camel);
+            let method = format_ident!("validate_{}", name);
+            let recode = recode(overrides);
+            Ok(quote_spanned! { span =>
+                if let Err(err) = crate::validate::#trait_ident::#method(#value) {
+                    #recode
+                    errors.add(#key, err);
                 }
             })
         }
@@ -516,24 +777,24 @@ fn generate_validation(
             min,
             max,
             equal,
-            message,
+            overrides,
         } => {
-            let msg = error_message(message, "length", "invalid length");
+            let params = [("min", min), ("max", max), ("equal", equal)]
+                .into_iter()
+                .filter_map(|(name, expr)| expr.as_ref().map(|e| (name, quote! { #e })))
+                .collect();
+            let error = new_error("length", overrides, params);
             let min_expr = option_expr(min);
             let max_expr = option_expr(max);
             let equal_expr = option_expr(equal);
             Ok(quote_spanned! { span =>
                 if !crate::validate::ValidateLength::validate_length(
-                    &self.#field_ident,
+                    #value,
                     #min_expr,
                     #max_expr,
                     #equal_expr,
                 ) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("length")
-                            .with_message(#msg),
-                    );
+                    errors.add(#key, #error);
                 }
             })
         }
@@ -542,106 +803,255 @@ fn generate_validation(
             max,
             exclusive_min,
             exclusive_max,
-            message,
+            overrides,
         } => {
-            let msg = error_message(message, "range", "value out of range");
+            let params = [
+                ("min", min),
+                ("max", max),
+                ("exclusive_min", exclusive_min),
+                ("exclusive_max", exclusive_max),
+            ]
+            .into_iter()
+            .filter_map(|(name, expr)| expr.as_ref().map(|e| (name, quote! { #e })))
+            .collect();
+            let error = new_error("range", overrides, params);
             let min_expr = option_f64_expr(min);
             let max_expr = option_f64_expr(max);
             let emin_expr = option_f64_expr(exclusive_min);
             let emax_expr = option_f64_expr(exclusive_max);
             Ok(quote_spanned! { span =>
                 if !crate::validate::ValidateRange::validate_range(
-                    &self.#field_ident,
+                    #value,
                     #min_expr,
                     #max_expr,
                     #emin_expr,
                     #emax_expr,
                 ) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("range")
-                            .with_message(#msg),
-                    );
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::Contains { pattern, message } => {
-            let msg = error_message(message, "contains", &format!("must contain '{}'", pattern));
+        Validator::Contains { pattern, overrides } => {
+            let error = new_error(
+                "contains",
+                overrides,
+                vec![("pattern", quote! { #pattern })],
+            );
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateContains::validate_contains(&self.#field_ident, #pattern) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("contains")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateContains::validate_contains(#value, #pattern) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::DoesNotContain { pattern, message } => {
-            let msg = error_message(
-                message,
+        Validator::DoesNotContain { pattern, overrides } => {
+            let error = new_error(
                 "does_not_contain",
-                &format!("must not contain '{}'", pattern),
+                overrides,
+                vec![("pattern", quote! { #pattern })],
             );
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateDoesNotContain::validate_does_not_contain(&self.#field_ident, #pattern) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("does_not_contain")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateDoesNotContain::validate_does_not_contain(#value, #pattern) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::MustMatch { other, message } => {
-            let other_ident = syn::Ident::new(other, span);
-            let msg = error_message(
-                message,
-                "must_match",
-                &format!("must match field '{}'", other),
-            );
+        Validator::MustMatch { other, overrides } => {
+            let other_binding = fields
+                .iter()
+                .find(|field| field.name == *other)
+                .map(|field| &field.binding)
+                .ok_or_else(|| syn::Error::new(span, format!("unknown field: {}", other)))?;
+            let error = new_error("must_match", overrides, vec![("other", quote! { #other })]);
             Ok(quote_spanned! { span =>
-                if !crate::validate::validate_must_match(&self.#field_ident, &self.#other_ident) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("must_match")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::validate_must_match(#value, #other_binding) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::Regex { path, message } => {
-            let msg = error_message(message, "regex", "does not match the required pattern");
+        Validator::Regex { path, overrides } => {
+            let error = new_error("regex", overrides, Vec::new());
             Ok(quote_spanned! { span =>
-                if !crate::validate::ValidateRegex::validate_regex(&self.#field_ident, &#path) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("regex")
-                            .with_message(#msg),
-                    );
+                if !crate::validate::ValidateRegex::validate_regex(#value, &#path) {
+                    errors.add(#key, #error);
                 }
             })
         }
-        Validator::Nested => Ok(quote_spanned! { span =>
-            errors.merge_self(#field_name, crate::validate::Validate::validate(&self.#field_ident));
-        }),
-        Validator::Custom { function, message } => {
-            let msg = error_message(message, "custom", "custom validation failed");
+        Validator::Custom {
+            function,
+            overrides,
+        } => {
+            let recode = recode(overrides);
             Ok(quote_spanned! { span =>
-                if let Err(_) = #function(&self.#field_ident) {
-                    errors.add(
-                        #field_name,
-                        crate::validate::ValidationError::new("custom")
-                            .with_message(#msg),
-                    );
+                if let Err(err) = #function(#value) {
+                    #recode
+                    errors.add(#key, err);
                 }
             })
         }
+        Validator::Nested | Validator::AsyncCustom { .. } | Validator::Each(_) => {
+            unreachable!("handled by generate_validation")
+        }
+    }
+}
+
+/// Builds a `ValidationError` carrying the validator's parameters. Its text
+/// is looked up in the message catalog by code, which an attribute may
+/// replace with `code = "..."`, unless it gives a `message = "..."`.
+fn new_error(
+    default: &str,
+    overrides: &Overrides,
+    params: Vec<(&str, TokenStream2)>,
+) -> TokenStream2 {
+    let code = overrides.code.as_deref().unwrap_or(default);
+    let params = params
+        .into_iter()
+        .map(|(name, value)| quote! { .add_param(#name, #value) });
+    let message = overrides
+        .message
+        .as_ref()
+        .map(|message| quote! { .with_message(#message) });
+    quote! { crate::validate::ValidationError::new(#code) #(#params)* #message }
+}
+
+/// Applies the overrides to an error reported by a function or domain
+/// validator.
+fn recode(overrides: &Overrides) -> TokenStream2 {
+    let code = overrides
+        .code
+        .as_ref()
+        .map(|code| quote! { let err = crate::validate::ValidationError { code: #code, ..err }; });
+    let message = overrides
+        .message
+        .as_ref()
+        .map(|message| quote! { let err = err.with_message(#message); });
+    quote! { #code #message }
+}
+
+fn is_option(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HTML constraints
+// ---------------------------------------------------------------------------
+
+/// Describes the client-side equivalent of a field's validators. Validators
+/// without an HTML counterpart, such as `custom` or `must_match`, are left
+/// to the server.
+fn generate_constraints(field: &FieldInfo) -> TokenStream2 {
+    let mut required = Vec::new();
+    let mut min_length = None;
+    let mut max_length = None;
+    let mut min = None;
+    let mut max = None;
+    let mut pattern = None;
+    let mut input_type = None;
+
+    for validator in &field.validators {
+        match validator {
+            Validator::Required { .. } => required.push(quote! { true }),
+            Validator::Email { .. } | Validator::Url { .. } => {
+                input_type = Some(if matches!(validator, Validator::Email { .. }) {
+                    "email"
+                } else {
+                    "url"
+                });
+
+                // An empty string is neither a valid address nor URL.
+                if !field.optional {
+                    required.push(quote! { true });
+                }
+            }
+            Validator::Length {
+                min: length_min,
+                max: length_max,
+                equal,
+                ..
+            } => {
+                if let Some(e) = length_min.as_ref().or(equal.as_ref()) {
+                    if !field.optional {
+                        required.push(quote! { (#e as usize) > 0 });
+                    }
+                    min_length = Some(quote! { #e as usize });
+                }
+                if let Some(e) = length_max.as_ref().or(equal.as_ref()) {
+                    max_length = Some(quote! { #e as usize });
+                }
+            }
+            Validator::Range {
+                min: range_min,
+                max: range_max,
+                ..
+            } => {
+                min = range_min.as_ref().or(min);
+                max = range_max.as_ref().or(max);
+            }
+            Validator::Regex { path, .. } => pattern = Some(quote! { (#path).as_str() }),
+            Validator::Domain { name, .. } => {
+                let (domain_pattern, domain_max) = match name.as_str() {
+                    "username" => (
+                        Some(quote! { crate::validate::USERNAME_PATTERN }),
+                        quote! { crate::validate::USERNAME_MAX_LENGTH },
+                    ),
+                    "repo_name" => (
+                        Some(quote! { crate::validate::REPO_NAME_PATTERN }),
+                        quote! { crate::validate::REPO_NAME_MAX_LENGTH },
+                    ),
+                    "filename" => (None, quote! { crate::validate::FILENAME_MAX_LENGTH }),
+                    _ => continue,
+                };
+
+                if !field.optional {
+                    required.push(quote! { true });
+                }
+                min_length = Some(quote! { 1 });
+                max_length = Some(domain_max);
+                pattern = domain_pattern.or(pattern);
+            }
+            _ => {}
+        }
+    }
+
+    let required = if required.is_empty() {
+        quote! { false }
+    } else {
+        quote! { #(#required)||* }
+    };
+    let min_length = option_tokens(min_length);
+    let max_length = option_tokens(max_length);
+    let min = option_f64_expr(&min.cloned());
+    let max = option_f64_expr(&max.cloned());
+    let pattern = option_tokens(pattern);
+    let input_type = match input_type {
+        Some(input_type) => quote! { Some(#input_type) },
+        None => quote! { None },
+    };
+
+    quote! {
+        crate::validate::FieldConstraints {
+            required: #required,
+            min_length: #min_length,
+            max_length: #max_length,
+            min: #min,
+            max: #max,
+            pattern: #pattern,
+            input_type: #input_type,
+        }
     }
 }
 
-fn error_message(custom: &Option<String>, _code: &str, default: &str) -> String {
-    custom.clone().unwrap_or_else(|| default.to_string())
+fn option_tokens(tokens: Option<TokenStream2>) -> TokenStream2 {
+    match tokens {
+        Some(tokens) => quote! { Some(#tokens) },
+        None => quote! { None },
+    }
 }
 
 fn option_expr(expr: &Option<Expr>) -> TokenStream2 {
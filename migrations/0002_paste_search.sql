@@ -0,0 +1,10 @@
+ALTER TABLE pastes ADD COLUMN created_at timestamptz not null default now();
+
+CREATE INDEX pastes_created_at_idx ON pastes (created_at desc);
+
+ALTER TABLE paste_files ADD COLUMN search tsvector not null generated always as (
+    setweight(to_tsvector('simple', filename), 'A') ||
+    setweight(to_tsvector('english', content), 'B')
+) stored;
+
+CREATE INDEX paste_files_search_idx ON paste_files USING gin (search);
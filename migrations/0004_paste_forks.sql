@@ -0,0 +1,3 @@
+ALTER TABLE pastes ADD COLUMN forked_from text references pastes(id) on delete set null;
+
+CREATE INDEX pastes_forked_from_idx ON pastes (forked_from);
@@ -0,0 +1,25 @@
+ALTER TABLE users ADD COLUMN email_verified_at timestamptz;
+
+CREATE TABLE mail_queue (
+    id bigint primary key generated always as identity,
+    recipient text not null,
+    subject text not null,
+    body text not null,
+    created_at timestamptz not null default now(),
+    attempts integer not null default 0,
+    next_attempt timestamptz not null default now(),
+    last_error text
+);
+
+CREATE INDEX mail_queue_next_attempt_idx ON mail_queue (next_attempt);
+
+-- Single-use links sent by email. Only a hash of the token is stored.
+CREATE TABLE email_tokens (
+    token_hash text primary key,
+    user_id integer not null references users(id) on delete cascade,
+    purpose text not null check (purpose in ('verify', 'reset')),
+    email text not null,
+    expires timestamptz not null
+);
+
+CREATE INDEX email_tokens_user_id_idx ON email_tokens (user_id);
@@ -0,0 +1,23 @@
+-- A TOTP secret is pending until the user confirms it with a valid code.
+CREATE TABLE user_totp (
+    user_id integer primary key references users(id) on delete cascade,
+    secret text not null,
+    confirmed_at timestamptz,
+    last_used_step bigint
+);
+
+CREATE TABLE recovery_codes (
+    user_id integer not null references users(id) on delete cascade,
+    code_hash text not null,
+    used_at timestamptz,
+    primary key (user_id, code_hash)
+);
+
+-- Issued after a correct password for accounts with two-factor enabled, and
+-- exchanged for a session once the second factor checks out.
+CREATE TABLE login_challenges (
+    token_hash text primary key,
+    user_id integer not null references users(id) on delete cascade,
+    expires timestamptz not null,
+    attempts integer not null default 0
+);
@@ -0,0 +1,8 @@
+ALTER TABLE sessions
+    ADD COLUMN id bigint generated always as identity unique,
+    ADD COLUMN created_at timestamptz not null default now(),
+    ADD COLUMN last_seen timestamptz not null default now(),
+    ADD COLUMN ip text,
+    ADD COLUMN user_agent text;
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,24 @@
+-- Accounts at external OpenID Connect providers, keyed by the issuer's
+-- stable subject identifier. A user has at most one per provider.
+CREATE TABLE user_identities (
+    provider text not null,
+    subject text not null,
+    user_id integer not null references users(id) on delete cascade,
+    email text,
+    created_at timestamptz not null default now(),
+    last_login timestamptz,
+    primary key (provider, subject),
+    unique (user_id, provider)
+);
+
+-- An authorization request in flight, from the redirect to the provider
+-- until its callback. Linking requests carry the user being linked.
+CREATE TABLE oidc_states (
+    state text primary key,
+    provider text not null,
+    nonce text not null,
+    pkce_verifier text not null,
+    link_user_id integer references users(id) on delete cascade,
+    redirect text,
+    expires timestamptz not null
+);
@@ -0,0 +1,3 @@
+-- Keys synced from an LDAP directory. They follow the directory and cannot
+-- be removed by their owner.
+ALTER TABLE user_keys ADD COLUMN managed boolean not null default false;
@@ -0,0 +1,47 @@
+-- Third-party applications allowed to request access to accounts. Public
+-- clients, such as command line tools, have no secret and rely on PKCE.
+CREATE TABLE oauth_apps (
+    id serial primary key,
+    client_id text not null unique,
+    client_secret_hash text,
+    name text not null,
+    redirect_uris text[] not null,
+    created_by integer references users(id) on delete set null,
+    created_at timestamptz not null default now()
+);
+
+-- The scopes a user has agreed to give an application.
+CREATE TABLE oauth_authorizations (
+    user_id integer not null references users(id) on delete cascade,
+    app_id integer not null references oauth_apps(id) on delete cascade,
+    scopes text[] not null,
+    created_at timestamptz not null default now(),
+    primary key (user_id, app_id)
+);
+
+CREATE TABLE oauth_codes (
+    code_hash text primary key,
+    app_id integer not null references oauth_apps(id) on delete cascade,
+    user_id integer not null references users(id) on delete cascade,
+    redirect_uri text not null,
+    scopes text[] not null,
+    code_challenge text not null,
+    expires timestamptz not null
+);
+
+-- An access token and the refresh token that replaces it. Refreshing
+-- rotates both, so a row lives until its refresh token expires.
+CREATE TABLE oauth_tokens (
+    id bigserial primary key,
+    access_token_hash text not null unique,
+    refresh_token_hash text not null unique,
+    app_id integer not null references oauth_apps(id) on delete cascade,
+    user_id integer not null references users(id) on delete cascade,
+    scopes text[] not null,
+    created_at timestamptz not null default now(),
+    expires timestamptz not null,
+    refresh_expires timestamptz not null,
+    last_used timestamptz
+);
+
+CREATE INDEX oauth_tokens_user_app_idx ON oauth_tokens (user_id, app_id);
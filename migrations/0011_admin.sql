@@ -0,0 +1,8 @@
+ALTER TABLE users ADD COLUMN is_admin boolean not null default false;
+
+-- Suspended users keep their data but cannot log in or use their keys.
+ALTER TABLE users ADD COLUMN suspended_at timestamptz;
+
+-- New installations make their first user an administrator on
+-- registration. Existing ones get their oldest user promoted.
+UPDATE users SET is_admin = true WHERE id = (SELECT min(id) FROM users);
@@ -0,0 +1,14 @@
+-- Single-use codes for registering while registration is invite-only. Only a
+-- hash of the code is stored.
+CREATE TABLE invites (
+    id serial primary key,
+    code_hash text not null unique,
+    note text not null default '',
+    created_by integer not null references users(id) on delete cascade,
+    created_at timestamptz not null default now(),
+    expires timestamptz not null,
+    used_by integer references users(id) on delete set null,
+    used_at timestamptz
+);
+
+CREATE INDEX invites_created_by_idx ON invites (created_by);
@@ -0,0 +1,30 @@
+-- Deleting a user takes everything they own with them.
+ALTER TABLE user_keys
+    DROP CONSTRAINT user_keys_user_id_fkey,
+    ADD CONSTRAINT user_keys_user_id_fkey foreign key (user_id) references users(id) on delete cascade;
+
+ALTER TABLE sessions
+    DROP CONSTRAINT sessions_user_id_fkey,
+    ADD CONSTRAINT sessions_user_id_fkey foreign key (user_id) references users(id) on delete cascade;
+
+ALTER TABLE pastes
+    DROP CONSTRAINT pastes_user_id_fkey,
+    ADD CONSTRAINT pastes_user_id_fkey foreign key (user_id) references users(id) on delete cascade;
+
+ALTER TABLE lfs_tokens
+    DROP CONSTRAINT lfs_tokens_user_id_fkey,
+    ADD CONSTRAINT lfs_tokens_user_id_fkey foreign key (user_id) references users(id) on delete cascade;
+
+-- Archives of a user's data, built in the background and kept on disk
+-- until they expire.
+CREATE TABLE exports (
+    id bigint primary key generated always as identity,
+    user_id integer not null references users(id) on delete cascade,
+    status text not null default 'pending' check (status in ('pending', 'running', 'ready', 'failed')),
+    created_at timestamptz not null default now(),
+    started_at timestamptz,
+    completed_at timestamptz,
+    size bigint
+);
+
+CREATE INDEX exports_user_id_idx ON exports (user_id);
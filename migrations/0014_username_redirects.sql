@@ -0,0 +1,21 @@
+-- Former usernames, so that web URLs and SSH remotes using them keep
+-- working after a rename.
+CREATE TABLE username_redirects (
+    username text primary key,
+    user_id integer not null references users(id) on delete cascade,
+    created_at timestamptz not null default now()
+);
+
+CREATE INDEX username_redirects_user_id_idx ON username_redirects (user_id);
+
+-- A redirect lasts until its name is taken again, by anyone.
+CREATE FUNCTION reclaim_username() RETURNS trigger AS $$
+BEGIN
+    DELETE FROM username_redirects WHERE username = NEW.username;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_reclaim_username
+    AFTER INSERT OR UPDATE OF username ON users
+    FOR EACH ROW EXECUTE FUNCTION reclaim_username();
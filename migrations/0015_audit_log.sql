@@ -0,0 +1,26 @@
+-- Security-relevant events. Entries outlive the accounts they mention, so
+-- usernames are copied in rather than referenced.
+CREATE TABLE audit_log (
+    id bigint primary key generated always as identity,
+    created_at timestamptz not null default now(),
+    action text not null,
+    actor_id integer,
+    actor text,
+    user_id integer,
+    username text,
+    ip text,
+    detail text not null default ''
+);
+
+CREATE INDEX audit_log_actor_id_idx ON audit_log (actor_id);
+CREATE INDEX audit_log_user_id_idx ON audit_log (user_id);
+
+CREATE FUNCTION reject_audit_log_change() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_log_change();
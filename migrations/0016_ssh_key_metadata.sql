@@ -0,0 +1,13 @@
+-- Fingerprints as `ssh-keygen -l` prints them.
+ALTER TABLE user_keys ADD COLUMN fingerprint text not null generated always as (
+    'SHA256:' || rtrim(encode(sha256(decode(encoded, 'base64')), 'base64'), '=')
+) stored;
+
+-- Expired keys are refused at login. Owners are warned by email shortly
+-- before, once per key.
+ALTER TABLE user_keys
+    ADD COLUMN last_used_at timestamptz,
+    ADD COLUMN expires_at timestamptz,
+    ADD COLUMN expiry_notified_at timestamptz;
+
+CREATE INDEX user_keys_expires_at_idx ON user_keys (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,387 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::{fs, task};
+use tracing::error;
+
+use crate::config::Config;
+use crate::model::user::UserId;
+use crate::state::AppState;
+use crate::{mail, model};
+
+/// Exports still running after this long were interrupted and are given up
+/// on.
+const EXPORT_TIMEOUT: time::Duration = time::Duration::hours(1);
+
+/// Delete a user along with their repositories and LFS objects.
+pub async fn delete(state: &AppState, user_id: UserId, username: &str) -> Result<()> {
+    model::user::delete(&state.db, user_id).await?;
+
+    let git = &state.config.git;
+    remove_dir(&git.repository_path.join(username)).await?;
+    remove_dir(&git.lfs_path.join(username)).await?;
+    Ok(())
+}
+
+/// Rename a user, moving their repositories and LFS objects along with
+/// them. Returns `false` if the username is taken, leaving everything as
+/// it was.
+pub async fn rename(state: &AppState, user_id: UserId, from: &str, to: &str) -> Result<bool> {
+    // The new username is reserved before anything is moved, so that
+    // nothing is moved onto the directories of someone who has it.
+    let mut txn = state.db.begin().await?;
+    if !model::user::rename(&mut txn, user_id, to).await? {
+        return Ok(false);
+    }
+
+    let git = &state.config.git;
+    let dirs = [
+        (git.repository_path.join(from), git.repository_path.join(to)),
+        (git.lfs_path.join(from), git.lfs_path.join(to)),
+    ];
+
+    let mut moved = Vec::new();
+    for (src, dst) in &dirs {
+        match move_dir(src, dst).await {
+            Ok(true) => moved.push((src, dst)),
+            Ok(false) => {}
+            Err(err) => {
+                restore_dirs(&moved).await;
+                return Err(err);
+            }
+        }
+    }
+
+    if let Err(err) = txn.commit().await {
+        restore_dirs(&moved).await;
+        return Err(err.into());
+    }
+
+    Ok(true)
+}
+
+/// Returns `false` if there was nothing to move.
+async fn move_dir(src: &Path, dst: &Path) -> Result<bool> {
+    match fs::rename(src, dst).await {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn restore_dirs(moved: &[(&PathBuf, &PathBuf)]) {
+    for (src, dst) in moved {
+        if let Err(err) = fs::rename(dst, src).await {
+            error!(
+                "failed to move {} back to {}: {:?}",
+                dst.display(),
+                src.display(),
+                err
+            );
+        }
+    }
+}
+
+async fn remove_dir(path: &Path) -> Result<()> {
+    match fs::remove_dir_all(path).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn export_path(config: &Config, id: i64) -> PathBuf {
+    config.export.path.join(format!("{}.tar.gz", id))
+}
+
+/// Queue an export of a user's data and start building it in the
+/// background.
+pub async fn request_export(state: &AppState, user_id: UserId) -> Result<()> {
+    model::export::request(&state.db, user_id).await?;
+
+    let state = state.clone();
+    state.task_tracker.clone().spawn(async move {
+        if let Err(err) = run_exports(&state).await {
+            error!("failed to build exports: {:?}", err);
+        }
+    });
+
+    Ok(())
+}
+
+/// Build every pending export, letting each user know once theirs is ready.
+pub async fn run_exports(state: &AppState) -> Result<()> {
+    while let Some((id, user_id)) = model::export::start_next(&state.db).await? {
+        let size = match build_export(state, id, user_id).await {
+            Ok(size) => size,
+            Err(err) => {
+                error!("failed to build export {}: {:?}", id, err);
+                model::export::fail(&state.db, id).await?;
+                continue;
+            }
+        };
+
+        model::export::finish(&state.db, id, size as i64).await?;
+
+        let text = format!(
+            "The export of your account data is ready. Download it from {}/meta/account within {} days.",
+            state.config.http.public_url, state.config.export.expiry_days
+        );
+        mail::notify(state, user_id, "Your data export is ready", &text).await?;
+    }
+
+    Ok(())
+}
+
+/// Delete expired exports, along with archives left behind by interrupted
+/// exports and deleted users.
+pub async fn clean_exports(state: &AppState) -> Result<()> {
+    model::export::fail_stale(&state.db, EXPORT_TIMEOUT).await?;
+
+    let expiry = time::Duration::days(state.config.export.expiry_days.into());
+    model::export::delete_finished_before(&state.db, OffsetDateTime::now_utc() - expiry).await?;
+
+    let ids: HashSet<i64> = model::export::all_ids(&state.db)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut entries = match fs::read_dir(&state.config.export.path).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    // Archives, finished or partial, are named after their export.
+    while let Some(entry) = entries.next_entry().await? {
+        let id = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.split('.').next())
+            .and_then(|id| id.parse::<i64>().ok());
+
+        if let Some(id) = id
+            && !ids.contains(&id)
+        {
+            fs::remove_file(entry.path()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AccountData {
+    exported_at: String,
+    username: String,
+    email: String,
+    email_verified: bool,
+    display_name: String,
+    biography: String,
+    keys: Vec<KeyData>,
+    identities: Vec<IdentityData>,
+    pastes: Vec<PasteData>,
+    repositories: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct KeyData {
+    key: String,
+    name: String,
+    managed: bool,
+}
+
+#[derive(Serialize)]
+struct IdentityData {
+    provider: String,
+    email: Option<String>,
+    linked_at: String,
+}
+
+/// Paste contents are stored in the archive under `pastes/<id>/`.
+#[derive(Serialize)]
+struct PasteData {
+    id: String,
+    visibility: String,
+    password_protected: bool,
+    files: Vec<String>,
+}
+
+/// Write the archive for an export: `account.json` describing the account,
+/// and the files of every paste. Returns the size of the archive.
+async fn build_export(state: &AppState, id: i64, user_id: UserId) -> Result<u64> {
+    let profile = model::user::get_profile(&state.db, user_id)
+        .await?
+        .context("user not found")?;
+
+    let keys = model::user::get_user_keys(&state.db, user_id)
+        .await?
+        .into_iter()
+        .map(|key| KeyData {
+            key: format!("{} {}", key.key_type, key.encoded),
+            name: key.name,
+            managed: key.managed,
+        })
+        .collect();
+
+    let identities = model::identity::list_for_user(&state.db, user_id)
+        .await?
+        .into_iter()
+        .map(|identity| IdentityData {
+            provider: identity.provider,
+            email: identity.email,
+            linked_at: format_time(identity.created_at),
+        })
+        .collect();
+
+    // One row per file, grouped by paste.
+    let mut pastes: Vec<PasteData> = Vec::new();
+    for paste in model::paste::get_user_pastes(&state.db, user_id).await? {
+        match pastes.last_mut() {
+            Some(last) if last.id == paste.id => last.files.push(paste.filename),
+            _ => pastes.push(PasteData {
+                id: paste.id,
+                visibility: paste.visibility,
+                password_protected: paste.protected,
+                files: vec![paste.filename],
+            }),
+        }
+    }
+
+    let repositories =
+        list_repositories(&state.config.git.repository_path.join(&profile.username)).await?;
+
+    let account = AccountData {
+        exported_at: format_time(OffsetDateTime::now_utc()),
+        username: profile.username,
+        email: profile.email,
+        email_verified: profile.email_verified,
+        display_name: profile.display_name,
+        biography: profile.biography,
+        keys,
+        identities,
+        pastes,
+        repositories,
+    };
+
+    let json = serde_json::to_vec_pretty(&account)?;
+    let files = model::paste::get_user_files(&state.db, user_id).await?;
+    let path = export_path(&state.config, id);
+
+    task::spawn_blocking(move || write_archive(&path, &json, &files)).await?
+}
+
+async fn list_repositories(path: &Path) -> Result<Vec<String>> {
+    let mut entries = match fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut repositories = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            repositories.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    repositories.sort();
+    Ok(repositories)
+}
+
+/// The archive is written next to its final path and moved into place once
+/// complete, so a partial archive is never served.
+fn write_archive(path: &Path, account: &[u8], files: &[model::paste::File]) -> Result<u64> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    let mut archive = tar::Builder::new(encoder);
+    let mtime = OffsetDateTime::now_utc().unix_timestamp() as u64;
+
+    append(&mut archive, "account.json", account, mtime)?;
+    for file in files {
+        let name = format!("pastes/{}/{}", file.paste_id, file.filename);
+        append(&mut archive, &name, file.content.as_bytes(), mtime)?;
+    }
+
+    archive.into_inner()?.finish()?;
+    std::fs::rename(&partial, path)?;
+    Ok(std::fs::metadata(path)?.len())
+}
+
+fn append(
+    archive: &mut tar::Builder<GzEncoder<File>>,
+    name: &str,
+    data: &[u8],
+    mtime: u64,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    archive.append_data(&mut header, name, data)
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres container"]
+    async fn rename_moves_directories(db: PgPool) {
+        let root = std::env::temp_dir().join(format!("conduit-rename-{}", rand::random::<u64>()));
+        let state = AppState::for_tests_with(db, |config| {
+            config.git.repository_path = root.join("repositories");
+            config.git.lfs_path = root.join("lfs");
+        });
+        let repositories = &state.config.git.repository_path;
+
+        let alice = model::user::create(&state.db, "alice", "alice@example.com", "password")
+            .await
+            .unwrap()
+            .unwrap();
+        model::user::create(&state.db, "bob", "bob@example.com", "password")
+            .await
+            .unwrap()
+            .unwrap();
+        for username in ["alice", "bob"] {
+            std::fs::create_dir_all(repositories.join(username).join("notes.git")).unwrap();
+        }
+
+        // A taken username leaves both users' repositories where they were.
+        assert!(!rename(&state, alice, "alice", "bob").await.unwrap());
+        assert!(repositories.join("alice/notes.git").exists());
+        assert!(repositories.join("bob/notes.git").exists());
+
+        assert!(rename(&state, alice, "alice", "carol").await.unwrap());
+        assert!(repositories.join("carol/notes.git").exists());
+        assert!(!repositories.join("alice").exists());
+        assert_eq!(
+            model::user::resolve_redirect(&state.db, "alice")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("carol")
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -11,6 +11,23 @@ pub struct Config {
     pub http: Http,
     pub ssh: Ssh,
     pub git: Git,
+    #[serde(default)]
+    pub paste: Paste,
+    /// Outbound email. Without it, password reset and email verification are
+    /// unavailable and notifications are dropped.
+    pub mail: Option<Mail>,
+    #[serde(default)]
+    pub auth: Auth,
+    #[serde(default)]
+    pub registration: Registration,
+    #[serde(default)]
+    pub export: Export,
+    /// OpenID Connect providers offered on the login page.
+    #[serde(default)]
+    pub oidc: Vec<OidcProvider>,
+    /// Authenticate web logins against an LDAP directory before falling back
+    /// to local accounts.
+    pub ldap: Option<Ldap>,
 }
 
 impl Config {
@@ -43,7 +60,13 @@ impl Config {
 
     async fn load_from_file(path: &Path) -> Result<Self> {
         let contents = fs::read_to_string(path).await?;
-        let config = toml::from_str(&contents)?;
+        let config: Self = toml::from_str(&contents)?;
+
+        // Domain registration trusts addresses only once they are verified.
+        if config.registration.mode == RegistrationMode::Domain && config.mail.is_none() {
+            bail!("registration mode \"domain\" requires [mail] to verify addresses");
+        }
+
         Ok(config)
     }
 }
@@ -62,6 +85,13 @@ pub struct Http {
     pub public_url: String,
     pub host: String,
     pub port: u16,
+    /// Take client addresses from `X-Forwarded-For`. Only enable this behind
+    /// a reverse proxy that sets the header.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// Signs values handed to clients, such as paste unlock cookies. Use a
+    /// long random string and keep it private.
+    pub secret: String,
 }
 
 #[derive(Deserialize)]
@@ -76,3 +106,253 @@ pub struct Git {
     pub repository_path: PathBuf,
     pub lfs_path: PathBuf,
 }
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Paste {
+    /// Maximum size of a single paste in bytes.
+    pub max_size: usize,
+    /// Maximum total size of all pastes owned by one user in bytes.
+    pub user_quota: u64,
+}
+
+impl Default for Paste {
+    fn default() -> Self {
+        Self {
+            max_size: 1024 * 1024,
+            user_quota: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Auth {
+    /// Require every user to set up two-factor authentication before they
+    /// can use the site.
+    pub require_two_factor: bool,
+    pub rate_limit: RateLimit,
+}
+
+/// Throttling of failed logins, registrations, SSH authentication and paste
+/// password guesses, keyed by client address and by username.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimit {
+    /// Attempts allowed within `window_secs` before locking out.
+    pub max_attempts: u32,
+    pub window_secs: u64,
+    /// Length of the first lockout. Each further lockout doubles it, up to
+    /// `max_lockout_secs`.
+    pub lockout_secs: u64,
+    pub max_lockout_secs: u64,
+    /// Ban an address from connecting over SSH once it has been locked out
+    /// this many times. Banning is disabled when unset.
+    pub ban_after: Option<u32>,
+    pub ban_secs: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            window_secs: 15 * 60,
+            lockout_secs: 60,
+            max_lockout_secs: 60 * 60,
+            ban_after: None,
+            ban_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+/// Who may create an account. Accounts created on a first sign-in through
+/// an identity provider or the directory follow the same rules, except that
+/// invite mode turns them away, as there is no invite to present.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Registration {
+    pub mode: RegistrationMode,
+    /// Email domains that may register in `domain` mode, such as
+    /// `example.com`. Subdomains are not included.
+    pub allowed_domains: Vec<String>,
+    /// Let every user issue invites, not just administrators.
+    pub user_invites: bool,
+    /// Days before an unused invite expires.
+    pub invite_expiry_days: u32,
+}
+
+impl Default for Registration {
+    fn default() -> Self {
+        Self {
+            mode: RegistrationMode::Open,
+            allowed_domains: Vec::new(),
+            user_invites: false,
+            invite_expiry_days: 14,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    Open,
+    /// Nobody may register, apart from the first user of a new instance.
+    Closed,
+    /// Registering takes a single-use invite code.
+    Invite,
+    /// Only addresses in `allowed_domains` may register, and must be
+    /// verified before logging in.
+    Domain,
+}
+
+/// Archives of account data that users can download.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Export {
+    pub path: PathBuf,
+    /// Days before a finished export is deleted.
+    pub expiry_days: u32,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("data/exports"),
+            expiry_days: 7,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Mail {
+    pub host: String,
+    pub port: u16,
+    pub tls: TlsMode,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The sender, such as `Conduit <conduit@example.com>`.
+    pub from: String,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// Plaintext, for local relays and SMTP sinks.
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Deserialize)]
+pub struct OidcProvider {
+    /// Identifies the provider in URLs and linked accounts. Changing it
+    /// unlinks every account linked through it.
+    pub id: String,
+    /// Shown on the login button, as in "Sign in with {name}".
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Requested in addition to `openid`.
+    #[serde(default = "OidcProvider::default_scopes")]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub claims: ClaimMapping,
+}
+
+impl OidcProvider {
+    fn default_scopes() -> Vec<String> {
+        vec!["email".to_owned(), "profile".to_owned()]
+    }
+}
+
+/// Which ID token claims hold the account details used to provision users.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ClaimMapping {
+    pub username: String,
+    pub email: String,
+    pub email_verified: String,
+    pub display_name: String,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            username: "preferred_username".to_owned(),
+            email: "email".to_owned(),
+            email_verified: "email_verified".to_owned(),
+            display_name: "name".to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Ldap {
+    /// Such as `ldap://ldap.example.com` or `ldaps://ldap.example.com`.
+    pub url: String,
+    #[serde(default)]
+    pub starttls: bool,
+    /// The account used to look users up. Anonymous when unset.
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub base_dn: String,
+    /// Finds the entry of a user, with `{username}` standing in for the
+    /// escaped login name.
+    #[serde(default = "Ldap::default_user_filter")]
+    pub user_filter: String,
+    #[serde(default)]
+    pub attributes: LdapAttributes,
+    #[serde(default)]
+    pub groups: LdapGroups,
+}
+
+impl Ldap {
+    fn default_user_filter() -> String {
+        "(&(objectClass=inetOrgPerson)(uid={username}))".to_owned()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LdapAttributes {
+    pub username: String,
+    pub email: String,
+    pub display_name: String,
+    /// Public keys to sync into `user_keys`. Syncing is disabled when unset.
+    pub ssh_public_key: Option<String>,
+}
+
+impl Default for LdapAttributes {
+    fn default() -> Self {
+        Self {
+            username: "uid".to_owned(),
+            email: "mail".to_owned(),
+            display_name: "cn".to_owned(),
+            ssh_public_key: None,
+        }
+    }
+}
+
+/// Maps group membership onto roles. Groups are given by DN and list their
+/// members by DN in `member_attribute`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LdapGroups {
+    pub member_attribute: String,
+    /// Members may log in. Everyone matching the user filter may when unset.
+    pub user: Option<String>,
+    /// Members are administrators. Administrator status is left alone when
+    /// unset.
+    pub admin: Option<String>,
+}
+
+impl Default for LdapGroups {
+    fn default() -> Self {
+        Self {
+            member_attribute: "member".to_owned(),
+            user: None,
+            admin: None,
+        }
+    }
+}
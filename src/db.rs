@@ -51,10 +51,19 @@ where
         let mut txn = conn.begin().await?;
 
         match callback(&mut txn, &args).await {
-            Ok(ret) => {
-                txn.commit().await?;
-                break Ok(ret);
-            }
+            // Serialization failures may only be reported on commit.
+            Ok(ret) => match txn.commit().await {
+                Ok(()) => break Ok(ret),
+                Err(err) => {
+                    drop(conn);
+
+                    if should_retry(&err) && backoff.wait().await {
+                        continue;
+                    }
+
+                    break Err(err.into());
+                }
+            },
             Err(err) => {
                 txn.rollback().await?;
                 drop(conn);
@@ -72,6 +81,14 @@ where
     }
 }
 
+/// The unique constraint that `err` violated, if any.
+pub fn unique_violation(err: &sqlx::Error) -> Option<&str> {
+    match err {
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => db_err.constraint(),
+        _ => None,
+    }
+}
+
 fn should_retry(err: &sqlx::Error) -> bool {
     if let sqlx::Error::Database(db_err) = err
         && let Some(code) = db_err.code()
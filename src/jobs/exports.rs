@@ -0,0 +1,18 @@
+use anyhow::Result;
+
+use super::Job;
+use crate::account;
+use crate::state::AppState;
+
+pub(super) const JOB: Job = Job {
+    name: "account_exports",
+    interval: 60 * 60,
+    run: |state| Box::pin(run(state)),
+};
+
+/// Exports are normally built as soon as they are requested. This picks up
+/// any left pending by a restart and removes expired ones.
+async fn run(state: &AppState) -> Result<()> {
+    account::clean_exports(state).await?;
+    account::run_exports(state).await
+}
@@ -0,0 +1,22 @@
+use anyhow::Result;
+
+use super::Job;
+use crate::state::AppState;
+
+pub(super) const JOB: Job = Job {
+    name: "expired_invites_cleanup",
+    interval: 24 * 60 * 60,
+    run: |state| Box::pin(run(state)),
+};
+
+/// Expired invites are kept for a while so that their issuers can see what
+/// became of them.
+async fn run(state: &AppState) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM invites WHERE used_at IS NULL AND expires < now() - interval '30 days'"
+    )
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
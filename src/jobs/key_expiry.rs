@@ -0,0 +1,39 @@
+use anyhow::Result;
+use tracing::error;
+
+use super::Job;
+use crate::state::AppState;
+use crate::{mail, model};
+
+pub(super) const JOB: Job = Job {
+    name: "ssh_key_expiry_notices",
+    interval: 60 * 60,
+    run: |state| Box::pin(run(state)),
+};
+
+/// How long before a key expires its owner is warned.
+const NOTICE: time::Duration = time::Duration::days(7);
+
+async fn run(state: &AppState) -> Result<()> {
+    for key in model::user::expiring_keys(&state.db, NOTICE).await? {
+        let text = format!(
+            "Your SSH key \"{}\" ({}) expires on {}. Add a new key at {}/meta/keys to keep access.",
+            key.name,
+            key.fingerprint,
+            key.expires_at.date(),
+            state.config.http.public_url
+        );
+        // A key is only marked once its notice is out, so a failed notice
+        // is retried on the next run instead of holding up the others.
+        if let Err(err) = mail::notify(state, key.user_id, "SSH key expiring soon", &text).await {
+            error!(
+                "failed to send expiry notice for key {}: {:?}",
+                key.fingerprint, err
+            );
+            continue;
+        }
+        model::user::mark_expiry_notified(&state.db, &key.key_type, &key.encoded).await?;
+    }
+
+    Ok(())
+}
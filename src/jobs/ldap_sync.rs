@@ -0,0 +1,19 @@
+use anyhow::Result;
+
+use super::Job;
+use crate::ldap;
+use crate::state::AppState;
+
+pub(super) const JOB: Job = Job {
+    name: "ldap_sync",
+    interval: 60 * 60,
+    run: |state| Box::pin(run(state)),
+};
+
+async fn run(state: &AppState) -> Result<()> {
+    let Some(config) = &state.config.ldap else {
+        return Ok(());
+    };
+
+    ldap::sync(state, config).await
+}
@@ -0,0 +1,18 @@
+use anyhow::Result;
+
+use super::Job;
+use crate::state::AppState;
+use crate::{mail, model};
+
+/// Retries deliveries that failed when the message was first sent.
+pub(super) const JOB: Job = Job {
+    name: "mail_queue",
+    interval: 0,
+    run: |state| Box::pin(run(state)),
+};
+
+async fn run(state: &AppState) -> Result<()> {
+    mail::deliver_pending(state).await?;
+    model::mail::delete_abandoned(&state.db).await?;
+    Ok(())
+}
@@ -1,4 +1,14 @@
+mod email_tokens;
+mod exports;
+mod invites;
+mod key_expiry;
+mod ldap_sync;
 mod lfs_tokens;
+mod login_challenges;
+mod mail_queue;
+mod oauth;
+mod oidc_states;
+mod rate_limit;
 mod web_sessions;
 
 use std::time::Duration;
@@ -12,7 +22,20 @@ use tracing::error;
 use crate::state::AppState;
 
 const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
-static JOBS: &[Job] = &[lfs_tokens::JOB, web_sessions::JOB];
+static JOBS: &[Job] = &[
+    lfs_tokens::JOB,
+    web_sessions::JOB,
+    mail_queue::JOB,
+    email_tokens::JOB,
+    login_challenges::JOB,
+    oidc_states::JOB,
+    ldap_sync::JOB,
+    rate_limit::JOB,
+    oauth::JOB,
+    invites::JOB,
+    exports::JOB,
+    key_expiry::JOB,
+];
 
 struct Job {
     name: &'static str,
@@ -20,6 +43,33 @@ struct Job {
     run: fn(&AppState) -> BoxFuture<'_, Result<()>>,
 }
 
+pub struct JobStatus {
+    pub name: &'static str,
+    /// Seconds between runs.
+    pub interval: u64,
+    /// When the job last completed successfully.
+    pub last_run: Option<OffsetDateTime>,
+}
+
+/// Every registered job along with when it last ran.
+pub async fn status(state: &AppState) -> Result<Vec<JobStatus>> {
+    let records = sqlx::query!("SELECT name, last_run FROM jobs_last_run")
+        .fetch_all(&state.db)
+        .await?;
+
+    Ok(JOBS
+        .iter()
+        .map(|job| JobStatus {
+            name: job.name,
+            interval: job.interval,
+            last_run: records
+                .iter()
+                .find(|record| record.name == job.name)
+                .map(|record| record.last_run),
+        })
+        .collect())
+}
+
 pub struct Scheduler {
     interval: Interval,
 }
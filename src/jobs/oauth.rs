@@ -0,0 +1,22 @@
+use anyhow::Result;
+
+use super::Job;
+use crate::state::AppState;
+
+pub(super) const JOB: Job = Job {
+    name: "expired_oauth_grants_cleanup",
+    interval: 24 * 60 * 60,
+    run: |state| Box::pin(run(state)),
+};
+
+async fn run(state: &AppState) -> Result<()> {
+    sqlx::query!("DELETE FROM oauth_codes WHERE expires < now()")
+        .execute(&state.db)
+        .await?;
+
+    sqlx::query!("DELETE FROM oauth_tokens WHERE refresh_expires < now()")
+        .execute(&state.db)
+        .await?;
+
+    Ok(())
+}
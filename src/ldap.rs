@@ -0,0 +1,591 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use ldap3::{LdapConnAsync, LdapConnSettings, Scope, SearchEntry, ldap_escape};
+use tracing::{debug, warn};
+
+use crate::config::{self, Ldap};
+use crate::model::identity::NewUser;
+use crate::model::user::{ManagedKey, UserId};
+use crate::state::AppState;
+use crate::validate::{self, SshPublicKey};
+use crate::{model, registration};
+
+/// Linked accounts are recorded in `user_identities` under this provider,
+/// keyed by the directory username.
+const PROVIDER: &str = "ldap";
+const TIMEOUT: Duration = Duration::from_secs(10);
+const RC_INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+/// A user entry, with its group memberships resolved into a role.
+pub struct DirectoryUser {
+    pub dn: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub ssh_public_keys: Vec<String>,
+    /// `None` when the user is not allowed to log in.
+    pub role: Option<Role>,
+}
+
+pub enum Login {
+    /// The directory has no such user, so local accounts should be tried.
+    Unknown,
+    /// Wrong password, no access, or no usable local account.
+    Denied,
+    User(UserId),
+}
+
+async fn connect(config: &Ldap) -> Result<ldap3::Ldap> {
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(TIMEOUT)
+        .set_starttls(config.starttls);
+
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &config.url).await?;
+    ldap3::drive!(conn);
+    ldap.with_timeout(TIMEOUT);
+    Ok(ldap)
+}
+
+struct Connection {
+    ldap: ldap3::Ldap,
+}
+
+impl Connection {
+    /// Connect and bind as the service account.
+    async fn open(config: &Ldap) -> Result<Self> {
+        let mut ldap = connect(config).await?;
+
+        if let Some(bind_dn) = &config.bind_dn {
+            let password = config.bind_password.as_deref().unwrap_or_default();
+            ldap.simple_bind(bind_dn, password).await?.success()?;
+        }
+
+        Ok(Self { ldap })
+    }
+
+    async fn close(mut self) {
+        if let Err(err) = self.ldap.unbind().await {
+            debug!("failed to unbind from LDAP: {}", err);
+        }
+    }
+
+    async fn search_users(&mut self, config: &Ldap, filter: &str) -> Result<Vec<SearchEntry>> {
+        let attributes = &config.attributes;
+        let mut wanted = vec![
+            attributes.username.as_str(),
+            attributes.email.as_str(),
+            attributes.display_name.as_str(),
+        ];
+        wanted.extend(attributes.ssh_public_key.as_deref());
+
+        let (entries, _) = self
+            .ldap
+            .search(&config.base_dn, Scope::Subtree, filter, wanted)
+            .await?
+            .success()?;
+
+        Ok(entries.into_iter().map(SearchEntry::construct).collect())
+    }
+
+    /// The DNs of the members of a group.
+    async fn group_members(
+        &mut self,
+        groups: &config::LdapGroups,
+        dn: &str,
+    ) -> Result<Vec<String>> {
+        let (entries, _) = self
+            .ldap
+            .search(
+                dn,
+                Scope::Base,
+                "(objectClass=*)",
+                vec![groups.member_attribute.as_str()],
+            )
+            .await?
+            .success()?;
+
+        Ok(entries
+            .into_iter()
+            .map(SearchEntry::construct)
+            .flat_map(|mut entry| {
+                entry
+                    .attrs
+                    .remove(&groups.member_attribute)
+                    .unwrap_or_default()
+            })
+            .collect())
+    }
+
+    async fn memberships(&mut self, config: &Ldap) -> Result<Memberships> {
+        let groups = &config.groups;
+        let mut memberships = Memberships::default();
+
+        if let Some(dn) = &groups.user {
+            memberships.users = Some(self.group_members(groups, dn).await?);
+        }
+        if let Some(dn) = &groups.admin {
+            memberships.admins = self.group_members(groups, dn).await?;
+        }
+
+        Ok(memberships)
+    }
+}
+
+#[derive(Default)]
+struct Memberships {
+    /// `None` when any user may log in.
+    users: Option<Vec<String>>,
+    admins: Vec<String>,
+}
+
+impl Memberships {
+    fn role(&self, dn: &str) -> Option<Role> {
+        let contains = |members: &[String]| members.iter().any(|m| m.eq_ignore_ascii_case(dn));
+
+        if contains(&self.admins) {
+            Some(Role::Admin)
+        } else if self.users.as_deref().is_none_or(contains) {
+            Some(Role::User)
+        } else {
+            None
+        }
+    }
+}
+
+fn directory_user(
+    config: &Ldap,
+    memberships: &Memberships,
+    mut entry: SearchEntry,
+) -> Option<DirectoryUser> {
+    let attributes = &config.attributes;
+    let mut first = |name: &str| {
+        entry
+            .attrs
+            .remove(name)
+            .and_then(|values| values.into_iter().next())
+    };
+
+    let username = first(&attributes.username)?;
+    let email = first(&attributes.email);
+    let display_name = first(&attributes.display_name);
+    let ssh_public_keys = attributes
+        .ssh_public_key
+        .as_ref()
+        .and_then(|name| entry.attrs.remove(name))
+        .unwrap_or_default();
+
+    Some(DirectoryUser {
+        role: memberships.role(&entry.dn),
+        dn: entry.dn,
+        username,
+        email,
+        display_name,
+        ssh_public_keys,
+    })
+}
+
+/// Look the user up with the service account, then bind as them to check
+/// the password.
+async fn authenticate(
+    config: &Ldap,
+    username: &str,
+    password: &str,
+) -> Result<Option<DirectoryUser>> {
+    let mut conn = Connection::open(config).await?;
+    let filter = config
+        .user_filter
+        .replace("{username}", &ldap_escape(username));
+    let mut entries = conn.search_users(config, &filter).await?;
+
+    if entries.len() > 1 {
+        conn.close().await;
+        return Err(anyhow!(
+            "user filter matched {} entries for {}",
+            entries.len(),
+            username
+        ));
+    }
+
+    let Some(entry) = entries.pop() else {
+        conn.close().await;
+        return Ok(None);
+    };
+
+    let memberships = conn.memberships(config).await?;
+    conn.close().await;
+
+    let Some(user) = directory_user(config, &memberships, entry) else {
+        return Err(anyhow!("entry for {} has no username attribute", username));
+    };
+
+    // A separate connection, so that the service account's binding is not
+    // replaced by the user's.
+    let mut ldap = connect(config).await?;
+    let result = ldap.simple_bind(&user.dn, password).await?;
+    let _ = ldap.unbind().await;
+
+    match result.rc {
+        0 => Ok(Some(user)),
+        RC_INVALID_CREDENTIALS => Ok(Some(DirectoryUser { role: None, ..user })),
+        _ => Err(result.success().unwrap_err().into()),
+    }
+}
+
+/// Every user matching the user filter.
+pub async fn list_users(config: &Ldap) -> Result<Vec<DirectoryUser>> {
+    let mut conn = Connection::open(config).await?;
+    let filter = config.user_filter.replace("{username}", "*");
+    let entries = conn.search_users(config, &filter).await?;
+    let memberships = conn.memberships(config).await?;
+    conn.close().await;
+
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| directory_user(config, &memberships, entry))
+        .collect())
+}
+
+/// Check a web login against the directory, creating or updating the local
+/// account of the user.
+pub async fn login(
+    state: &AppState,
+    config: &Ldap,
+    username: &str,
+    password: &str,
+) -> Result<Login> {
+    // An empty password would be an unauthenticated bind, which succeeds.
+    if password.is_empty() {
+        return Ok(Login::Denied);
+    }
+
+    let Some(user) = authenticate(config, username, password).await? else {
+        return Ok(Login::Unknown);
+    };
+
+    if user.role.is_none() {
+        return Ok(Login::Denied);
+    }
+
+    match local_account(state, &user).await? {
+        Some(user_id) => {
+            model::identity::login(&state.db, PROVIDER, &user.username).await?;
+            sync_user(state, config, user_id, &user).await?;
+            Ok(Login::User(user_id))
+        }
+        None => Ok(Login::Denied),
+    }
+}
+
+/// The local account of a directory user, linking or creating it on their
+/// first login. An existing local account is only taken over if its email
+/// address matches the directory's, and new ones are only created if the
+/// registration mode allows it.
+async fn local_account(state: &AppState, user: &DirectoryUser) -> Result<Option<UserId>> {
+    if let Some(user_id) = model::identity::find_user(&state.db, PROVIDER, &user.username).await? {
+        return Ok(Some(user_id));
+    }
+
+    let Some(email) = &user.email else {
+        warn!("LDAP user {} has no email address", user.username);
+        return Ok(None);
+    };
+
+    if let Some(user_id) = model::user::get_id_by_username(&state.db, &user.username).await? {
+        let profile = model::user::get_profile(&state.db, user_id).await?;
+        if profile.is_some_and(|profile| profile.email.eq_ignore_ascii_case(email)) {
+            model::identity::link(&state.db, user_id, PROVIDER, &user.username, Some(email))
+                .await?;
+            return Ok(Some(user_id));
+        }
+
+        warn!(
+            "LDAP user {} conflicts with a local account with another email address",
+            user.username
+        );
+        return Ok(None);
+    }
+
+    if !validate::is_username(&user.username)
+        || model::user::get_id_by_email(&state.db, email)
+            .await?
+            .is_some()
+    {
+        warn!(
+            "LDAP user {} cannot be given a local account",
+            user.username
+        );
+        return Ok(None);
+    }
+
+    if !registration::allows_provisioning(state, email).await? {
+        warn!(
+            "LDAP user {} was not given a local account, since registration does not allow it",
+            user.username
+        );
+        return Ok(None);
+    }
+
+    let user_id = model::identity::provision(
+        &state.db,
+        NewUser {
+            username: user.username.clone(),
+            email: email.clone(),
+            email_verified: true,
+            display_name: user
+                .display_name
+                .clone()
+                .unwrap_or_else(|| user.username.clone()),
+            provider: PROVIDER.to_owned(),
+            subject: user.username.clone(),
+        },
+    )
+    .await?;
+
+    Ok(Some(user_id))
+}
+
+/// Bring the role and keys of a linked user in line with the directory.
+/// Users who lost access keep their account but lose their synced keys.
+async fn sync_user(
+    state: &AppState,
+    config: &Ldap,
+    user_id: UserId,
+    user: &DirectoryUser,
+) -> Result<()> {
+    if config.groups.admin.is_some() {
+        model::user::set_admin(&state.db, user_id, user.role == Some(Role::Admin)).await?;
+    }
+
+    if config.attributes.ssh_public_key.is_none() {
+        return Ok(());
+    }
+
+    let keys = match user.role {
+        Some(_) => managed_keys(user),
+        None => Vec::new(),
+    };
+
+    model::user::sync_managed_keys(&state.db, user_id, keys).await
+}
+
+fn managed_keys(user: &DirectoryUser) -> Vec<ManagedKey> {
+    user.ssh_public_keys
+        .iter()
+        .filter_map(|value| match SshPublicKey::parse(value) {
+            Ok(key) => {
+                let (username, hostname) = model::user::key_comment_parts(key.comment);
+                Some(ManagedKey {
+                    key_type: key.key_type.to_owned(),
+                    encoded: key.encoded.to_owned(),
+                    name: key.comment.unwrap_or("directory key").to_owned(),
+                    username,
+                    hostname,
+                })
+            }
+            Err(err) => {
+                warn!(
+                    "skipping invalid SSH key of LDAP user {}: {}",
+                    user.username, err
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// Sync every linked user with the directory. Users who have disappeared
+/// from it are treated as having lost access.
+pub async fn sync(state: &AppState, config: &Ldap) -> Result<()> {
+    let mut directory = list_users(config).await?;
+
+    for (subject, user_id) in model::identity::list_for_provider(&state.db, PROVIDER).await? {
+        let user = match directory.iter().position(|user| user.username == subject) {
+            Some(index) => directory.swap_remove(index),
+            None => DirectoryUser {
+                dn: String::new(),
+                username: subject,
+                email: None,
+                display_name: None,
+                ssh_public_keys: Vec::new(),
+                role: None,
+            },
+        };
+
+        sync_user(state, config, user_id, &user).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::config::RegistrationMode;
+
+    /// Logs in against the seeded directory in docker/ldap, where alice is
+    /// an administrator, bob a user and carol not allowed in.
+    async fn try_login(state: &AppState, username: &str, password: &str) -> Login {
+        let config = state.config.ldap.as_ref().unwrap();
+        login(state, config, username, password).await.unwrap()
+    }
+
+    async fn is_admin(state: &AppState, username: &str) -> bool {
+        model::user::get_summary(&state.db, username)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_admin
+    }
+
+    async fn key_names(state: &AppState, user_id: UserId) -> Vec<String> {
+        model::user::get_user_keys(&state.db, user_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|key| key.managed)
+            .map(|key| key.name)
+            .collect()
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres and OpenLDAP containers"]
+    async fn provisioning_and_roles(db: PgPool) {
+        let state = AppState::for_tests(db);
+
+        let Login::User(alice) = try_login(&state, "alice", "alice").await else {
+            panic!("alice could not log in");
+        };
+        assert!(is_admin(&state, "alice").await);
+        assert_eq!(key_names(&state, alice).await, ["alice@example"]);
+
+        let Login::User(bob) = try_login(&state, "bob", "bob").await else {
+            panic!("bob could not log in");
+        };
+        assert!(!is_admin(&state, "bob").await);
+        assert_eq!(key_names(&state, bob).await, ["bob@example"]);
+
+        let profile = model::user::get_profile(&state.db, bob)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile.email, "bob@example.org");
+        assert_eq!(profile.display_name, "Bob Example");
+        assert!(profile.email_verified);
+
+        // Logging in again finds the same account.
+        assert!(matches!(try_login(&state, "bob", "bob").await, Login::User(id) if id == bob));
+
+        assert!(matches!(
+            try_login(&state, "carol", "carol").await,
+            Login::Denied
+        ));
+        assert!(matches!(
+            try_login(&state, "bob", "wrong").await,
+            Login::Denied
+        ));
+        assert!(matches!(try_login(&state, "bob", "").await, Login::Denied));
+        assert!(matches!(
+            try_login(&state, "dave", "dave").await,
+            Login::Unknown
+        ));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres and OpenLDAP containers"]
+    async fn existing_accounts(db: PgPool) {
+        let state = AppState::for_tests(db);
+        let bob = model::user::create(&state.db, "bob", "bob@example.org", "password")
+            .await
+            .unwrap()
+            .unwrap();
+        model::user::create(&state.db, "alice", "alice@elsewhere.org", "password")
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Matching email addresses link, others are left alone.
+        assert!(matches!(try_login(&state, "bob", "bob").await, Login::User(id) if id == bob));
+        assert!(matches!(
+            try_login(&state, "alice", "alice").await,
+            Login::Denied
+        ));
+        assert!(
+            model::identity::find_user(&state.db, PROVIDER, "alice")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres and OpenLDAP containers"]
+    async fn closed_registration(db: PgPool) {
+        let state = AppState::for_tests_with(db, |config| {
+            config.registration.mode = RegistrationMode::Closed;
+        });
+        let bob = model::user::create(&state.db, "bob", "bob@example.org", "password")
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Existing accounts still link, but no new ones are created.
+        assert!(matches!(try_login(&state, "bob", "bob").await, Login::User(id) if id == bob));
+        assert!(matches!(
+            try_login(&state, "alice", "alice").await,
+            Login::Denied
+        ));
+        assert!(
+            model::user::get_id_by_username(&state.db, "alice")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres and OpenLDAP containers"]
+    async fn sync_follows_the_directory(db: PgPool) {
+        let state = AppState::for_tests(db);
+        let config = state.config.ldap.as_ref().unwrap();
+
+        let Login::User(bob) = try_login(&state, "bob", "bob").await else {
+            panic!("bob could not log in");
+        };
+        model::user::set_admin(&state.db, bob, true).await.unwrap();
+
+        // Someone who has since left the directory.
+        let dave = model::user::create(&state.db, "dave", "dave@example.org", "password")
+            .await
+            .unwrap()
+            .unwrap();
+        model::identity::link(&state.db, dave, PROVIDER, "dave", None)
+            .await
+            .unwrap();
+        let key = ManagedKey {
+            key_type: "ssh-ed25519".to_owned(),
+            encoded: "AAAAC3NzaC1lZDI1NTE5AAAAIGdpTy1S4VmvIm4F2yZ7gDdDaHdgZDKg0fRRgD1ZuIm8"
+                .to_owned(),
+            username: "dave".to_owned(),
+            hostname: "example".to_owned(),
+            name: "dave@example".to_owned(),
+        };
+        model::user::sync_managed_keys(&state.db, dave, vec![key])
+            .await
+            .unwrap();
+
+        sync(&state, config).await.unwrap();
+
+        assert!(!is_admin(&state, "bob").await);
+        assert_eq!(key_names(&state, bob).await, ["bob@example"]);
+        assert!(key_names(&state, dave).await.is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 use std::ffi::CString;
 use std::mem;
+use std::net::SocketAddr;
 use std::os::fd::{AsRawFd, OwnedFd};
 
 use libssh_rs_sys::{self as libssh};
@@ -50,8 +51,8 @@ impl Listener {
         Ok(Self { bind, listener })
     }
 
-    pub async fn accept(&mut self) -> io::Result<Session> {
-        let (socket, _) = self.listener.accept().await?;
+    pub async fn accept(&mut self) -> io::Result<(Session, SocketAddr)> {
+        let (socket, addr) = self.listener.accept().await?;
         let fd = OwnedFd::from(socket.into_std()?);
 
         let session = unsafe { libssh::ssh_new() };
@@ -60,7 +61,7 @@ impl Listener {
         mem::forget(fd);
 
         match rc {
-            error::SSH_OK => Ok(Session::new(session)),
+            error::SSH_OK => Ok((Session::new(session), addr)),
             error::SSH_ERROR => Err(error::libssh(session as _)),
             _ => unreachable!(),
         }
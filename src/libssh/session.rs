@@ -60,6 +60,17 @@ impl Session {
         self.handle_mut().keys = keys;
     }
 
+    /// Reject all further authentication attempts on this session.
+    pub fn deny_auth(&mut self) {
+        self.handle_mut().auth_denied = true;
+    }
+
+    /// The number of signed authentication attempts rejected since the last
+    /// call.
+    pub fn take_auth_failures(&mut self) -> u32 {
+        mem::take(&mut self.handle_mut().auth_failures)
+    }
+
     pub async fn handle_key_exchange(&mut self) -> io::Result<()> {
         loop {
             let mut guard = self
@@ -140,6 +151,11 @@ impl Session {
     pub fn authenticated_user(&self) -> Option<&str> {
         self.handle_ref().authenticated_user.as_deref()
     }
+
+    /// The base64 encoded key the user authenticated with.
+    pub fn authenticated_key(&self) -> Option<&str> {
+        self.handle_ref().authenticated_key.as_deref()
+    }
 }
 
 struct Handle {
@@ -148,6 +164,9 @@ struct Handle {
     callbacks: libssh::ssh_server_callbacks_struct,
     keys: Vec<(String, String)>,
     authenticated_user: Option<String>,
+    authenticated_key: Option<String>,
+    auth_denied: bool,
+    auth_failures: u32,
     channel: Option<Pin<Box<UnsafePinned<ChannelState>>>>,
     _pinned: marker::PhantomPinned,
 }
@@ -178,6 +197,9 @@ impl Handle {
             callbacks,
             keys: Vec::new(),
             authenticated_user: None,
+            authenticated_key: None,
+            auth_denied: false,
+            auth_failures: 0,
             channel: None,
             _pinned: marker::PhantomPinned,
         }));
@@ -205,6 +227,10 @@ impl Handle {
         unsafe {
             let handle = &mut *(userdata as *mut Handle);
 
+            if handle.auth_denied {
+                return libssh::ssh_auth_e_SSH_AUTH_DENIED;
+            }
+
             const SSH_PUBLICKEY_STATE_NONE: c_char =
                 libssh::ssh_publickey_state_e::SSH_PUBLICKEY_STATE_NONE as _;
 
@@ -219,30 +245,46 @@ impl Handle {
                 return libssh::ssh_auth_e_SSH_AUTH_DENIED;
             }
 
+            if Self::authenticate(handle, username, pubkey) {
+                libssh::ssh_auth_e_SSH_AUTH_SUCCESS
+            } else {
+                handle.auth_failures += 1;
+                libssh::ssh_auth_e_SSH_AUTH_DENIED
+            }
+        }
+    }
+
+    /// Checks a signed public key attempt against the allowed keys.
+    unsafe fn authenticate(
+        handle: &mut Handle,
+        username: *const c_char,
+        pubkey: libssh::ssh_key,
+    ) -> bool {
+        unsafe {
             let maybe_username = CStr::from_ptr(username).to_str();
             let Ok(username) = maybe_username else {
-                return libssh::ssh_auth_e_SSH_AUTH_DENIED;
+                return false;
             };
 
             if username != "git" {
-                return libssh::ssh_auth_e_SSH_AUTH_DENIED;
+                return false;
             }
 
             let ty = libssh::ssh_key_type(pubkey);
             if ty != libssh::ssh_keytypes_e_SSH_KEYTYPE_ED25519 {
-                return libssh::ssh_auth_e_SSH_AUTH_DENIED;
+                return false;
             }
 
             let mut pubkey_buf: *mut c_char = ptr::null_mut();
 
             let rc = libssh::ssh_pki_export_pubkey_base64(pubkey, &mut pubkey_buf);
             if rc != error::SSH_OK {
-                return libssh::ssh_auth_e_SSH_AUTH_DENIED;
+                return false;
             }
 
             let maybe_pubkey = CStr::from_ptr(pubkey_buf).to_str();
             let Ok(pubkey) = maybe_pubkey.map(ToOwned::to_owned) else {
-                return libssh::ssh_auth_e_SSH_AUTH_DENIED;
+                return false;
             };
 
             libssh::ssh_string_free_char(pubkey_buf);
@@ -250,10 +292,11 @@ impl Handle {
             let entry = handle.keys.iter().find(|(key, _)| key == &pubkey);
             if let Some((_, username)) = entry {
                 handle.authenticated_user = Some(username.clone());
-                return libssh::ssh_auth_e_SSH_AUTH_SUCCESS;
+                handle.authenticated_key = Some(pubkey);
+                return true;
             }
 
-            libssh::ssh_auth_e_SSH_AUTH_DENIED
+            false
         }
     }
 
@@ -0,0 +1,224 @@
+use anyhow::{Result, bail};
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tracing::{error, info};
+
+use crate::config::{self, TlsMode};
+use crate::model;
+use crate::model::email::TokenPurpose;
+use crate::model::mail::QueuedMail;
+use crate::model::user::UserId;
+use crate::state::AppState;
+
+struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    fn new(config: &config::Mail) -> Result<Self> {
+        let builder = match config.tls {
+            TlsMode::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            TlsMode::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            TlsMode::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        };
+
+        let mut builder = builder.port(config.port);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from.parse()?,
+        })
+    }
+
+    async fn deliver(&self, mail: &QueuedMail) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.recipient.parse()?)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())?;
+
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Whether outbound mail is configured. Password reset and email
+/// verification depend on it, since their links must not end up in logs.
+pub fn enabled(state: &AppState) -> bool {
+    state.config.mail.is_some()
+}
+
+/// Queue a message and start delivering it right away. The scheduler picks
+/// up anything that fails here.
+pub async fn send(state: &AppState, recipient: &str, subject: &str, body: &str) -> Result<()> {
+    model::mail::enqueue(&state.db, recipient, subject, body).await?;
+
+    let state = state.clone();
+    state.task_tracker.clone().spawn(async move {
+        if let Err(err) = deliver_pending(&state).await {
+            error!("failed to deliver queued mail: {:?}", err);
+        }
+    });
+
+    Ok(())
+}
+
+/// Deliver every message that is due, recording failures for retry.
+pub async fn deliver_pending(state: &AppState) -> Result<()> {
+    let mailer = state.config.mail.as_ref().map(Mailer::new).transpose()?;
+
+    while let Some(mail) = model::mail::claim_next(&state.db).await? {
+        let Some(mailer) = &mailer else {
+            info!(
+                "mail is not configured, dropping message to {}: {}",
+                mail.recipient, mail.subject
+            );
+            model::mail::mark_sent(&state.db, mail.id).await?;
+            continue;
+        };
+
+        match mailer.deliver(&mail).await {
+            Ok(()) => model::mail::mark_sent(&state.db, mail.id).await?,
+            Err(err) => {
+                error!(
+                    "failed to send mail {} (attempt {}/{}): {}",
+                    mail.id,
+                    mail.attempts,
+                    model::mail::MAX_ATTEMPTS,
+                    err
+                );
+                model::mail::mark_failed(&state.db, mail.id, &err.to_string()).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a link that verifies `email`. Does nothing when mail is not
+/// configured, leaving the address unverified.
+pub async fn send_verification(
+    state: &AppState,
+    user_id: UserId,
+    username: &str,
+    email: &str,
+) -> Result<()> {
+    if !enabled(state) {
+        return Ok(());
+    }
+
+    let token = model::email::create_token(&state.db, user_id, TokenPurpose::Verify, email).await?;
+    let link = format!(
+        "{}/verify-email?token={}",
+        state.config.http.public_url, token
+    );
+    let body = format!(
+        "Hi {username},\n\n\
+         Please confirm that {email} is your email address by opening this link:\n\n\
+         {link}\n\n\
+         The link expires in 2 days. If you did not sign up, you can ignore this message.\n"
+    );
+
+    send(state, email, "Verify your email address", &body).await
+}
+
+/// Send a password reset link. Callers check `enabled` first, as there is
+/// no other way to deliver the link.
+pub async fn send_password_reset(
+    state: &AppState,
+    user_id: UserId,
+    username: &str,
+    email: &str,
+) -> Result<()> {
+    if !enabled(state) {
+        bail!("password reset requested without mail configured");
+    }
+
+    let token = model::email::create_token(&state.db, user_id, TokenPurpose::Reset, email).await?;
+    let link = format!(
+        "{}/reset-password/confirm?token={}",
+        state.config.http.public_url, token
+    );
+    let body = format!(
+        "Hi {username},\n\n\
+         Someone asked to reset the password for your account. To choose a new \
+         password, open this link:\n\n\
+         {link}\n\n\
+         The link expires in 1 hour and can only be used once. If you did not ask \
+         for this, you can ignore this message.\n"
+    );
+
+    send(state, email, "Reset your password", &body).await
+}
+
+/// Tell a user about a change to their account. Only verified addresses
+/// receive notifications.
+pub async fn notify(state: &AppState, user_id: UserId, subject: &str, text: &str) -> Result<()> {
+    let Some(profile) = model::user::get_profile(&state.db, user_id).await? else {
+        return Ok(());
+    };
+
+    if !profile.email_verified {
+        return Ok(());
+    }
+
+    let body = format!(
+        "Hi {},\n\n{}\n\nIf this was not you, reset your password at {}/reset-password.\n",
+        profile.username, text, state.config.http.public_url
+    );
+    send(state, &profile.email, subject, &body).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use openidconnect::reqwest;
+    use sqlx::PgPool;
+
+    use super::*;
+
+    /// Drains the queue into the Mailpit container from docker-compose, with
+    /// `DATABASE_URL` pointing at the Postgres container. `MAILPIT_URL`
+    /// overrides where Mailpit's API is.
+    #[sqlx::test]
+    #[ignore = "needs the Postgres and Mailpit containers"]
+    async fn deliver_to_smtp_sink(db: PgPool) {
+        let state = AppState::for_tests(db);
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let recipient = format!("sink-{}@example.com", nonce);
+
+        model::mail::enqueue(&state.db, &recipient, "Queued", "Hello from the queue.")
+            .await
+            .unwrap();
+        deliver_pending(&state).await.unwrap();
+        assert!(model::mail::claim_next(&state.db).await.unwrap().is_none());
+
+        let mailpit = env::var("MAILPIT_URL").unwrap_or_else(|_| "http://localhost:8025".into());
+        let body = reqwest::Client::new()
+            .get(format!("{}/api/v1/search", mailpit))
+            .query(&[("query", format!("to:{}", recipient))])
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let found: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(found["messages_count"], 1);
+        assert_eq!(found["messages"][0]["Subject"], "Queued");
+    }
+}
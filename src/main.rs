@@ -1,13 +1,19 @@
 #![feature(cfg_select)]
 #![feature(unsafe_pinned)]
 
+mod account;
 mod config;
 mod db;
 mod jobs;
+mod ldap;
 mod libssh;
+mod mail;
 mod metrics;
 mod middleware;
 mod model;
+mod oidc;
+mod ratelimit;
+mod registration;
 mod routes;
 mod signal;
 mod ssh;
@@ -15,9 +21,12 @@ mod state;
 mod utils;
 mod validate;
 
+use std::net::SocketAddr;
+
 use anyhow::Result;
 use axum::Router;
 use config::Config;
+use ratelimit::RateLimiter;
 use state::{AppState, AppStateInner};
 use tokio::fs;
 use tower::ServiceBuilder;
@@ -46,11 +55,13 @@ async fn run() -> Result<()> {
     let config = Config::load(None).await?;
     let (cancel_token, task_tracker) = signal::bind();
     let db = db::connect(&config.database).await?;
+    let limiter = RateLimiter::new(config.auth.rate_limit.clone());
     let state = AppState::new(AppStateInner {
         db,
         config,
         cancel_token,
         task_tracker,
+        limiter,
     });
 
     metrics::get();
@@ -79,6 +90,7 @@ async fn run() -> Result<()> {
             state.clone(),
             middleware::auth::middleware,
         ))
+        .layer(axum::middleware::from_fn(middleware::csrf::middleware))
         .layer(middleware::panic::middleware());
 
     let app = Router::new()
@@ -94,9 +106,12 @@ async fn run() -> Result<()> {
         state.task_tracker.spawn(async move {
             let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
             info!("http server worker starting on {}", addr);
-            if let Err(err) = axum::serve(listener, app)
-                .with_graceful_shutdown(signal)
-                .await
+            if let Err(err) = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(signal)
+            .await
             {
                 error!("http server worker error: {}", err);
             }
@@ -122,13 +137,18 @@ async fn run() -> Result<()> {
                 tokio::select! {
                     _ = state2.cancel_token.cancelled() => break,
                     session = listener.accept() => {
-                        let session = session.unwrap();
+                        let (session, addr) = session.unwrap();
+                        if state2.limiter.is_banned(addr.ip()) {
+                            debug!("rejected ssh connection from banned address {}", addr.ip());
+                            continue;
+                        }
+
                         let state3 = state2.clone();
 
                         state2.task_tracker.spawn(async move {
                             debug!("accepted ssh connection");
 
-                            if let Err(err) = ssh::handle_session(&state3, session).await {
+                            if let Err(err) = ssh::handle_session(&state3, session, addr.ip()).await {
                                 error!("ssh session error: {}", err);
                             }
                         });
@@ -3,7 +3,9 @@ use std::sync::LazyLock;
 use anyhow::Result;
 use axum::response::Response;
 use axum::{Router, routing};
-use prometheus::{self, Encoder, IntGauge, TextEncoder, register_int_gauge};
+use prometheus::{
+    self, Encoder, IntCounter, IntGauge, TextEncoder, register_int_counter, register_int_gauge,
+};
 
 use crate::state::AppState;
 
@@ -27,6 +29,7 @@ macro_rules! metrics {
     };
 
     (hook: IntGauge, $($tail:tt)*) => { register_int_gauge!($($tail)*) };
+    (hook: IntCounter, $($tail:tt)*) => { register_int_counter!($($tail)*) };
 }
 
 metrics! {
@@ -46,7 +49,10 @@ metrics! {
     IntGauge, rt_worker_local_schedule_count, "Number of tasks scheduled from within the runtime on the given worker's local queue.",
     IntGauge, rt_io_driver_fd_registered_count, "Number of file descriptors that have been registered with the runtime's I/O driver.",
     IntGauge, rt_io_driver_fd_deregistered_count, "Number of file descriptors that have been deregistered by the runtime's I/O driver.",
-    IntGauge, rt_io_driver_ready_count, "Number of ready events processed by the runtime's I/O driver."
+    IntGauge, rt_io_driver_ready_count, "Number of ready events processed by the runtime's I/O driver.",
+    IntCounter, auth_failures_total, "Number of failed authentication attempts and registrations counted by the rate limiter.",
+    IntCounter, auth_lockouts_total, "Number of times a client address or username has been locked out.",
+    IntCounter, auth_bans_total, "Number of times a client address has been banned from SSH."
 }
 
 pub fn get() -> &'static Metrics {
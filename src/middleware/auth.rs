@@ -3,10 +3,11 @@ use std::convert::Infallible;
 use axum::extract::{FromRequestParts, OptionalFromRequestParts, Request};
 use axum::http::request::Parts;
 use axum::middleware::Next;
-use axum::response::{Redirect, Response};
+use axum::response::{IntoResponse, Redirect, Response};
 use axum_extra::extract::cookie::CookieJar;
 use url::form_urlencoded;
 
+use crate::middleware::client;
 use crate::model;
 use crate::model::user::UserId;
 use crate::routes::AppError;
@@ -18,6 +19,9 @@ pub const COOKIE_NAME: &str = "conduit_session";
 pub struct Session {
     pub id: UserId,
     pub username: String,
+    /// Identifies this login among the user's sessions.
+    pub session_id: i64,
+    pub is_admin: bool,
 }
 
 impl FromRequestParts<AppState> for Session {
@@ -62,9 +66,21 @@ pub async fn middleware(
         if let Some(session) = maybe_session
             && session.expires > time::OffsetDateTime::now_utc()
         {
+            if state.config.auth.require_two_factor
+                && !session.two_factor
+                && !allowed_without_two_factor(request.uri().path())
+            {
+                return Ok((jar, Redirect::to("/meta/security").into_response()));
+            }
+
+            let ip = client::client_ip(&state, request.headers(), request.extensions());
+            model::session::touch(&state.db, &session, ip).await?;
+
             let auth_session = Session {
                 id: session.user_id,
                 username: session.username,
+                session_id: session.id,
+                is_admin: session.is_admin,
             };
 
             request.extensions_mut().insert(auth_session);
@@ -77,3 +93,11 @@ pub async fn middleware(
     let response = next.run(request).await;
     Ok((jar, response))
 }
+
+/// Pages a user can reach before setting up two-factor authentication when
+/// it is required.
+fn allowed_without_two_factor(path: &str) -> bool {
+    ["/meta/security", "/logout", "/assets/"]
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
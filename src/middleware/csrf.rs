@@ -0,0 +1,142 @@
+use axum::body::{Body, Bytes};
+use axum::extract::Request;
+use axum::http::{Method, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use base64::engine::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+use futures_util::{StreamExt, stream};
+use sha2::{Digest, Sha256};
+
+use crate::middleware::auth::{self, Session};
+use crate::routes::AppError;
+use crate::utils;
+
+/// Seeds the token for visitors without a session, so that forms such as
+/// login and registration are protected too.
+pub const COOKIE_NAME: &str = "conduit_csrf";
+pub const FIELD_NAME: &str = "csrf_token";
+pub const HEADER_NAME: &str = "x-csrf-token";
+
+/// Upper bound on the form prefix read to find the token, which
+/// [`csrf_field`](crate::routes::form::csrf_field) puts first in every form.
+/// The rest of the body is left to the route and its own limit.
+const MAX_FIELD_SIZE: usize = 1024;
+
+tokio::task_local! {
+    static TOKEN: String;
+}
+
+/// The token for the request being handled, for embedding in pages.
+pub fn token() -> Option<String> {
+    TOKEN.try_with(Clone::clone).ok()
+}
+
+/// Must run after [`auth::middleware`], so that the token is tied to a
+/// valid session when there is one.
+///
+/// State-changing requests that carry a valid session or the token cookie
+/// must echo the token in the `X-CSRF-Token` header or the first form field,
+/// `csrf_token`. Other requests have no ambient authority to abuse and are let
+/// through, which covers API clients such as git-lfs.
+pub async fn middleware(
+    mut jar: CookieJar,
+    request: Request,
+    next: Next,
+) -> Result<(CookieJar, Response), AppError> {
+    let session_cookie = jar
+        .get(auth::COOKIE_NAME)
+        .filter(|_| request.extensions().get::<Session>().is_some());
+    let csrf_cookie = jar.get(COOKIE_NAME);
+    let has_cookies = session_cookie.is_some() || csrf_cookie.is_some();
+
+    let seed = match (session_cookie, csrf_cookie) {
+        (Some(cookie), _) | (None, Some(cookie)) => cookie.value().to_owned(),
+        (None, None) => {
+            let buf: [u8; 16] = rand::random();
+            let seed = BASE64_URL_SAFE_NO_PAD.encode(buf);
+            let cookie = Cookie::build((COOKIE_NAME, seed.clone()))
+                .path("/")
+                .http_only(true)
+                .secure(cfg!(not(debug_assertions)))
+                .same_site(SameSite::Lax);
+            jar = jar.add(cookie);
+            seed
+        }
+    };
+    let token = derive_token(&seed);
+
+    let request = if has_cookies && !is_safe(request.method()) {
+        verify(request, &token).await?
+    } else {
+        request
+    };
+
+    let response = TOKEN.scope(token, next.run(request)).await;
+    Ok((jar, response))
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Check the submitted token, handing back the request with its body intact.
+async fn verify(request: Request, token: &str) -> Result<Request, AppError> {
+    if let Some(header) = request.headers().get(HEADER_NAME) {
+        let submitted = header.to_str().unwrap_or_default();
+        if !utils::constant_time_eq(submitted, token) {
+            return Err(AppError::Forbidden);
+        }
+        return Ok(request);
+    }
+
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Err(AppError::Forbidden);
+    }
+
+    let (parts, body) = request.into_parts();
+    let mut rest = body.into_data_stream();
+    let mut prefix = Vec::new();
+    let mut complete = false;
+    while !complete && prefix.len() <= MAX_FIELD_SIZE {
+        match rest.next().await {
+            Some(Ok(chunk)) => {
+                prefix.extend_from_slice(&chunk);
+                complete = prefix.contains(&b'&');
+            }
+            Some(Err(_)) => return Err(AppError::Forbidden),
+            None => complete = true,
+        }
+    }
+
+    let field = prefix.split(|&b| b == b'&').next().unwrap_or_default();
+    let valid = complete
+        && url::form_urlencoded::parse(field)
+            .next()
+            .is_some_and(|(name, submitted)| {
+                name == FIELD_NAME && utils::constant_time_eq(&submitted, token)
+            });
+    if !valid {
+        return Err(AppError::Forbidden);
+    }
+
+    let read = stream::iter([Ok(Bytes::from(prefix))]);
+    Ok(Request::from_parts(
+        parts,
+        Body::from_stream(read.chain(rest)),
+    ))
+}
+
+fn derive_token(seed: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"conduit-csrf:")
+        .chain_update(seed.as_bytes())
+        .finalize();
+    BASE64_URL_SAFE_NO_PAD.encode(digest)
+}
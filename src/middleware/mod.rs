@@ -1,3 +1,5 @@
 pub mod auth;
+pub mod client;
+pub mod csrf;
 pub mod panic;
 pub mod trace;
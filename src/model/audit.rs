@@ -0,0 +1,279 @@
+use std::net::IpAddr;
+
+use anyhow::Result;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::model::user::UserId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Login,
+    LoginFailed,
+    KeyAdded,
+    KeyRemoved,
+    TokenCreated,
+    SessionRevoked,
+    UserSuspended,
+    UserUnsuspended,
+    AdminGranted,
+    AdminRevoked,
+    UserDeleted,
+    ApplicationCreated,
+    ApplicationSecretReset,
+    ApplicationDeleted,
+    InviteRevoked,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Login => "login",
+            Action::LoginFailed => "login_failed",
+            Action::KeyAdded => "key_added",
+            Action::KeyRemoved => "key_removed",
+            Action::TokenCreated => "token_created",
+            Action::SessionRevoked => "session_revoked",
+            Action::UserSuspended => "user_suspended",
+            Action::UserUnsuspended => "user_unsuspended",
+            Action::AdminGranted => "admin_granted",
+            Action::AdminRevoked => "admin_revoked",
+            Action::UserDeleted => "user_deleted",
+            Action::ApplicationCreated => "application_created",
+            Action::ApplicationSecretReset => "application_secret_reset",
+            Action::ApplicationDeleted => "application_deleted",
+            Action::InviteRevoked => "invite_revoked",
+        }
+    }
+
+    /// A short description for showing in the log.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Action::Login => "logged in",
+            Action::LoginFailed => "failed to log in",
+            Action::KeyAdded => "added an SSH key",
+            Action::KeyRemoved => "removed an SSH key",
+            Action::TokenCreated => "issued an access token",
+            Action::SessionRevoked => "revoked a session",
+            Action::UserSuspended => "suspended an account",
+            Action::UserUnsuspended => "unsuspended an account",
+            Action::AdminGranted => "made an administrator",
+            Action::AdminRevoked => "removed an administrator",
+            Action::UserDeleted => "deleted an account",
+            Action::ApplicationCreated => "registered an application",
+            Action::ApplicationSecretReset => "reset an application secret",
+            Action::ApplicationDeleted => "deleted an application",
+            Action::InviteRevoked => "revoked an invite",
+        }
+    }
+
+    /// The action stored as `value`, or `None` if it is not one this
+    /// version knows of, such as one recorded by a newer version.
+    fn from_db(value: &str) -> Option<Self> {
+        Some(match value {
+            "login" => Action::Login,
+            "login_failed" => Action::LoginFailed,
+            "key_added" => Action::KeyAdded,
+            "key_removed" => Action::KeyRemoved,
+            "token_created" => Action::TokenCreated,
+            "session_revoked" => Action::SessionRevoked,
+            "user_suspended" => Action::UserSuspended,
+            "user_unsuspended" => Action::UserUnsuspended,
+            "admin_granted" => Action::AdminGranted,
+            "admin_revoked" => Action::AdminRevoked,
+            "user_deleted" => Action::UserDeleted,
+            "application_created" => Action::ApplicationCreated,
+            "application_secret_reset" => Action::ApplicationSecretReset,
+            "application_deleted" => Action::ApplicationDeleted,
+            "invite_revoked" => Action::InviteRevoked,
+            _ => return None,
+        })
+    }
+}
+
+/// Something to be recorded in the audit log.
+pub struct Event<'a> {
+    pub action: Action,
+    /// Whoever did it, if they were logged in.
+    pub actor: Option<UserId>,
+    /// The account it concerns.
+    pub user: Option<UserId>,
+    pub ip: Option<IpAddr>,
+    pub detail: &'a str,
+}
+
+impl<'a> Event<'a> {
+    /// Something a user did to their own account.
+    pub fn new(action: Action, user_id: UserId, ip: IpAddr) -> Self {
+        Self {
+            action,
+            actor: Some(user_id),
+            user: Some(user_id),
+            ip: Some(ip),
+            detail: "",
+        }
+    }
+
+    /// Something an administrator did to another account, or to the site
+    /// as a whole when `user` is `None`.
+    pub fn admin(action: Action, admin: UserId, user: Option<UserId>, ip: IpAddr) -> Self {
+        Self {
+            action,
+            actor: Some(admin),
+            user,
+            ip: Some(ip),
+            detail: "",
+        }
+    }
+
+    pub fn detail(mut self, detail: &'a str) -> Self {
+        self.detail = detail;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: i64,
+    pub created_at: OffsetDateTime,
+    /// As stored, since it may not be an `Action` this version knows of.
+    pub action: String,
+    pub actor: Option<String>,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    pub detail: String,
+}
+
+impl Entry {
+    /// A short description for showing in the log, or the stored action
+    /// itself if it is not known.
+    pub fn describe(&self) -> &str {
+        Action::from_db(&self.action).map_or(self.action.as_str(), |action| action.describe())
+    }
+}
+
+/// Append an event to the log. Usernames are looked up as they are now, so
+/// that the entry still reads right after a rename or deletion.
+pub async fn record(db: &PgPool, event: Event<'_>) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO audit_log (action, actor_id, actor, user_id, username, ip, detail)
+         VALUES (
+             $1, $2, (SELECT username FROM users WHERE id = $2),
+             $3, (SELECT username FROM users WHERE id = $3), $4, $5
+         )",
+        event.action.as_str(),
+        event.actor.map(|id| id.0),
+        event.user.map(|id| id.0),
+        event.ip.map(|ip| ip.to_string()),
+        event.detail,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Entries done by or to a user, newest first.
+pub async fn list_for_user(db: &PgPool, user_id: UserId, limit: i64) -> Result<Vec<Entry>> {
+    let records = sqlx::query!(
+        "SELECT id, created_at, action, actor, username, ip, detail FROM audit_log
+         WHERE user_id = $1 OR actor_id = $1
+         ORDER BY id DESC LIMIT $2",
+        user_id.0,
+        limit,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Entry {
+            id: r.id,
+            created_at: r.created_at,
+            action: r.action,
+            actor: r.actor,
+            username: r.username,
+            ip: r.ip,
+            detail: r.detail,
+        })
+        .collect())
+}
+
+/// All entries, newest first.
+pub async fn list(db: &PgPool, limit: i64, offset: i64) -> Result<Vec<Entry>> {
+    let records = sqlx::query!(
+        "SELECT id, created_at, action, actor, username, ip, detail FROM audit_log
+         ORDER BY id DESC LIMIT $1 OFFSET $2",
+        limit,
+        offset,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Entry {
+            id: r.id,
+            created_at: r.created_at,
+            action: r.action,
+            actor: r.actor,
+            username: r.username,
+            ip: r.ip,
+            detail: r.detail,
+        })
+        .collect())
+}
+
+/// Entries after `after`, oldest first, for walking the whole log.
+pub async fn list_after(db: &PgPool, after: i64, limit: i64) -> Result<Vec<Entry>> {
+    let records = sqlx::query!(
+        "SELECT id, created_at, action, actor, username, ip, detail FROM audit_log
+         WHERE id > $1 ORDER BY id LIMIT $2",
+        after,
+        limit,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Entry {
+            id: r.id,
+            created_at: r.created_at,
+            action: r.action,
+            actor: r.actor,
+            username: r.username,
+            ip: r.ip,
+            detail: r.detail,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(action: &str) -> Entry {
+        Entry {
+            id: 1,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            action: action.to_owned(),
+            actor: None,
+            username: None,
+            ip: None,
+            detail: String::new(),
+        }
+    }
+
+    #[test]
+    fn describe_entries() {
+        assert_eq!(
+            entry(Action::KeyRemoved.as_str()).describe(),
+            "removed an SSH key"
+        );
+        assert_eq!(
+            entry("from_a_newer_version").describe(),
+            "from_a_newer_version"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use base64::engine::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+
+use crate::model::user::UserId;
+
+/// What an emailed link lets its holder do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    Verify,
+    Reset,
+}
+
+impl TokenPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            TokenPurpose::Verify => "verify",
+            TokenPurpose::Reset => "reset",
+        }
+    }
+
+    fn lifetime(self) -> Duration {
+        match self {
+            TokenPurpose::Verify => Duration::days(2),
+            TokenPurpose::Reset => Duration::hours(1),
+        }
+    }
+}
+
+/// Issue a token for `email`, replacing any earlier token for the same
+/// purpose so that only the latest link works.
+pub async fn create_token(
+    db: &PgPool,
+    user_id: UserId,
+    purpose: TokenPurpose,
+    email: &str,
+) -> Result<String> {
+    let buf: [u8; 32] = rand::random();
+    let token = BASE64_URL_SAFE_NO_PAD.encode(buf);
+    let expires = OffsetDateTime::now_utc() + purpose.lifetime();
+
+    sqlx::query!(
+        "DELETE FROM email_tokens WHERE user_id = $1 AND purpose = $2",
+        user_id.0,
+        purpose.as_str(),
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO email_tokens (token_hash, user_id, purpose, email, expires)
+         VALUES ($1, $2, $3, $4, $5)",
+        hash_token(&token),
+        user_id.0,
+        purpose.as_str(),
+        email,
+        expires,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Whether a token for `purpose` was issued to the user in the last
+/// `period`. Tokens are not timestamped, but all of them are issued with
+/// the same lifetime, so their expiry tells when they were issued.
+pub async fn issued_within(
+    db: &PgPool,
+    user_id: UserId,
+    purpose: TokenPurpose,
+    period: Duration,
+) -> Result<bool> {
+    let issued_after = OffsetDateTime::now_utc() + purpose.lifetime() - period;
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS (
+             SELECT 1 FROM email_tokens
+             WHERE user_id = $1 AND purpose = $2 AND expires > $3
+         ) AS \"exists!\"",
+        user_id.0,
+        purpose.as_str(),
+        issued_after,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(exists)
+}
+
+pub struct TokenOwner {
+    pub user_id: UserId,
+    pub email: String,
+}
+
+/// Look up an unexpired token without using it up, for pages that show a
+/// form before acting on the token.
+pub async fn peek_token(
+    db: &PgPool,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<TokenOwner>> {
+    let record = sqlx::query!(
+        "SELECT user_id, email FROM email_tokens
+         WHERE token_hash = $1 AND purpose = $2 AND expires > now()",
+        hash_token(token),
+        purpose.as_str(),
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|r| TokenOwner {
+        user_id: UserId(r.user_id),
+        email: r.email,
+    }))
+}
+
+/// Use up a token, returning who it was issued to if it was still valid.
+pub async fn consume_token(
+    db: &PgPool,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<TokenOwner>> {
+    let record = sqlx::query!(
+        "DELETE FROM email_tokens
+         WHERE token_hash = $1 AND purpose = $2
+         RETURNING user_id, email, expires",
+        hash_token(token),
+        purpose.as_str(),
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record
+        .filter(|r| r.expires > OffsetDateTime::now_utc())
+        .map(|r| TokenOwner {
+            user_id: UserId(r.user_id),
+            email: r.email,
+        }))
+}
+
+/// Mark `email` as verified, unless the user has changed address since the
+/// link was sent.
+pub async fn mark_verified(db: &PgPool, user_id: UserId, email: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE users SET email_verified_at = now() WHERE id = $1 AND email = $2",
+        user_id.0,
+        email,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+fn hash_token(token: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
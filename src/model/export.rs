@@ -0,0 +1,171 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::model::user::UserId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pending,
+    Running,
+    Ready,
+    Failed,
+}
+
+impl Status {
+    fn from_str(s: &str) -> Self {
+        match s {
+            "pending" => Status::Pending,
+            "running" => Status::Running,
+            "ready" => Status::Ready,
+            _ => Status::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub id: i64,
+    pub status: Status,
+    pub created_at: OffsetDateTime,
+    pub completed_at: Option<OffsetDateTime>,
+    pub size: Option<i64>,
+}
+
+/// Queue an export for a user. A user has at most one export waiting at a
+/// time, so asking again returns the one already queued.
+pub async fn request(db: &PgPool, user_id: UserId) -> Result<i64> {
+    let pending = sqlx::query_scalar!(
+        "SELECT id FROM exports WHERE user_id = $1 AND status IN ('pending', 'running')",
+        user_id.0,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(id) = pending {
+        return Ok(id);
+    }
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO exports (user_id) VALUES ($1) RETURNING id",
+        user_id.0,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(id)
+}
+
+/// A user's exports, newest first.
+pub async fn list(db: &PgPool, user_id: UserId) -> Result<Vec<Export>> {
+    let records = sqlx::query!(
+        "SELECT id, status, created_at, completed_at, size FROM exports
+         WHERE user_id = $1 ORDER BY id DESC",
+        user_id.0,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Export {
+            id: r.id,
+            status: Status::from_str(&r.status),
+            created_at: r.created_at,
+            completed_at: r.completed_at,
+            size: r.size,
+        })
+        .collect())
+}
+
+/// Whether a user owns a finished export.
+pub async fn is_ready(db: &PgPool, user_id: UserId, id: i64) -> Result<bool> {
+    let ready = sqlx::query_scalar!(
+        "SELECT EXISTS (
+             SELECT 1 FROM exports WHERE id = $1 AND user_id = $2 AND status = 'ready'
+         ) AS \"ready!\"",
+        id,
+        user_id.0,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(ready)
+}
+
+/// Take the oldest pending export to work on, if any. Concurrent callers
+/// never get the same export.
+pub async fn start_next(db: &PgPool) -> Result<Option<(i64, UserId)>> {
+    let record = sqlx::query!(
+        "UPDATE exports SET status = 'running', started_at = now()
+         WHERE id = (
+             SELECT id FROM exports WHERE status = 'pending'
+             ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED
+         )
+         RETURNING id, user_id"
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|r| (r.id, UserId(r.user_id))))
+}
+
+pub async fn finish(db: &PgPool, id: i64, size: i64) -> Result<()> {
+    sqlx::query!(
+        "UPDATE exports SET status = 'ready', completed_at = now(), size = $2 WHERE id = $1",
+        id,
+        size,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn fail(db: &PgPool, id: i64) -> Result<()> {
+    sqlx::query!(
+        "UPDATE exports SET status = 'failed', completed_at = now() WHERE id = $1",
+        id,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Give up on exports that have been running for longer than `timeout`,
+/// such as ones interrupted by a restart.
+pub async fn fail_stale(db: &PgPool, timeout: time::Duration) -> Result<()> {
+    sqlx::query!(
+        "UPDATE exports SET status = 'failed', completed_at = now()
+         WHERE status = 'running' AND started_at < $1",
+        OffsetDateTime::now_utc() - timeout,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Forget exports finished before `before`. Their archives are removed
+/// separately.
+pub async fn delete_finished_before(db: &PgPool, before: OffsetDateTime) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM exports WHERE status IN ('ready', 'failed') AND completed_at < $1",
+        before,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Every export still on record, for finding archives that no longer
+/// belong to one.
+pub async fn all_ids(db: &PgPool) -> Result<Vec<i64>> {
+    let ids = sqlx::query_scalar!("SELECT id FROM exports")
+        .fetch_all(db)
+        .await?;
+
+    Ok(ids)
+}
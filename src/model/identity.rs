@@ -0,0 +1,212 @@
+use anyhow::Result;
+use futures_util::FutureExt;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::db;
+use crate::model::user::UserId;
+
+#[derive(Debug, Clone)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    pub email: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub last_login: Option<OffsetDateTime>,
+}
+
+/// The user an external account is linked to, recording the login.
+pub async fn login(db: &PgPool, provider: &str, subject: &str) -> Result<Option<UserId>> {
+    let user_id = sqlx::query_scalar!(
+        "UPDATE user_identities SET last_login = now()
+         WHERE provider = $1 AND subject = $2
+         RETURNING user_id",
+        provider,
+        subject,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user_id.map(UserId))
+}
+
+/// The user an external account is linked to, without recording a login.
+pub async fn find_user(db: &PgPool, provider: &str, subject: &str) -> Result<Option<UserId>> {
+    let user_id = sqlx::query_scalar!(
+        "SELECT user_id FROM user_identities WHERE provider = $1 AND subject = $2",
+        provider,
+        subject,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user_id.map(UserId))
+}
+
+/// All accounts linked through a provider, as subjects and their users.
+pub async fn list_for_provider(db: &PgPool, provider: &str) -> Result<Vec<(String, UserId)>> {
+    let records = sqlx::query!(
+        "SELECT subject, user_id FROM user_identities WHERE provider = $1",
+        provider,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| (record.subject, UserId(record.user_id)))
+        .collect())
+}
+
+/// Link an external account, unless it is already linked to someone or the
+/// user already has an account at that provider.
+pub async fn link(
+    db: &PgPool,
+    user_id: UserId,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        "INSERT INTO user_identities (provider, subject, user_id, email, last_login)
+         VALUES ($1, $2, $3, $4, now())
+         ON CONFLICT DO NOTHING",
+        provider,
+        subject,
+        user_id.0,
+        email,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn unlink(db: &PgPool, user_id: UserId, provider: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM user_identities WHERE user_id = $1 AND provider = $2",
+        user_id.0,
+        provider,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn list_for_user(db: &PgPool, user_id: UserId) -> Result<Vec<LinkedIdentity>> {
+    let records = sqlx::query!(
+        "SELECT provider, email, created_at, last_login FROM user_identities
+         WHERE user_id = $1 ORDER BY provider",
+        user_id.0,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| LinkedIdentity {
+            provider: record.provider,
+            email: record.email,
+            created_at: record.created_at,
+            last_login: record.last_login,
+        })
+        .collect())
+}
+
+pub struct NewUser {
+    pub username: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub display_name: String,
+    pub provider: String,
+    pub subject: String,
+}
+
+/// Create a user for a first sign-in through a provider. The account has no
+/// password until the user sets one through a password reset. Like with
+/// registration, the first user becomes the administrator.
+pub async fn provision(db: &PgPool, user: NewUser) -> Result<UserId> {
+    db::transaction(db, user, |txn, user| {
+        async move {
+            super::user::serialize_creation(txn).await?;
+
+            let id = sqlx::query_scalar!(
+                "INSERT INTO users (username, email, password_hash, created_at, display_name, biography, email_verified_at, is_admin)
+                 VALUES ($1, $2, '', now(), $3, '', CASE WHEN $4 THEN now() END, NOT EXISTS (SELECT 1 FROM users))
+                 RETURNING id",
+                user.username,
+                user.email,
+                user.display_name,
+                user.email_verified,
+            )
+            .fetch_one(&mut **txn)
+            .await?;
+
+            sqlx::query!(
+                "INSERT INTO user_identities (provider, subject, user_id, email, last_login)
+                 VALUES ($1, $2, $3, $4, now())",
+                user.provider,
+                user.subject,
+                id,
+                user.email,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            Ok(UserId(id))
+        }
+        .boxed()
+    })
+    .await
+}
+
+pub struct PendingLogin {
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub link_user_id: Option<UserId>,
+    pub redirect: Option<String>,
+}
+
+pub async fn create_state(
+    db: &PgPool,
+    state: &str,
+    provider: &str,
+    pending: &PendingLogin,
+) -> Result<()> {
+    let expires = OffsetDateTime::now_utc() + time::Duration::minutes(10);
+
+    sqlx::query!(
+        "INSERT INTO oidc_states (state, provider, nonce, pkce_verifier, link_user_id, redirect, expires)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        state,
+        provider,
+        pending.nonce,
+        pending.pkce_verifier,
+        pending.link_user_id.map(|id| id.0),
+        pending.redirect,
+        expires,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Consume the state of an authorization request, if it is still valid.
+pub async fn take_state(db: &PgPool, state: &str, provider: &str) -> Result<Option<PendingLogin>> {
+    let record = sqlx::query!(
+        "DELETE FROM oidc_states WHERE state = $1 AND provider = $2 AND expires > now()
+         RETURNING nonce, pkce_verifier, link_user_id, redirect",
+        state,
+        provider,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|record| PendingLogin {
+        nonce: record.nonce,
+        pkce_verifier: record.pkce_verifier,
+        link_user_id: record.link_user_id.map(UserId),
+        redirect: record.redirect,
+    }))
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use base64::engine::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+
+use crate::model::user::UserId;
+
+#[derive(Debug, Clone)]
+pub struct Invite {
+    pub id: i32,
+    pub note: String,
+    pub created_by: String,
+    pub created_at: OffsetDateTime,
+    pub expires: OffsetDateTime,
+    pub used_by: Option<String>,
+    pub used_at: Option<OffsetDateTime>,
+}
+
+impl Invite {
+    pub fn is_expired(&self) -> bool {
+        self.used_at.is_none() && self.expires < OffsetDateTime::now_utc()
+    }
+}
+
+/// Issue an invite, returning its code. Only a hash of the code is stored,
+/// so it cannot be shown again.
+pub async fn create(
+    db: &PgPool,
+    created_by: UserId,
+    note: &str,
+    lifetime: Duration,
+) -> Result<String> {
+    let buf: [u8; 16] = rand::random();
+    let code = BASE64_URL_SAFE_NO_PAD.encode(buf);
+    let expires = OffsetDateTime::now_utc() + lifetime;
+
+    sqlx::query!(
+        "INSERT INTO invites (code_hash, note, created_by, expires) VALUES ($1, $2, $3, $4)",
+        hash_code(&code),
+        note,
+        created_by.0,
+        expires,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(code)
+}
+
+/// Invites issued by one user, newest first.
+pub async fn list_by_creator(db: &PgPool, user_id: UserId) -> Result<Vec<Invite>> {
+    let records = sqlx::query!(
+        "SELECT i.id, i.note, c.username AS created_by, i.created_at, i.expires,
+             u.username AS \"used_by?\", i.used_at
+         FROM invites i
+         JOIN users c ON i.created_by = c.id
+         LEFT JOIN users u ON i.used_by = u.id
+         WHERE i.created_by = $1
+         ORDER BY i.id DESC",
+        user_id.0,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Invite {
+            id: r.id,
+            note: r.note,
+            created_by: r.created_by,
+            created_at: r.created_at,
+            expires: r.expires,
+            used_by: r.used_by,
+            used_at: r.used_at,
+        })
+        .collect())
+}
+
+/// Every invite, newest first.
+pub async fn list(db: &PgPool, limit: i64, offset: i64) -> Result<Vec<Invite>> {
+    let records = sqlx::query!(
+        "SELECT i.id, i.note, c.username AS created_by, i.created_at, i.expires,
+             u.username AS \"used_by?\", i.used_at
+         FROM invites i
+         JOIN users c ON i.created_by = c.id
+         LEFT JOIN users u ON i.used_by = u.id
+         ORDER BY i.id DESC
+         LIMIT $1 OFFSET $2",
+        limit,
+        offset,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| Invite {
+            id: r.id,
+            note: r.note,
+            created_by: r.created_by,
+            created_at: r.created_at,
+            expires: r.expires,
+            used_by: r.used_by,
+            used_at: r.used_at,
+        })
+        .collect())
+}
+
+/// Delete an unused invite. With `created_by` set, only an invite issued by
+/// that user is deleted.
+pub async fn revoke(db: &PgPool, id: i32, created_by: Option<UserId>) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM invites
+         WHERE id = $1 AND used_at IS NULL AND ($2::integer IS NULL OR created_by = $2)",
+        id,
+        created_by.map(|user_id| user_id.0),
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether `code` belongs to an unused, unexpired invite.
+pub async fn is_valid(db: &PgPool, code: &str) -> Result<bool> {
+    let valid = sqlx::query_scalar!(
+        "SELECT EXISTS (
+             SELECT 1 FROM invites
+             WHERE code_hash = $1 AND used_at IS NULL AND expires > now()
+         ) AS \"valid!\"",
+        hash_code(code),
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(valid)
+}
+
+pub(super) fn hash_code(code: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code.as_bytes()))
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use sqlx::PgPool;
+
+/// Messages are dropped after this many failed delivery attempts.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+pub struct QueuedMail {
+    pub id: i64,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub attempts: i32,
+}
+
+pub async fn enqueue(db: &PgPool, recipient: &str, subject: &str, body: &str) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO mail_queue (recipient, subject, body) VALUES ($1, $2, $3)",
+        recipient,
+        subject,
+        body,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Claim the next message that is due. Claiming schedules the retry with
+/// exponential backoff up front, so that concurrent drains skip it while it
+/// is being delivered and a crash mid-delivery does not lose it.
+pub async fn claim_next(db: &PgPool) -> Result<Option<QueuedMail>> {
+    let mail = sqlx::query_as!(
+        QueuedMail,
+        "UPDATE mail_queue
+         SET attempts = attempts + 1,
+             next_attempt = now() + interval '2 minutes' * power(2, attempts)
+         WHERE id = (
+             SELECT id FROM mail_queue
+             WHERE next_attempt <= now() AND attempts < $1
+             ORDER BY next_attempt
+             LIMIT 1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING id, recipient, subject, body, attempts",
+        MAX_ATTEMPTS,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(mail)
+}
+
+pub async fn mark_sent(db: &PgPool, id: i64) -> Result<()> {
+    sqlx::query!("DELETE FROM mail_queue WHERE id = $1", id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn mark_failed(db: &PgPool, id: i64, error: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE mail_queue SET last_error = $1 WHERE id = $2",
+        error,
+        id,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Forget messages that exhausted their attempts a week ago, keeping them
+/// around long enough to diagnose why.
+pub async fn delete_abandoned(db: &PgPool) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM mail_queue
+         WHERE attempts >= $1 AND next_attempt < now() - interval '7 days'",
+        MAX_ATTEMPTS,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
@@ -1,4 +1,12 @@
+pub mod audit;
+pub mod email;
+pub mod export;
+pub mod identity;
+pub mod invite;
 pub mod lfs;
+pub mod mail;
+pub mod oauth;
 pub mod paste;
 pub mod session;
+pub mod two_factor;
 pub mod user;
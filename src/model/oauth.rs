@@ -0,0 +1,490 @@
+use anyhow::Result;
+use base64::engine::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+use futures_util::FutureExt;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::model::user::UserId;
+use crate::{db, utils};
+
+const CODE_TTL: time::Duration = time::Duration::minutes(10);
+const ACCESS_TOKEN_TTL: time::Duration = time::Duration::hours(1);
+const REFRESH_TOKEN_TTL: time::Duration = time::Duration::days(30);
+
+/// What an application may do with an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Profile,
+    Email,
+    Keys,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Profile, Scope::Email, Scope::Keys];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Profile => "profile",
+            Scope::Email => "email",
+            Scope::Keys => "keys",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Scope::Profile => "See your username and display name",
+            Scope::Email => "See your email address",
+            Scope::Keys => "See your SSH public keys",
+        }
+    }
+
+    fn from_str(scope: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.as_str() == scope)
+    }
+}
+
+/// Parse a space separated list of scopes, as found in requests. Unknown
+/// scopes make the whole list invalid.
+pub fn parse_scopes(scopes: &str) -> Option<Vec<Scope>> {
+    let mut parsed = scopes
+        .split_whitespace()
+        .map(Scope::from_str)
+        .collect::<Option<Vec<_>>>()?;
+
+    parsed.sort();
+    parsed.dedup();
+    Some(parsed)
+}
+
+pub fn format_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn scope_names(scopes: &[Scope]) -> Vec<String> {
+    scopes
+        .iter()
+        .map(|scope| scope.as_str().to_owned())
+        .collect()
+}
+
+/// Scopes stored before a scope was removed are dropped rather than
+/// rejected.
+fn scopes_from_names(names: Vec<String>) -> Vec<Scope> {
+    let mut scopes: Vec<_> = names
+        .iter()
+        .filter_map(|name| Scope::from_str(name))
+        .collect();
+    scopes.sort();
+    scopes
+}
+
+#[derive(Debug, Clone)]
+pub struct App {
+    pub id: i32,
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub created_by: Option<String>,
+    pub created_at: OffsetDateTime,
+    client_secret_hash: Option<String>,
+}
+
+impl App {
+    /// Confidential clients authenticate with a secret, public ones only
+    /// with PKCE.
+    pub fn is_confidential(&self) -> bool {
+        self.client_secret_hash.is_some()
+    }
+
+    pub fn check_secret(&self, secret: &str) -> bool {
+        self.client_secret_hash
+            .as_deref()
+            .is_some_and(|hash| utils::constant_time_eq(hash, &hash_secret(secret)))
+    }
+}
+
+/// Register an application, returning its client ID and, for confidential
+/// clients, its secret. The secret is only stored hashed.
+pub async fn create_app(
+    db: &PgPool,
+    name: &str,
+    redirect_uris: &[String],
+    confidential: bool,
+    created_by: UserId,
+) -> Result<(String, Option<String>)> {
+    let buf: [u8; 16] = rand::random();
+    let client_id = BASE64_URL_SAFE_NO_PAD.encode(buf);
+    let secret = confidential.then(random_token);
+
+    sqlx::query!(
+        "INSERT INTO oauth_apps (client_id, client_secret_hash, name, redirect_uris, created_by)
+         VALUES ($1, $2, $3, $4, $5)",
+        client_id,
+        secret.as_deref().map(hash_secret),
+        name,
+        redirect_uris,
+        created_by.0,
+    )
+    .execute(db)
+    .await?;
+
+    Ok((client_id, secret))
+}
+
+pub async fn list_apps(db: &PgPool) -> Result<Vec<App>> {
+    let records = sqlx::query!(
+        "SELECT a.id, a.client_id, a.client_secret_hash, a.name, a.redirect_uris, a.created_at,
+             u.username AS \"created_by?\"
+         FROM oauth_apps a
+         LEFT JOIN users u ON a.created_by = u.id
+         ORDER BY a.name"
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| App {
+            id: record.id,
+            client_id: record.client_id,
+            name: record.name,
+            redirect_uris: record.redirect_uris,
+            created_by: record.created_by,
+            created_at: record.created_at,
+            client_secret_hash: record.client_secret_hash,
+        })
+        .collect())
+}
+
+pub async fn get_app(db: &PgPool, client_id: &str) -> Result<Option<App>> {
+    let record = sqlx::query!(
+        "SELECT a.id, a.client_id, a.client_secret_hash, a.name, a.redirect_uris, a.created_at,
+             u.username AS \"created_by?\"
+         FROM oauth_apps a
+         LEFT JOIN users u ON a.created_by = u.id
+         WHERE a.client_id = $1",
+        client_id,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|record| App {
+        id: record.id,
+        client_id: record.client_id,
+        name: record.name,
+        redirect_uris: record.redirect_uris,
+        created_by: record.created_by,
+        created_at: record.created_at,
+        client_secret_hash: record.client_secret_hash,
+    }))
+}
+
+/// Replace the secret of a confidential client, returning the new one.
+pub async fn reset_secret(db: &PgPool, app_id: i32) -> Result<Option<String>> {
+    let secret = random_token();
+
+    let result = sqlx::query!(
+        "UPDATE oauth_apps SET client_secret_hash = $1
+         WHERE id = $2 AND client_secret_hash IS NOT NULL",
+        hash_secret(&secret),
+        app_id,
+    )
+    .execute(db)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(secret))
+}
+
+/// Delete an application along with every grant and token issued to it.
+pub async fn delete_app(db: &PgPool, app_id: i32) -> Result<()> {
+    sqlx::query!("DELETE FROM oauth_apps WHERE id = $1", app_id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct Authorization {
+    pub app_id: i32,
+    pub app_name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: OffsetDateTime,
+    pub last_used: Option<OffsetDateTime>,
+}
+
+/// The scopes a user has already granted an application.
+pub async fn granted_scopes(db: &PgPool, user_id: UserId, app_id: i32) -> Result<Vec<Scope>> {
+    let scopes = sqlx::query_scalar!(
+        "SELECT scopes FROM oauth_authorizations WHERE user_id = $1 AND app_id = $2",
+        user_id.0,
+        app_id,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(scopes.map(scopes_from_names).unwrap_or_default())
+}
+
+/// Record the consent of a user, adding to any scopes granted before.
+pub async fn authorize(db: &PgPool, user_id: UserId, app_id: i32, scopes: &[Scope]) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO oauth_authorizations (user_id, app_id, scopes) VALUES ($1, $2, $3)
+         ON CONFLICT (user_id, app_id) DO UPDATE SET scopes = ARRAY(
+             SELECT DISTINCT unnest(oauth_authorizations.scopes || EXCLUDED.scopes)
+         )",
+        user_id.0,
+        app_id,
+        &scope_names(scopes),
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn list_authorizations(db: &PgPool, user_id: UserId) -> Result<Vec<Authorization>> {
+    let records = sqlx::query!(
+        "SELECT a.app_id, p.name, a.scopes, a.created_at,
+             (SELECT max(t.last_used) FROM oauth_tokens t
+              WHERE t.user_id = a.user_id AND t.app_id = a.app_id) AS last_used
+         FROM oauth_authorizations a
+         JOIN oauth_apps p ON a.app_id = p.id
+         WHERE a.user_id = $1
+         ORDER BY p.name",
+        user_id.0,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| Authorization {
+            app_id: record.app_id,
+            app_name: record.name,
+            scopes: scopes_from_names(record.scopes),
+            created_at: record.created_at,
+            last_used: record.last_used,
+        })
+        .collect())
+}
+
+/// Withdraw the consent of a user, invalidating everything the application
+/// was issued on their behalf.
+pub async fn revoke(db: &PgPool, user_id: UserId, app_id: i32) -> Result<()> {
+    db::transaction(db, (), |txn, ()| {
+        async move {
+            sqlx::query!(
+                "DELETE FROM oauth_authorizations WHERE user_id = $1 AND app_id = $2",
+                user_id.0,
+                app_id,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            sqlx::query!(
+                "DELETE FROM oauth_codes WHERE user_id = $1 AND app_id = $2",
+                user_id.0,
+                app_id,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            sqlx::query!(
+                "DELETE FROM oauth_tokens WHERE user_id = $1 AND app_id = $2",
+                user_id.0,
+                app_id,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+/// What an authorization code stands for until it is exchanged.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub app_id: i32,
+    pub user_id: UserId,
+    pub redirect_uri: String,
+    pub scopes: Vec<Scope>,
+    pub code_challenge: String,
+}
+
+pub async fn create_code(db: &PgPool, grant: &Grant) -> Result<String> {
+    let code = random_token();
+    let expires = OffsetDateTime::now_utc() + CODE_TTL;
+
+    sqlx::query!(
+        "INSERT INTO oauth_codes (code_hash, app_id, user_id, redirect_uri, scopes, code_challenge, expires)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        hash_secret(&code),
+        grant.app_id,
+        grant.user_id.0,
+        grant.redirect_uri,
+        &scope_names(&grant.scopes),
+        grant.code_challenge,
+        expires,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(code)
+}
+
+/// Consume an authorization code issued to an application. A code can only
+/// be exchanged once, whether or not the exchange goes on to succeed.
+pub async fn take_code(db: &PgPool, code: &str, app_id: i32) -> Result<Option<Grant>> {
+    let record = sqlx::query!(
+        "DELETE FROM oauth_codes WHERE code_hash = $1 AND app_id = $2 AND expires > now()
+         RETURNING user_id, redirect_uri, scopes, code_challenge",
+        hash_secret(code),
+        app_id,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|record| Grant {
+        app_id,
+        user_id: UserId(record.user_id),
+        redirect_uri: record.redirect_uri,
+        scopes: scopes_from_names(record.scopes),
+        code_challenge: record.code_challenge,
+    }))
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+    pub scopes: Vec<Scope>,
+}
+
+pub async fn issue_tokens(
+    db: &PgPool,
+    app_id: i32,
+    user_id: UserId,
+    scopes: &[Scope],
+) -> Result<TokenPair> {
+    let (pair, hashes) = new_token_pair(scopes);
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        "INSERT INTO oauth_tokens (access_token_hash, refresh_token_hash, app_id, user_id, scopes, expires, refresh_expires)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        hashes.0,
+        hashes.1,
+        app_id,
+        user_id.0,
+        &scope_names(scopes),
+        now + ACCESS_TOKEN_TTL,
+        now + REFRESH_TOKEN_TTL,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(pair)
+}
+
+/// Exchange a refresh token for a new pair of tokens. The old pair stops
+/// working straight away.
+pub async fn refresh(db: &PgPool, refresh_token: &str, app_id: i32) -> Result<Option<TokenPair>> {
+    let refresh_hash = hash_secret(refresh_token);
+
+    db::transaction(db, refresh_hash, |txn, refresh_hash| {
+        async move {
+            let Some(record) = sqlx::query!(
+                "DELETE FROM oauth_tokens
+                 WHERE refresh_token_hash = $1 AND app_id = $2 AND refresh_expires > now()
+                 RETURNING user_id, scopes",
+                refresh_hash,
+                app_id,
+            )
+            .fetch_optional(&mut **txn)
+            .await?
+            else {
+                return Ok(None);
+            };
+
+            let scopes = scopes_from_names(record.scopes);
+            let (pair, hashes) = new_token_pair(&scopes);
+            let now = OffsetDateTime::now_utc();
+
+            sqlx::query!(
+                "INSERT INTO oauth_tokens (access_token_hash, refresh_token_hash, app_id, user_id, scopes, expires, refresh_expires)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                hashes.0,
+                hashes.1,
+                app_id,
+                record.user_id,
+                &scope_names(&scopes),
+                now + ACCESS_TOKEN_TTL,
+                now + REFRESH_TOKEN_TTL,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            Ok(Some(pair))
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessToken {
+    pub user_id: UserId,
+    pub scopes: Vec<Scope>,
+}
+
+/// Look up a valid access token, recording that it was used.
+pub async fn use_access_token(db: &PgPool, token: &str) -> Result<Option<AccessToken>> {
+    let record = sqlx::query!(
+        "UPDATE oauth_tokens SET last_used = now()
+         WHERE access_token_hash = $1 AND expires > now()
+         RETURNING user_id, scopes",
+        hash_secret(token),
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|record| AccessToken {
+        user_id: UserId(record.user_id),
+        scopes: scopes_from_names(record.scopes),
+    }))
+}
+
+fn new_token_pair(scopes: &[Scope]) -> (TokenPair, (String, String)) {
+    let access_token = random_token();
+    let refresh_token = random_token();
+    let hashes = (hash_secret(&access_token), hash_secret(&refresh_token));
+
+    let pair = TokenPair {
+        access_token,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_TTL.whole_seconds(),
+        scopes: scopes.to_vec(),
+    };
+
+    (pair, hashes)
+}
+
+fn random_token() -> String {
+    let buf: [u8; 32] = rand::random();
+    BASE64_URL_SAFE_NO_PAD.encode(buf)
+}
+
+fn hash_secret(secret: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(secret.as_bytes()))
+}
@@ -1,23 +1,54 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use base64::engine::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
 use futures_util::FutureExt;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use sqlx::PgPool;
+use time::OffsetDateTime;
+use tokio::task;
 
 use crate::model::user::UserId;
 use crate::{db, utils};
 
 pub struct Paste {
     pub id: String,
+    pub user_id: UserId,
     pub visibility: Visibility,
+    pub password_hash: Option<String>,
+    pub forked_from: Option<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Visibility {
     Public,
     Unlisted,
     Private,
 }
 
+impl Visibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        match value {
+            "public" => Visibility::Public,
+            "unlisted" => Visibility::Unlisted,
+            "private" => Visibility::Private,
+            _ => unreachable!("invalid paste visibility in database: {}", value),
+        }
+    }
+}
+
 pub struct File {
-    pub paste_id: i32,
+    pub paste_id: String,
     pub filename: String,
     pub content: String,
 }
@@ -26,24 +57,17 @@ pub struct PasteInfo {
     pub id: String,
     pub visibility: String,
     pub filename: String,
-}
-
-pub struct PasteWithFile {
-    pub id: String,
-    pub user_id: UserId,
-    pub visibility: String,
-    pub filename: String,
-    pub content: String,
+    pub protected: bool,
 }
 
 pub async fn get_user_pastes(db: &PgPool, user_id: UserId) -> Result<Vec<PasteInfo>> {
     let pastes = sqlx::query_as!(
         PasteInfo,
-        "SELECT p.id, p.visibility, pf.filename
+        r#"SELECT p.id, p.visibility, pf.filename, p.password_hash IS NOT NULL AS "protected!"
          FROM pastes p
          JOIN paste_files pf ON p.id = pf.paste_id
          WHERE p.user_id = $1
-         ORDER BY p.id DESC",
+         ORDER BY p.id DESC"#,
         user_id.0
     )
     .fetch_all(db)
@@ -52,26 +76,175 @@ pub async fn get_user_pastes(db: &PgPool, user_id: UserId) -> Result<Vec<PasteIn
     Ok(pastes)
 }
 
-pub async fn get_paste(db: &PgPool, paste_id: &str) -> Result<Option<PasteWithFile>> {
+/// Every file of every paste owned by a user.
+pub async fn get_user_files(db: &PgPool, user_id: UserId) -> Result<Vec<File>> {
+    let files = sqlx::query_as!(
+        File,
+        "SELECT pf.paste_id, pf.filename, pf.content
+         FROM paste_files pf
+         JOIN pastes p ON p.id = pf.paste_id
+         WHERE p.user_id = $1
+         ORDER BY pf.paste_id, pf.filename",
+        user_id.0
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(files)
+}
+
+pub async fn get_paste(db: &PgPool, paste_id: &str) -> Result<Option<Paste>> {
     let record = sqlx::query!(
-        "SELECT p.id, p.user_id, p.visibility, pf.filename, pf.content
-         FROM pastes p
-         JOIN paste_files pf ON p.id = pf.paste_id
-         WHERE p.id = $1",
+        "SELECT id, user_id, visibility, password_hash, forked_from FROM pastes WHERE id = $1",
         paste_id
     )
     .fetch_optional(db)
     .await?;
 
-    Ok(record.map(|r| PasteWithFile {
+    Ok(record.map(|r| Paste {
         id: r.id,
         user_id: UserId(r.user_id),
-        visibility: r.visibility,
-        filename: r.filename,
-        content: r.content,
+        visibility: Visibility::from_db(&r.visibility),
+        password_hash: r.password_hash,
+        forked_from: r.forked_from,
     }))
 }
 
+pub async fn get_paste_files(db: &PgPool, paste_id: &str) -> Result<Vec<File>> {
+    let files = sqlx::query_as!(
+        File,
+        "SELECT paste_id, filename, content
+         FROM paste_files
+         WHERE paste_id = $1
+         ORDER BY filename",
+        paste_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(files)
+}
+
+pub async fn get_paste_file(db: &PgPool, paste_id: &str, filename: &str) -> Result<Option<File>> {
+    let file = sqlx::query_as!(
+        File,
+        "SELECT paste_id, filename, content
+         FROM paste_files
+         WHERE paste_id = $1 AND filename = $2",
+        paste_id,
+        filename
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(file)
+}
+
+pub struct PasteListing {
+    pub id: String,
+    pub username: String,
+    pub visibility: String,
+    pub created_at: OffsetDateTime,
+    pub filename: String,
+    pub file_count: i64,
+}
+
+/// List the most recent public pastes, newest first.
+pub async fn list_public(db: &PgPool, limit: i64, offset: i64) -> Result<Vec<PasteListing>> {
+    let pastes = sqlx::query_as!(
+        PasteListing,
+        r#"
+        SELECT p.id, u.username, p.visibility, p.created_at,
+               min(pf.filename) AS "filename!", count(*) AS "file_count!"
+        FROM pastes p
+        JOIN users u ON p.user_id = u.id
+        JOIN paste_files pf ON p.id = pf.paste_id
+        WHERE p.visibility = 'public' AND p.password_hash IS NULL
+        GROUP BY p.id, u.username
+        ORDER BY p.created_at DESC, p.id
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(pastes)
+}
+
+pub struct PasteSize {
+    pub id: String,
+    pub username: String,
+    pub visibility: String,
+    pub created_at: OffsetDateTime,
+    pub file_count: i64,
+    pub size: i64,
+}
+
+/// Every paste regardless of visibility, largest first.
+pub async fn list_by_size(db: &PgPool, limit: i64, offset: i64) -> Result<Vec<PasteSize>> {
+    let pastes = sqlx::query_as!(
+        PasteSize,
+        r#"
+        SELECT p.id, u.username, p.visibility, p.created_at,
+               count(*) AS "file_count!",
+               sum(octet_length(pf.content))::bigint AS "size!"
+        FROM pastes p
+        JOIN users u ON p.user_id = u.id
+        JOIN paste_files pf ON p.id = pf.paste_id
+        GROUP BY p.id, u.username
+        ORDER BY 6 DESC, p.id
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(pastes)
+}
+
+/// Full-text search over filenames and contents. Public pastes match for
+/// everyone, unlisted, private and password-protected pastes only for their
+/// owner.
+pub async fn search(
+    db: &PgPool,
+    viewer: Option<UserId>,
+    query: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PasteListing>> {
+    let pastes = sqlx::query_as!(
+        PasteListing,
+        r#"
+        SELECT p.id, u.username, p.visibility, p.created_at,
+               min(pf.filename) AS "filename!", count(*) AS "file_count!"
+        FROM pastes p
+        JOIN users u ON p.user_id = u.id
+        JOIN paste_files pf ON p.id = pf.paste_id
+        WHERE ((p.visibility = 'public' AND p.password_hash IS NULL) OR p.user_id = $1)
+          AND p.id IN (
+              SELECT paste_id FROM paste_files
+              WHERE search @@ websearch_to_tsquery('english', $2)
+          )
+        GROUP BY p.id, u.username
+        ORDER BY max(ts_rank(pf.search, websearch_to_tsquery('english', $2))) DESC,
+                 p.created_at DESC, p.id
+        LIMIT $3 OFFSET $4
+        "#,
+        viewer.map(|id| id.0),
+        query,
+        limit,
+        offset
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(pastes)
+}
+
 pub async fn delete_paste(db: &PgPool, user_id: UserId, paste_id: &str) -> Result<()> {
     sqlx::query!(
         "DELETE FROM pastes WHERE id = $1 AND user_id = $2",
@@ -90,25 +263,27 @@ pub async fn create_paste(
     visibility: Visibility,
     filename: String,
     content: String,
+    password: Option<String>,
 ) -> Result<String> {
-    let visibility = match visibility {
-        Visibility::Public => "public",
-        Visibility::Unlisted => "unlisted",
-        Visibility::Private => "private",
+    let visibility = visibility.as_str();
+    let password_hash = match password {
+        Some(password) => Some(hash_password(password).await?),
+        None => None,
     };
 
     let id = db::transaction(
         db,
-        (visibility, filename, content),
-        |txn, (visibility, filename, content)| {
+        (visibility, filename, content, password_hash),
+        |txn, (visibility, filename, content, password_hash)| {
             async move {
                 let id = utils::unique_string(txn, "pastes", "id", 4).await;
 
                 sqlx::query!(
-                    "INSERT INTO pastes (id, user_id, visibility) VALUES ($1, $2, $3)",
+                    "INSERT INTO pastes (id, user_id, visibility, password_hash) VALUES ($1, $2, $3, $4)",
                     id,
                     user_id.0,
-                    visibility
+                    visibility,
+                    password_hash.as_deref()
                 )
                 .execute(&mut **txn)
                 .await?;
@@ -131,3 +306,172 @@ pub async fn create_paste(
 
     Ok(id)
 }
+
+/// Total number of content bytes stored across all pastes owned by a user.
+pub async fn storage_used(db: &PgPool, user_id: UserId) -> Result<i64> {
+    let used = sqlx::query_scalar!(
+        r#"SELECT coalesce(sum(octet_length(f.content)), 0)::bigint AS "used!"
+           FROM paste_files f
+           JOIN pastes p ON f.paste_id = p.id
+           WHERE p.user_id = $1"#,
+        user_id.0
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(used)
+}
+
+/// Number of content bytes stored in a single paste.
+pub async fn paste_size(db: &PgPool, paste_id: &str) -> Result<i64> {
+    let size = sqlx::query_scalar!(
+        r#"SELECT coalesce(sum(octet_length(content)), 0)::bigint AS "size!"
+           FROM paste_files
+           WHERE paste_id = $1"#,
+        paste_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(size)
+}
+
+/// Copy a paste and all of its files into a new paste owned by `user_id`,
+/// recording where it was forked from. Visibility carries over so that
+/// forking never exposes content more widely than the original. The password
+/// is the original owner's, so forks of protected pastes are made private
+/// instead.
+pub async fn fork_paste(db: &PgPool, user_id: UserId, source_id: &str) -> Result<String> {
+    let id = db::transaction(db, source_id.to_owned(), |txn, source_id| {
+        async move {
+            let id = utils::unique_string(txn, "pastes", "id", 4).await;
+
+            sqlx::query!(
+                "INSERT INTO pastes (id, user_id, visibility, forked_from)
+                 SELECT $1, $2, CASE WHEN password_hash IS NULL THEN visibility ELSE 'private' END, id
+                 FROM pastes WHERE id = $3",
+                id,
+                user_id.0,
+                source_id
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            sqlx::query!(
+                "INSERT INTO paste_files (paste_id, filename, content)
+                 SELECT $1, filename, content FROM paste_files WHERE paste_id = $2",
+                id,
+                source_id
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            Ok(id)
+        }
+        .boxed()
+    })
+    .await?;
+
+    Ok(id)
+}
+
+pub struct ForkInfo {
+    pub id: String,
+    pub username: String,
+    pub created_at: OffsetDateTime,
+}
+
+pub async fn get_fork_info(db: &PgPool, paste_id: &str) -> Result<Option<ForkInfo>> {
+    let info = sqlx::query_as!(
+        ForkInfo,
+        "SELECT p.id, u.username, p.created_at
+         FROM pastes p
+         JOIN users u ON p.user_id = u.id
+         WHERE p.id = $1",
+        paste_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(info)
+}
+
+/// Count the forks of a paste that the viewer may know about, as listed by
+/// `get_forks`.
+pub async fn count_forks(db: &PgPool, paste_id: &str, viewer: Option<UserId>) -> Result<i64> {
+    let count = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM pastes
+           WHERE forked_from = $1 AND (visibility = 'public' OR user_id = $2)"#,
+        paste_id,
+        viewer.map(|id| id.0)
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(count)
+}
+
+/// List the forks of a paste that the viewer may know about: public forks and
+/// the viewer's own.
+pub async fn get_forks(
+    db: &PgPool,
+    paste_id: &str,
+    viewer: Option<UserId>,
+) -> Result<Vec<ForkInfo>> {
+    let forks = sqlx::query_as!(
+        ForkInfo,
+        "SELECT p.id, u.username, p.created_at
+         FROM pastes p
+         JOIN users u ON p.user_id = u.id
+         WHERE p.forked_from = $1 AND (p.visibility = 'public' OR p.user_id = $2)
+         ORDER BY p.created_at DESC",
+        paste_id,
+        viewer.map(|id| id.0)
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(forks)
+}
+
+async fn hash_password(password: String) -> Result<String> {
+    task::spawn_blocking(move || {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+            .map_err(|err| anyhow!("failed to encode salt: {}", err))?;
+
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|err| anyhow!("failed to hash password: {}", err))?;
+
+        Ok(hash.to_string())
+    })
+    .await?
+}
+
+/// Check a password against a paste's argon2 hash. Hashing is slow on purpose,
+/// so it runs on the blocking pool.
+pub async fn verify_password(password_hash: &str, password: &str) -> Result<bool> {
+    let password_hash = password_hash.to_owned();
+    let password = password.to_owned();
+
+    task::spawn_blocking(move || {
+        let parsed = PasswordHash::new(&password_hash)
+            .map_err(|err| anyhow!("invalid password hash: {}", err))?;
+
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok())
+    })
+    .await?
+}
+
+/// Value of the cookie that unlocks a password-protected paste. It is derived
+/// from the stored hash, so changing the password invalidates old cookies,
+/// and keyed with the server secret, so that it cannot be computed from a
+/// leaked hash.
+pub fn unlock_token(secret: &str, password_hash: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(password_hash.as_bytes());
+    BASE64_URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
@@ -1,9 +1,13 @@
+use std::net::IpAddr;
+
 use anyhow::Result;
 use base64::engine::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use futures_util::FutureExt;
 use sqlx::PgPool;
 use time::OffsetDateTime;
 
+use crate::db;
 use crate::model::user::UserId;
 
 #[derive(Debug, Clone)]
@@ -13,16 +17,23 @@ pub struct Session {
     pub expires: OffsetDateTime,
 }
 
-pub async fn create(db: &PgPool, user_id: UserId) -> Result<Session> {
+pub async fn create(
+    db: &PgPool,
+    user_id: UserId,
+    ip: IpAddr,
+    user_agent: Option<&str>,
+) -> Result<Session> {
     let buf: [u8; 16] = rand::random();
     let token = BASE64_STANDARD.encode(buf);
     let expires = OffsetDateTime::now_utc() + time::Duration::days(30);
 
     sqlx::query!(
-        "INSERT INTO sessions (token, user_id, expires) VALUES ($1, $2, $3)",
+        "INSERT INTO sessions (token, user_id, expires, ip, user_agent) VALUES ($1, $2, $3, $4, $5)",
         token,
         user_id.0,
-        expires
+        expires,
+        ip.to_string(),
+        user_agent,
     )
     .execute(db)
     .await?;
@@ -55,17 +66,25 @@ pub async fn get_by_token(db: &PgPool, token: &str) -> Result<Option<Session>> {
 
 #[derive(Debug, Clone)]
 pub struct SessionWithUser {
+    pub id: i64,
     pub token: String,
     pub user_id: UserId,
     pub username: String,
     pub expires: OffsetDateTime,
+    pub last_seen: OffsetDateTime,
+    pub two_factor: bool,
+    pub is_admin: bool,
 }
 
 /// Get session with user data in a single query
 pub async fn get_by_token_with_user(db: &PgPool, token: &str) -> Result<Option<SessionWithUser>> {
     let record = sqlx::query!(
         r#"
-        SELECT s.user_id, s.expires, u.username
+        SELECT s.id, s.user_id, s.expires, s.last_seen, u.username, u.is_admin,
+            EXISTS(
+                SELECT 1 FROM user_totp t
+                WHERE t.user_id = s.user_id AND t.confirmed_at IS NOT NULL
+            ) AS "two_factor!"
         FROM sessions s
         JOIN users u ON s.user_id = u.id
         WHERE s.token = $1
@@ -77,12 +96,110 @@ pub async fn get_by_token_with_user(db: &PgPool, token: &str) -> Result<Option<S
 
     if let Some(record) = record {
         Ok(Some(SessionWithUser {
+            id: record.id,
             token: token.to_owned(),
             user_id: UserId(record.user_id),
             username: record.username,
             expires: record.expires,
+            last_seen: record.last_seen,
+            two_factor: record.two_factor,
+            is_admin: record.is_admin,
         }))
     } else {
         Ok(None)
     }
 }
+
+/// Record activity on a session. Called on every request, so the row is
+/// only written once the previous timestamp is a few minutes old.
+pub async fn touch(db: &PgPool, session: &SessionWithUser, ip: IpAddr) -> Result<()> {
+    if OffsetDateTime::now_utc() - session.last_seen < time::Duration::minutes(5) {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "UPDATE sessions SET last_seen = now(), ip = $1 WHERE id = $2",
+        ip.to_string(),
+        session.id,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: i64,
+    pub created_at: OffsetDateTime,
+    pub last_seen: OffsetDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub async fn list_for_user(db: &PgPool, user_id: UserId) -> Result<Vec<SessionInfo>> {
+    let sessions = sqlx::query_as!(
+        SessionInfo,
+        "SELECT id, created_at, last_seen, ip, user_agent FROM sessions
+         WHERE user_id = $1 AND expires > now()
+         ORDER BY last_seen DESC",
+        user_id.0,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// End the session behind a cookie, as on logout.
+pub async fn delete(db: &PgPool, token: &str) -> Result<()> {
+    sqlx::query!("DELETE FROM sessions WHERE token = $1", token)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Revoke one of a user's sessions. Outstanding LFS tokens are revoked as
+/// well, since they may have been handed out to whoever held the session.
+pub async fn revoke(db: &PgPool, user_id: UserId, session_id: i64) -> Result<()> {
+    db::transaction(db, (), |txn, _| {
+        async move {
+            sqlx::query!(
+                "DELETE FROM sessions WHERE id = $1 AND user_id = $2",
+                session_id,
+                user_id.0,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            sqlx::query!("DELETE FROM lfs_tokens WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+/// Log a user out everywhere, such as after their password is reset, along
+/// with their outstanding LFS tokens.
+pub async fn delete_for_user(db: &PgPool, user_id: UserId) -> Result<()> {
+    db::transaction(db, (), |txn, _| {
+        async move {
+            sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            sqlx::query!("DELETE FROM lfs_tokens WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
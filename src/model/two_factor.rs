@@ -0,0 +1,291 @@
+use anyhow::{Result, anyhow};
+use base64::engine::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+use futures_util::FutureExt;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::model::user::UserId;
+use crate::{db, utils};
+
+const ISSUER: &str = "Conduit";
+const STEP_SECS: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const CHALLENGE_MAX_ATTEMPTS: i32 = 5;
+
+pub struct TotpState {
+    pub secret: String,
+    pub confirmed: bool,
+}
+
+impl TotpState {
+    /// The authenticator configuration, for display as a QR code or URI.
+    pub fn totp(&self, username: &str) -> Result<TOTP> {
+        let secret = Secret::Encoded(self.secret.clone())
+            .to_bytes()
+            .map_err(|err| anyhow!("invalid TOTP secret: {:?}", err))?;
+
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            STEP_SECS,
+            secret,
+            Some(ISSUER.to_owned()),
+            username.to_owned(),
+        )?;
+
+        Ok(totp)
+    }
+
+    /// The time step `code` is valid for, allowing one step of clock skew.
+    fn matching_step(&self, code: &str) -> Result<Option<i64>> {
+        // The account name is not part of the code, so any will do.
+        let totp = self.totp("user")?;
+        let now = OffsetDateTime::now_utc().unix_timestamp() as u64 / STEP_SECS;
+
+        Ok((now.saturating_sub(1)..=now + 1)
+            .find(|step| utils::constant_time_eq(&totp.generate(step * STEP_SECS), code))
+            .map(|step| step as i64))
+    }
+}
+
+pub async fn get_totp(db: &PgPool, user_id: UserId) -> Result<Option<TotpState>> {
+    let record = sqlx::query!(
+        "SELECT secret, confirmed_at FROM user_totp WHERE user_id = $1",
+        user_id.0,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|r| TotpState {
+        secret: r.secret,
+        confirmed: r.confirmed_at.is_some(),
+    }))
+}
+
+pub async fn is_enabled(db: &PgPool, user_id: UserId) -> Result<bool> {
+    let enabled = sqlx::query_scalar!(
+        r#"SELECT EXISTS(
+             SELECT 1 FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL
+           ) AS "enabled!""#,
+        user_id.0,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(enabled)
+}
+
+/// Start enrolment with a fresh secret, replacing any unconfirmed one.
+/// Does nothing if two-factor is already enabled.
+pub async fn begin_enrolment(db: &PgPool, user_id: UserId) -> Result<()> {
+    let secret: [u8; 20] = rand::random();
+    let secret = Secret::Raw(secret.to_vec()).to_encoded().to_string();
+
+    sqlx::query!(
+        "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2)
+         ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret
+         WHERE user_totp.confirmed_at IS NULL",
+        user_id.0,
+        secret,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Enable two-factor if `code` matches the pending secret, returning the
+/// new recovery codes.
+pub async fn confirm_enrolment(
+    db: &PgPool,
+    user_id: UserId,
+    code: &str,
+) -> Result<Option<Vec<String>>> {
+    let Some(totp) = get_totp(db, user_id).await? else {
+        return Ok(None);
+    };
+
+    if totp.confirmed {
+        return Ok(None);
+    }
+
+    let Some(step) = totp.matching_step(code)? else {
+        return Ok(None);
+    };
+
+    sqlx::query!(
+        "UPDATE user_totp SET confirmed_at = now(), last_used_step = $2 WHERE user_id = $1",
+        user_id.0,
+        step,
+    )
+    .execute(db)
+    .await?;
+
+    replace_recovery_codes(db, user_id).await.map(Some)
+}
+
+pub async fn disable(db: &PgPool, user_id: UserId) -> Result<()> {
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id.0)
+        .execute(db)
+        .await?;
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id.0)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Check a second factor: a TOTP code, or else an unused recovery code.
+/// Each TOTP code and recovery code is accepted at most once.
+pub async fn verify(db: &PgPool, user_id: UserId, code: &str) -> Result<bool> {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    let Some(totp) = get_totp(db, user_id).await? else {
+        return Ok(false);
+    };
+
+    if !totp.confirmed {
+        return Ok(false);
+    }
+
+    if let Some(step) = totp.matching_step(&code)? {
+        let result = sqlx::query!(
+            "UPDATE user_totp SET last_used_step = $2
+             WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+            user_id.0,
+            step,
+        )
+        .execute(db)
+        .await?;
+
+        return Ok(result.rows_affected() > 0);
+    }
+
+    let result = sqlx::query!(
+        "UPDATE recovery_codes SET used_at = now()
+         WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        user_id.0,
+        hash_secret(&code),
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Replace all recovery codes, returning the new ones for display. Only
+/// their hashes are stored.
+pub async fn replace_recovery_codes(db: &PgPool, user_id: UserId) -> Result<Vec<String>> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| hash_secret(&code.replace('-', "")))
+        .collect();
+
+    db::transaction(db, hashes, |txn, hashes| {
+        async move {
+            sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            sqlx::query!(
+                "INSERT INTO recovery_codes (user_id, code_hash)
+                 SELECT $1, unnest($2::text[])",
+                user_id.0,
+                hashes,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+
+    Ok(codes)
+}
+
+pub async fn count_recovery_codes(db: &PgPool, user_id: UserId) -> Result<i64> {
+    let count = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL"#,
+        user_id.0,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(count)
+}
+
+/// Formatted like `k3m9p-x2rtq`, avoiding characters that are easily
+/// confused when written down.
+fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+/// Begin the second login step for a user whose password checked out.
+pub async fn create_challenge(db: &PgPool, user_id: UserId) -> Result<String> {
+    let buf: [u8; 32] = rand::random();
+    let token = BASE64_URL_SAFE_NO_PAD.encode(buf);
+    let expires = OffsetDateTime::now_utc() + time::Duration::minutes(5);
+
+    sqlx::query!(
+        "INSERT INTO login_challenges (token_hash, user_id, expires) VALUES ($1, $2, $3)",
+        hash_secret(&token),
+        user_id.0,
+        expires,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Count an attempt against a challenge, returning its user while it is
+/// still valid. A challenge allows a handful of attempts before it expires.
+pub async fn attempt_challenge(db: &PgPool, token: &str) -> Result<Option<UserId>> {
+    let user_id = sqlx::query_scalar!(
+        "UPDATE login_challenges SET attempts = attempts + 1
+         WHERE token_hash = $1 AND expires > now() AND attempts < $2
+         RETURNING user_id",
+        hash_secret(token),
+        CHALLENGE_MAX_ATTEMPTS,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user_id.map(UserId))
+}
+
+pub async fn complete_challenge(db: &PgPool, token: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM login_challenges WHERE token_hash = $1",
+        hash_secret(token),
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+fn hash_secret(secret: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(secret.as_bytes()))
+}
@@ -1,12 +1,24 @@
+use std::fmt;
+
 use anyhow::Result;
 use base64::engine::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use futures_util::FutureExt;
 use sha2::{Digest, Sha256};
-use sqlx::PgPool;
+use sqlx::{PgPool, PgTransaction};
+use time::OffsetDateTime;
+
+use crate::db;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct UserId(pub(super) i32);
 
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct User {
     pub id: UserId,
@@ -14,24 +26,157 @@ pub struct User {
     pub password_hash: String,
 }
 
-pub async fn create(db: &PgPool, username: &str, email: &str, password: &str) -> Result<()> {
-    let password_hash = hash_password(password);
-
-    sqlx::query!(
-        "INSERT INTO users (username, email, password_hash, created_at, display_name, biography) VALUES ($1, $2, $3, now(), $4, $5)",
-        username,
-        email,
-        password_hash,
-        username,
-        "",
-    )
-    .execute(db)
-    .await?;
+/// New users become the administrator if no other user exists yet. Account
+/// creation runs serializably so that concurrent first sign-ups conflict,
+/// and `db::transaction` retries the losers, who then see the winner. Must
+/// be the first statement of the transaction.
+pub(super) async fn serialize_creation(txn: &mut PgTransaction<'_>) -> Result<()> {
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE")
+        .execute(&mut **txn)
+        .await?;
 
     Ok(())
 }
 
-pub async fn login(db: &PgPool, username: &str, password: &str) -> Result<UserId> {
+/// Why an account could not be created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unavailable {
+    Username,
+    Email,
+    /// The invite is unknown, used up or expired.
+    Invite,
+}
+
+impl Unavailable {
+    /// Checks for a username or email address that someone else took
+    /// between the form being validated and the account being inserted.
+    fn from_insert(err: &sqlx::Error) -> Option<Self> {
+        match db::unique_violation(err)? {
+            "users_username_key" => Some(Unavailable::Username),
+            "users_email_key" => Some(Unavailable::Email),
+            _ => None,
+        }
+    }
+}
+
+/// Create a user. The first user of an installation becomes its
+/// administrator.
+pub async fn create(
+    db: &PgPool,
+    username: &str,
+    email: &str,
+    password: &str,
+) -> Result<Result<UserId, Unavailable>> {
+    let args = (
+        username.to_owned(),
+        email.to_owned(),
+        hash_password(password),
+    );
+
+    db::transaction(db, args, |txn, (username, email, password_hash)| {
+        async move {
+            serialize_creation(txn).await?;
+
+            let id = sqlx::query_scalar!(
+                "INSERT INTO users (username, email, password_hash, created_at, display_name, biography, is_admin)
+                 VALUES ($1, $2, $3, now(), $1, '', NOT EXISTS (SELECT 1 FROM users))
+                 RETURNING id",
+                username,
+                email,
+                password_hash,
+            )
+            .fetch_one(&mut **txn)
+            .await;
+
+            match id {
+                Ok(id) => Ok(Ok(UserId(id))),
+                Err(err) => match Unavailable::from_insert(&err) {
+                    Some(unavailable) => Ok(Err(unavailable)),
+                    None => Err(err.into()),
+                },
+            }
+        }
+        .boxed()
+    })
+    .await
+}
+
+/// Register a user with an invite, using the invite up in the same
+/// transaction, so that it is left unused if the account is not created.
+pub async fn create_with_invite(
+    db: &PgPool,
+    username: &str,
+    email: &str,
+    password: &str,
+    invite: &str,
+) -> Result<Result<UserId, Unavailable>> {
+    let args = (
+        username.to_owned(),
+        email.to_owned(),
+        hash_password(password),
+        super::invite::hash_code(invite),
+    );
+
+    db::transaction(db, args, |txn, (username, email, password_hash, code_hash)| {
+        async move {
+            serialize_creation(txn).await?;
+
+            let invite_id = sqlx::query_scalar!(
+                "UPDATE invites SET used_at = now()
+                 WHERE code_hash = $1 AND used_at IS NULL AND expires > now()
+                 RETURNING id",
+                code_hash,
+            )
+            .fetch_optional(&mut **txn)
+            .await?;
+
+            let Some(invite_id) = invite_id else {
+                return Ok(Err(Unavailable::Invite));
+            };
+
+            let id = sqlx::query_scalar!(
+                "INSERT INTO users (username, email, password_hash, created_at, display_name, biography, is_admin)
+                 VALUES ($1, $2, $3, now(), $1, '', NOT EXISTS (SELECT 1 FROM users))
+                 RETURNING id",
+                username,
+                email,
+                password_hash,
+            )
+            .fetch_one(&mut **txn)
+            .await;
+
+            // The failed insert aborts the transaction, which leaves the
+            // invite unused.
+            let id = match id {
+                Ok(id) => id,
+                Err(err) => match Unavailable::from_insert(&err) {
+                    Some(unavailable) => return Ok(Err(unavailable)),
+                    None => return Err(err.into()),
+                },
+            };
+
+            sqlx::query!("UPDATE invites SET used_by = $1 WHERE id = $2", id, invite_id)
+                .execute(&mut **txn)
+                .await?;
+
+            Ok(Ok(UserId(id)))
+        }
+        .boxed()
+    })
+    .await
+}
+
+/// Whether any account exists yet. A new instance lets its first user
+/// register whatever the registration mode.
+pub async fn any_exist(db: &PgPool) -> Result<bool> {
+    let exists = sqlx::query_scalar!("SELECT EXISTS (SELECT 1 FROM users) AS \"exists!\"")
+        .fetch_one(db)
+        .await?;
+
+    Ok(exists)
+}
+
+pub async fn login(db: &PgPool, username: &str, password: &str) -> Result<Option<UserId>> {
     let password_hash = hash_password(password);
 
     let id = sqlx::query_scalar!(
@@ -39,10 +184,10 @@ pub async fn login(db: &PgPool, username: &str, password: &str) -> Result<UserId
         username,
         password_hash,
     )
-    .fetch_one(db)
+    .fetch_optional(db)
     .await?;
 
-    Ok(UserId(id))
+    Ok(id.map(UserId))
 }
 
 pub async fn get_by_id(db: &PgPool, user_id: UserId) -> Result<Option<User>> {
@@ -72,6 +217,128 @@ pub async fn get_id_by_username(db: &PgPool, username: &str) -> Result<Option<Us
     Ok(record.map(UserId))
 }
 
+pub async fn get_id_by_email(db: &PgPool, email: &str) -> Result<Option<UserId>> {
+    let record = sqlx::query_scalar!("SELECT id FROM users WHERE email = $1", email)
+        .fetch_optional(db)
+        .await?;
+
+    Ok(record.map(UserId))
+}
+
+/// Change a user's username, keeping the old one as a redirect. Returns
+/// `false` if the new username is taken. Until `txn` is committed, the new
+/// username stays reserved and others trying to take it wait.
+pub async fn rename(txn: &mut PgTransaction<'_>, user_id: UserId, username: &str) -> Result<bool> {
+    let taken = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE username = $1) AS "taken!""#,
+        username,
+    )
+    .fetch_one(&mut **txn)
+    .await?;
+
+    if taken {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "INSERT INTO username_redirects (username, user_id)
+         SELECT username, id FROM users WHERE id = $1",
+        user_id.0,
+    )
+    .execute(&mut **txn)
+    .await?;
+
+    let updated = sqlx::query!(
+        "UPDATE users SET username = $1 WHERE id = $2",
+        username,
+        user_id.0,
+    )
+    .execute(&mut **txn)
+    .await;
+
+    // Someone else took the username after it was checked.
+    match updated {
+        Ok(_) => Ok(true),
+        Err(err) if db::unique_violation(&err) == Some("users_username_key") => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The current username of whoever used to go by `username`.
+pub async fn resolve_redirect(db: &PgPool, username: &str) -> Result<Option<String>> {
+    let current = sqlx::query_scalar!(
+        "SELECT u.username FROM username_redirects r
+         JOIN users u ON u.id = r.user_id
+         WHERE r.username = $1",
+        username,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(current)
+}
+
+pub async fn set_password(db: &PgPool, user_id: UserId, password: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE id = $2",
+        hash_password(password),
+        user_id.0,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Users provisioned through an identity provider have no password until
+/// they set one.
+pub async fn has_password(db: &PgPool, user_id: UserId) -> Result<bool> {
+    let has_password = sqlx::query_scalar!(
+        r#"SELECT password_hash <> '' AS "has_password!" FROM users WHERE id = $1"#,
+        user_id.0,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(has_password)
+}
+
+pub async fn is_suspended(db: &PgPool, user_id: UserId) -> Result<bool> {
+    let suspended = sqlx::query_scalar!(
+        r#"SELECT suspended_at IS NOT NULL AS "suspended!" FROM users WHERE id = $1"#,
+        user_id.0,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(suspended.unwrap_or(true))
+}
+
+pub async fn set_admin(db: &PgPool, user_id: UserId, is_admin: bool) -> Result<()> {
+    sqlx::query!(
+        "UPDATE users SET is_admin = $1 WHERE id = $2",
+        is_admin,
+        user_id.0,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether a user is the only administrator left.
+pub async fn is_last_admin(db: &PgPool, user_id: UserId) -> Result<bool> {
+    let last = sqlx::query_scalar!(
+        r#"SELECT is_admin AND NOT EXISTS (SELECT 1 FROM users WHERE is_admin AND id <> $1) AS "last!"
+         FROM users WHERE id = $1"#,
+        user_id.0,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(last)
+}
+
 fn hash_password(password: &str) -> String {
     let password_hash_bytes = Sha256::digest(password.as_bytes());
     let password_hash = BASE64_STANDARD.encode(password_hash_bytes);
@@ -82,13 +349,14 @@ fn hash_password(password: &str) -> String {
 pub struct UserProfile {
     pub username: String,
     pub email: String,
+    pub email_verified: bool,
     pub display_name: String,
     pub biography: String,
 }
 
 pub async fn get_profile(db: &PgPool, user_id: UserId) -> Result<Option<UserProfile>> {
     let record = sqlx::query!(
-        "SELECT username, email, display_name, biography FROM users WHERE id = $1",
+        "SELECT username, email, email_verified_at, display_name, biography FROM users WHERE id = $1",
         user_id.0,
     )
     .fetch_optional(db)
@@ -97,39 +365,52 @@ pub async fn get_profile(db: &PgPool, user_id: UserId) -> Result<Option<UserProf
     Ok(record.map(|r| UserProfile {
         username: r.username,
         email: r.email,
+        email_verified: r.email_verified_at.is_some(),
         display_name: r.display_name,
         biography: r.biography,
     }))
 }
 
+/// Returns whether the email address changed, in which case it needs to be
+/// verified again.
 pub async fn update_profile(
     db: &PgPool,
     user_id: UserId,
     email: &str,
     display_name: &str,
     biography: &str,
-) -> Result<()> {
-    sqlx::query!(
-        "UPDATE users SET email = $1, display_name = $2, biography = $3 WHERE id = $4",
+) -> Result<bool> {
+    let previous = sqlx::query_scalar!(
+        "UPDATE users u
+         SET email = $1,
+             email_verified_at = CASE WHEN u.email = $1 THEN u.email_verified_at END,
+             display_name = $2,
+             biography = $3
+         FROM users old
+         WHERE u.id = $4 AND old.id = u.id
+         RETURNING old.email",
         email,
         display_name,
         biography,
         user_id.0,
     )
-    .execute(db)
+    .fetch_one(db)
     .await?;
 
-    Ok(())
+    Ok(previous != email)
 }
 
 /// Load all SSH keys with their associated usernames.
 /// Returns Vec<(encoded_key, username)> for authentication.
+/// Expired keys and keys of suspended users are left out.
 pub async fn get_all_ssh_keys(db: &PgPool) -> Result<Vec<(String, String)>> {
     let records = sqlx::query!(
         r#"
         SELECT uk.encoded, u.username
         FROM user_keys uk
         JOIN users u ON uk.user_id = u.id
+        WHERE u.suspended_at IS NULL
+            AND (uk.expires_at IS NULL OR uk.expires_at > now())
         "#
     )
     .fetch_all(db)
@@ -141,20 +422,44 @@ pub async fn get_all_ssh_keys(db: &PgPool) -> Result<Vec<(String, String)>> {
         .collect())
 }
 
+/// Record that a key was just used to log in.
+pub async fn touch_key(db: &PgPool, encoded: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE user_keys SET last_used_at = now() WHERE encoded = $1",
+        encoded,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct UserKey {
     pub key_type: String,
     pub encoded: String,
+    pub fingerprint: String,
     pub username: String,
     pub hostname: String,
     pub name: String,
+    pub managed: bool,
+    pub last_used_at: Option<OffsetDateTime>,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+impl UserKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= OffsetDateTime::now_utc())
+    }
 }
 
 /// Get all SSH keys for a specific user
 pub async fn get_user_keys(db: &PgPool, user_id: UserId) -> Result<Vec<UserKey>> {
     let records = sqlx::query!(
         r#"
-        SELECT type, encoded, username, hostname, name
+        SELECT type, encoded, fingerprint, username, hostname, name, managed,
+            last_used_at, expires_at
         FROM user_keys
         WHERE user_id = $1
         ORDER BY name
@@ -169,34 +474,297 @@ pub async fn get_user_keys(db: &PgPool, user_id: UserId) -> Result<Vec<UserKey>>
         .map(|r| UserKey {
             key_type: r.r#type,
             encoded: r.encoded,
+            fingerprint: r.fingerprint,
             username: r.username,
             hostname: r.hostname,
             name: r.name,
+            managed: r.managed,
+            last_used_at: r.last_used_at,
+            expires_at: r.expires_at,
+        })
+        .collect())
+}
+
+pub struct ExpiringKey {
+    pub key_type: String,
+    pub encoded: String,
+    pub user_id: UserId,
+    pub name: String,
+    pub fingerprint: String,
+    pub expires_at: OffsetDateTime,
+}
+
+/// Keys expiring within `notice` whose owners have not been told yet.
+pub async fn expiring_keys(db: &PgPool, notice: time::Duration) -> Result<Vec<ExpiringKey>> {
+    let records = sqlx::query!(
+        r#"SELECT type, encoded, user_id, name, fingerprint, expires_at AS "expires_at!"
+         FROM user_keys
+         WHERE expiry_notified_at IS NULL AND expires_at > now() AND expires_at <= $1"#,
+        OffsetDateTime::now_utc() + notice,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| ExpiringKey {
+            key_type: r.r#type,
+            encoded: r.encoded,
+            user_id: UserId(r.user_id),
+            name: r.name,
+            fingerprint: r.fingerprint,
+            expires_at: r.expires_at,
         })
         .collect())
 }
 
-/// Add a new SSH key for a user
-pub async fn add_user_key(
+/// Records that the owner of a key has been told it expires soon.
+pub async fn mark_expiry_notified(db: &PgPool, key_type: &str, encoded: &str) -> Result<()> {
+    sqlx::query!(
+        "UPDATE user_keys SET expiry_notified_at = now() WHERE type = $1 AND encoded = $2",
+        key_type,
+        encoded
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether any user has already added this key.
+pub async fn key_exists(db: &PgPool, key_type: &str, encoded: &str) -> Result<bool> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM user_keys WHERE type = $1 AND encoded = $2) AS "exists!""#,
+        key_type,
+        encoded
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(exists)
+}
+
+pub struct NewKey<'a> {
+    pub key_type: &'a str,
+    pub encoded: &'a str,
+    pub username: &'a str,
+    pub hostname: &'a str,
+    pub name: &'a str,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+/// Add a new SSH key for a user. Returns `false` if the key is already in
+/// use, by them or anyone else.
+pub async fn add_user_key(db: &PgPool, user_id: UserId, key: &NewKey<'_>) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO user_keys (type, encoded, username, hostname, user_id, name, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        key.key_type,
+        key.encoded,
+        key.username,
+        key.hostname,
+        user_id.0,
+        key.name,
+        key.expires_at,
+    )
+    .execute(db)
+    .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(err) if db::unique_violation(&err) == Some("user_keys_pkey") => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Delete one of a user's SSH keys, unless it is managed by directory
+/// sync. Returns `false` if nothing was deleted.
+pub async fn delete_user_key(
     db: &PgPool,
     user_id: UserId,
     key_type: &str,
     encoded: &str,
-    username: &str,
-    hostname: &str,
-    name: &str,
-) -> Result<()> {
-    sqlx::query!(
+) -> Result<bool> {
+    let result = sqlx::query!(
         r#"
-        INSERT INTO user_keys (type, encoded, username, hostname, user_id, name)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        DELETE FROM user_keys
+        WHERE user_id = $1 AND type = $2 AND encoded = $3 AND NOT managed
         "#,
+        user_id.0,
         key_type,
-        encoded,
+        encoded
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Split a key comment of the form `username@hostname`.
+pub fn key_comment_parts(comment: Option<&str>) -> (String, String) {
+    match comment {
+        Some(comment) => match comment.split_once('@') {
+            Some((user, host)) => (user.to_string(), host.to_string()),
+            None => (comment.to_string(), "unknown".to_string()),
+        },
+        None => ("unknown".to_string(), "unknown".to_string()),
+    }
+}
+
+pub struct ManagedKey {
+    pub key_type: String,
+    pub encoded: String,
+    pub username: String,
+    pub hostname: String,
+    pub name: String,
+}
+
+/// Make the user's managed keys match `keys`. Keys already registered by
+/// hand, by anyone, are left alone.
+pub async fn sync_managed_keys(db: &PgPool, user_id: UserId, keys: Vec<ManagedKey>) -> Result<()> {
+    db::transaction(db, keys, |txn, keys| {
+        async move {
+            let encoded: Vec<&str> = keys.iter().map(|key| key.encoded.as_str()).collect();
+
+            sqlx::query!(
+                "DELETE FROM user_keys WHERE user_id = $1 AND managed AND NOT encoded = ANY($2)",
+                user_id.0,
+                &encoded as &[&str],
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            for key in keys {
+                sqlx::query!(
+                    "INSERT INTO user_keys (type, encoded, username, hostname, user_id, name, managed)
+                     VALUES ($1, $2, $3, $4, $5, $6, true)
+                     ON CONFLICT DO NOTHING",
+                    key.key_type,
+                    key.encoded,
+                    key.username,
+                    key.hostname,
+                    user_id.0,
+                    key.name,
+                )
+                .execute(&mut **txn)
+                .await?;
+            }
+
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[derive(Debug, Clone)]
+pub struct UserSummary {
+    pub id: UserId,
+    pub username: String,
+    pub email: String,
+    pub display_name: String,
+    pub created_at: OffsetDateTime,
+    pub is_admin: bool,
+    pub suspended_at: Option<OffsetDateTime>,
+}
+
+/// Users whose username, email address or display name contains `query`,
+/// oldest first. An empty query matches everyone.
+pub async fn search(db: &PgPool, query: &str, limit: i64, offset: i64) -> Result<Vec<UserSummary>> {
+    let records = sqlx::query!(
+        "SELECT id, username, email, display_name, created_at, is_admin, suspended_at
+         FROM users
+         WHERE strpos(lower(username), lower($1)) > 0
+             OR strpos(lower(email), lower($1)) > 0
+             OR strpos(lower(display_name), lower($1)) > 0
+         ORDER BY id
+         LIMIT $2 OFFSET $3",
+        query,
+        limit,
+        offset,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|r| UserSummary {
+            id: UserId(r.id),
+            username: r.username,
+            email: r.email,
+            display_name: r.display_name,
+            created_at: r.created_at,
+            is_admin: r.is_admin,
+            suspended_at: r.suspended_at,
+        })
+        .collect())
+}
+
+pub async fn get_summary(db: &PgPool, username: &str) -> Result<Option<UserSummary>> {
+    let record = sqlx::query!(
+        "SELECT id, username, email, display_name, created_at, is_admin, suspended_at
+         FROM users WHERE username = $1",
         username,
-        hostname,
-        user_id.0,
-        name
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(record.map(|r| UserSummary {
+        id: UserId(r.id),
+        username: r.username,
+        email: r.email,
+        display_name: r.display_name,
+        created_at: r.created_at,
+        is_admin: r.is_admin,
+        suspended_at: r.suspended_at,
+    }))
+}
+
+/// Suspend a user, ending their sessions and invalidating every token they
+/// hold. Their SSH keys stop working as well.
+pub async fn suspend(db: &PgPool, user_id: UserId) -> Result<()> {
+    db::transaction(db, (), |txn, _| {
+        async move {
+            sqlx::query!(
+                "UPDATE users SET suspended_at = now() WHERE id = $1 AND suspended_at IS NULL",
+                user_id.0,
+            )
+            .execute(&mut **txn)
+            .await?;
+
+            sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            sqlx::query!("DELETE FROM lfs_tokens WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            sqlx::query!("DELETE FROM login_challenges WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            sqlx::query!("DELETE FROM oauth_codes WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            sqlx::query!("DELETE FROM oauth_tokens WHERE user_id = $1", user_id.0)
+                .execute(&mut **txn)
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+pub async fn unsuspend(db: &PgPool, user_id: UserId) -> Result<()> {
+    sqlx::query!(
+        "UPDATE users SET suspended_at = NULL WHERE id = $1",
+        user_id.0
     )
     .execute(db)
     .await?;
@@ -204,18 +772,113 @@ pub async fn add_user_key(
     Ok(())
 }
 
-/// Delete an SSH key
-pub async fn delete_user_key(db: &PgPool, key_type: &str, encoded: &str) -> Result<()> {
-    sqlx::query!(
-        r#"
-        DELETE FROM user_keys
-        WHERE type = $1 AND encoded = $2
-        "#,
+/// Remove one of a user's SSH keys on their behalf, including keys managed
+/// by directory sync. Returns `false` if they have no such key.
+pub async fn revoke_user_key(
+    db: &PgPool,
+    user_id: UserId,
+    key_type: &str,
+    encoded: &str,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM user_keys WHERE user_id = $1 AND type = $2 AND encoded = $3",
+        user_id.0,
         key_type,
-        encoded
+        encoded,
     )
     .execute(db)
     .await?;
 
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete a user. Everything they own in the database goes with them
+/// through the foreign keys. Repositories on disk are left to the caller.
+pub async fn delete(db: &PgPool, user_id: UserId) -> Result<()> {
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id.0)
+        .execute(db)
+        .await?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres container"]
+    async fn one_first_administrator(db: PgPool) {
+        let (alice, bob, carol) = futures_util::join!(
+            create(&db, "alice", "alice@example.com", "password"),
+            create(&db, "bob", "bob@example.com", "password"),
+            create(&db, "carol", "carol@example.com", "password"),
+        );
+        for created in [alice, bob, carol] {
+            assert!(matches!(created, Ok(Ok(_))));
+        }
+
+        let mut admins = 0;
+        for username in ["alice", "bob", "carol"] {
+            let user = get_summary(&db, username).await.unwrap().unwrap();
+            admins += user.is_admin as usize;
+        }
+        assert_eq!(admins, 1);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres container"]
+    async fn taken_usernames_and_emails(db: PgPool) {
+        create(&db, "alice", "alice@example.com", "password")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let username = create(&db, "alice", "other@example.com", "password").await;
+        assert_eq!(username.unwrap(), Err(Unavailable::Username));
+        let email = create(&db, "bob", "alice@example.com", "password").await;
+        assert_eq!(email.unwrap(), Err(Unavailable::Email));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres container"]
+    async fn keys_are_deleted_by_their_owner(db: PgPool) {
+        let alice = create(&db, "alice", "alice@example.com", "password")
+            .await
+            .unwrap()
+            .unwrap();
+        let bob = create(&db, "bob", "bob@example.com", "password")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let encoded = "AAAAC3NzaC1lZDI1NTE5AAAAIGdpTy1S4VmvIm4F2yZ7gDdDaHdgZDKg0fRRgD1ZuIm8";
+        let key = NewKey {
+            key_type: "ssh-ed25519",
+            encoded,
+            username: "alice",
+            hostname: "example",
+            name: "alice@example",
+            expires_at: None,
+        };
+        assert!(add_user_key(&db, alice, &key).await.unwrap());
+        assert!(!add_user_key(&db, bob, &key).await.unwrap());
+
+        assert!(
+            !delete_user_key(&db, bob, "ssh-ed25519", encoded)
+                .await
+                .unwrap()
+        );
+        assert_eq!(get_user_keys(&db, alice).await.unwrap().len(), 1);
+        assert!(
+            delete_user_key(&db, alice, "ssh-ed25519", encoded)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !delete_user_key(&db, alice, "ssh-ed25519", encoded)
+                .await
+                .unwrap()
+        );
+    }
+}
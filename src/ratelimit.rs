@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::config::RateLimit;
+use crate::metrics;
+
+/// What is being attempted. Each scope keeps its own counters, so failing
+/// to log in does not count against registering or cloning over SSH.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Login,
+    Register,
+    Ssh,
+    PastePassword,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Login => "login",
+            Scope::Register => "registration",
+            Scope::Ssh => "ssh",
+            Scope::PastePassword => "paste passwords",
+        })
+    }
+}
+
+/// Who is attempting it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subject {
+    Ip(IpAddr),
+    Username(String),
+}
+
+impl Subject {
+    /// Usernames are matched case-insensitively so that varying the case
+    /// does not get a fresh set of attempts.
+    pub fn username(username: &str) -> Self {
+        Self::Username(username.to_lowercase())
+    }
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::Ip(ip) => write!(f, "address {}", ip),
+            Subject::Username(username) => write!(f, "username \"{}\"", username),
+        }
+    }
+}
+
+struct Entry {
+    attempts: u32,
+    window_start: Instant,
+    last_attempt: Instant,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Counters {
+    entries: HashMap<(Scope, Subject), Entry>,
+    bans: HashMap<IpAddr, Instant>,
+}
+
+/// Counts attempts per subject in memory and locks a subject out once it
+/// reaches the configured limit, doubling the lockout each time it happens
+/// again. Addresses that keep getting locked out of SSH can optionally be
+/// banned from it altogether. Bans do not affect the other scopes.
+pub struct RateLimiter {
+    policy: RateLimit,
+    counters: Mutex<Counters>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimit) -> Self {
+        Self {
+            policy,
+            counters: Mutex::new(Counters::default()),
+        }
+    }
+
+    /// How long until all of the subjects may try again, if any of them is
+    /// locked out, or banned from SSH.
+    pub fn check(&self, scope: Scope, subjects: &[Subject]) -> Option<Duration> {
+        self.check_at(scope, subjects, Instant::now())
+    }
+
+    fn check_at(&self, scope: Scope, subjects: &[Subject], now: Instant) -> Option<Duration> {
+        let counters = self.counters.lock().unwrap();
+
+        subjects
+            .iter()
+            .flat_map(|subject| {
+                let locked_until = counters
+                    .entries
+                    .get(&(scope, subject.clone()))
+                    .and_then(|entry| entry.locked_until);
+
+                let banned_until = match subject {
+                    Subject::Ip(ip) if scope == Scope::Ssh => counters.bans.get(ip).copied(),
+                    _ => None,
+                };
+
+                [locked_until, banned_until]
+            })
+            .flatten()
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    /// Counts an attempt against each of the subjects, locking out those
+    /// that have reached the limit.
+    pub fn record(&self, scope: Scope, subjects: &[Subject]) {
+        self.record_at(scope, subjects, Instant::now());
+    }
+
+    fn record_at(&self, scope: Scope, subjects: &[Subject], now: Instant) {
+        let window = Duration::from_secs(self.policy.window_secs);
+        let mut counters = self.counters.lock().unwrap();
+        metrics::get().auth_failures_total.inc();
+
+        for subject in subjects {
+            let entry = counters
+                .entries
+                .entry((scope, subject.clone()))
+                .or_insert_with(|| Entry {
+                    attempts: 0,
+                    window_start: now,
+                    last_attempt: now,
+                    lockouts: 0,
+                    locked_until: None,
+                });
+
+            if now - entry.window_start > window {
+                entry.attempts = 0;
+                entry.window_start = now;
+            }
+
+            entry.attempts += 1;
+            entry.last_attempt = now;
+
+            if entry.attempts < self.policy.max_attempts {
+                continue;
+            }
+
+            entry.attempts = 0;
+            entry.window_start = now;
+            entry.lockouts += 1;
+
+            let lockouts = entry.lockouts;
+            let lockout = self.lockout_duration(lockouts);
+            entry.locked_until = Some(now + lockout);
+
+            metrics::get().auth_lockouts_total.inc();
+            warn!(
+                "locked out {} from {} for {}s after {} attempts",
+                subject,
+                scope,
+                lockout.as_secs(),
+                self.policy.max_attempts
+            );
+
+            if scope == Scope::Ssh
+                && let Subject::Ip(ip) = subject
+                && let Some(ban_after) = self.policy.ban_after
+                && lockouts >= ban_after
+            {
+                counters
+                    .bans
+                    .insert(*ip, now + Duration::from_secs(self.policy.ban_secs));
+
+                metrics::get().auth_bans_total.inc();
+                warn!(
+                    "banned address {} for {}s after {} lockouts",
+                    ip, self.policy.ban_secs, lockouts
+                );
+            }
+        }
+    }
+
+    /// Forgets the attempts of a subject that has just succeeded. Earlier
+    /// lockouts still count towards the length of the next one.
+    pub fn reset(&self, scope: Scope, subject: &Subject) {
+        let mut counters = self.counters.lock().unwrap();
+        if let Some(entry) = counters.entries.get_mut(&(scope, subject.clone())) {
+            entry.attempts = 0;
+        }
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let counters = self.counters.lock().unwrap();
+        counters
+            .bans
+            .get(&ip)
+            .is_some_and(|until| *until > Instant::now())
+    }
+
+    /// Drops expired bans and subjects that have been quiet for long enough
+    /// that their lockout history no longer matters.
+    pub fn prune(&self) {
+        self.prune_at(Instant::now());
+    }
+
+    fn prune_at(&self, now: Instant) {
+        let retention =
+            Duration::from_secs(self.policy.window_secs.max(self.policy.max_lockout_secs));
+        let mut counters = self.counters.lock().unwrap();
+
+        counters.entries.retain(|_, entry| {
+            entry.locked_until.is_some_and(|until| until > now)
+                || now - entry.last_attempt <= retention
+        });
+
+        counters.bans.retain(|_, until| *until > now);
+    }
+
+    fn lockout_duration(&self, lockouts: u32) -> Duration {
+        let factor = 1u64.checked_shl(lockouts - 1).unwrap_or(u64::MAX);
+        let secs = self.policy.lockout_secs.saturating_mul(factor);
+        Duration::from_secs(secs.min(self.policy.max_lockout_secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimit {
+            max_attempts: 3,
+            window_secs: 60,
+            lockout_secs: 10,
+            max_lockout_secs: 35,
+            ban_after: Some(3),
+            ban_secs: 100,
+        })
+    }
+
+    fn ip() -> Subject {
+        Subject::Ip("192.0.2.1".parse().unwrap())
+    }
+
+    fn fail(limiter: &RateLimiter, subjects: &[Subject], times: u32, now: Instant) {
+        fail_in(limiter, Scope::Login, subjects, times, now);
+    }
+
+    fn fail_in(
+        limiter: &RateLimiter,
+        scope: Scope,
+        subjects: &[Subject],
+        times: u32,
+        now: Instant,
+    ) {
+        for _ in 0..times {
+            limiter.record_at(scope, subjects, now);
+        }
+    }
+
+    #[test]
+    fn lockout_threshold() {
+        let limiter = limiter();
+        let subjects = [ip(), Subject::username("Alice")];
+        let now = Instant::now();
+
+        fail(&limiter, &subjects, 2, now);
+        assert_eq!(limiter.check_at(Scope::Login, &subjects, now), None);
+
+        fail(&limiter, &subjects, 1, now);
+        assert_eq!(
+            limiter.check_at(Scope::Login, &subjects, now),
+            Some(Duration::from_secs(10))
+        );
+
+        // Each subject is locked out on its own, and usernames ignore case.
+        assert!(
+            limiter
+                .check_at(Scope::Login, &[Subject::username("ALICE")], now)
+                .is_some()
+        );
+        assert!(limiter.check_at(Scope::Register, &subjects, now).is_none());
+        assert!(
+            limiter
+                .check_at(Scope::Login, &[Subject::username("bob")], now)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn attempts_outside_the_window_are_forgotten() {
+        let limiter = limiter();
+        let subjects = [ip()];
+        let now = Instant::now();
+
+        fail(&limiter, &subjects, 2, now);
+        fail(&limiter, &subjects, 2, now + Duration::from_secs(61));
+        assert_eq!(limiter.check_at(Scope::Login, &subjects, now), None);
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let limiter = limiter();
+        let subjects = [Subject::username("alice")];
+        let mut now = Instant::now();
+
+        for expected in [10, 20, 35, 35] {
+            fail(&limiter, &subjects, 3, now);
+            assert_eq!(
+                limiter.check_at(Scope::Login, &subjects, now),
+                Some(Duration::from_secs(expected))
+            );
+
+            // The lockout expires on its own.
+            now += Duration::from_secs(expected);
+            assert_eq!(limiter.check_at(Scope::Login, &subjects, now), None);
+        }
+    }
+
+    #[test]
+    fn reset_keeps_lockout_history() {
+        let limiter = limiter();
+        let subjects = [Subject::username("alice")];
+        let now = Instant::now();
+
+        fail(&limiter, &subjects, 2, now);
+        limiter.reset(Scope::Login, &subjects[0]);
+        fail(&limiter, &subjects, 2, now);
+        assert_eq!(limiter.check_at(Scope::Login, &subjects, now), None);
+
+        fail(&limiter, &subjects, 1, now);
+        let later = now + Duration::from_secs(10);
+        limiter.reset(Scope::Login, &subjects[0]);
+        fail(&limiter, &subjects, 3, later);
+        assert_eq!(
+            limiter.check_at(Scope::Login, &subjects, later),
+            Some(Duration::from_secs(20))
+        );
+    }
+
+    #[test]
+    fn repeated_lockouts_ban_the_address() {
+        let limiter = limiter();
+        let subjects = [ip()];
+        let mut now = Instant::now();
+
+        for _ in 0..3 {
+            fail_in(&limiter, Scope::Ssh, &subjects, 3, now);
+            now += Duration::from_secs(35);
+        }
+
+        let Subject::Ip(address) = ip() else {
+            unreachable!()
+        };
+        assert!(limiter.is_banned(address));
+        assert!(
+            limiter
+                .check_at(Scope::Ssh, &subjects, now)
+                .is_some_and(|retry_after| retry_after > Duration::from_secs(35))
+        );
+
+        // The ban is from SSH only.
+        assert_eq!(limiter.check_at(Scope::Login, &subjects, now), None);
+    }
+
+    #[test]
+    fn web_lockouts_do_not_ban() {
+        let limiter = limiter();
+        let subjects = [ip()];
+        let mut now = Instant::now();
+
+        for scope in [Scope::Login, Scope::Register, Scope::PastePassword] {
+            for _ in 0..3 {
+                fail_in(&limiter, scope, &subjects, 3, now);
+                now += Duration::from_secs(35);
+            }
+        }
+
+        let Subject::Ip(address) = ip() else {
+            unreachable!()
+        };
+        assert!(!limiter.is_banned(address));
+        assert_eq!(limiter.check_at(Scope::Ssh, &subjects, now), None);
+    }
+
+    #[test]
+    fn prune_drops_quiet_subjects() {
+        let limiter = limiter();
+        let alice = [Subject::username("alice")];
+        let bob = [Subject::username("bob")];
+        let now = Instant::now();
+
+        fail(&limiter, &alice, 3, now);
+        fail(&limiter, &bob, 1, now + Duration::from_secs(30));
+
+        // Retention is the longer of the window and the maximum lockout.
+        limiter.prune_at(now + Duration::from_secs(61));
+        let counters = limiter.counters.lock().unwrap();
+        assert!(
+            !counters
+                .entries
+                .contains_key(&(Scope::Login, alice[0].clone()))
+        );
+        assert!(
+            counters
+                .entries
+                .contains_key(&(Scope::Login, bob[0].clone()))
+        );
+    }
+}
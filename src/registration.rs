@@ -0,0 +1,39 @@
+use anyhow::Result;
+
+use crate::config::RegistrationMode;
+use crate::model;
+use crate::state::AppState;
+
+/// The registration mode in effect. A new instance lets its first user
+/// register whatever the configured mode, so that it gets an administrator.
+pub async fn mode(state: &AppState) -> Result<RegistrationMode> {
+    let mode = state.config.registration.mode;
+    if mode != RegistrationMode::Open && !model::user::any_exist(&state.db).await? {
+        return Ok(RegistrationMode::Open);
+    }
+
+    Ok(mode)
+}
+
+/// Whether `email` is at one of the domains allowed to register in
+/// `domain` mode.
+pub fn domain_allowed(state: &AppState, email: &str) -> bool {
+    let domain = email.rsplit_once('@').map_or("", |(_, domain)| domain);
+    state
+        .config
+        .registration
+        .allowed_domains
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+}
+
+/// Whether an account may be created for `email` on a first sign-in through
+/// an identity provider or the directory. There is no invite to present
+/// there, so invite mode turns such accounts away like closed mode does.
+pub async fn allows_provisioning(state: &AppState, email: &str) -> Result<bool> {
+    Ok(match mode(state).await? {
+        RegistrationMode::Open => true,
+        RegistrationMode::Domain => domain_allowed(state, email),
+        RegistrationMode::Closed | RegistrationMode::Invite => false,
+    })
+}
@@ -0,0 +1,287 @@
+use axum::Router;
+use axum::extract::Form;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use conduit_derive::Validate;
+use serde::Deserialize;
+use url::Url;
+
+use super::Admin;
+use crate::middleware::auth::Session;
+use crate::middleware::client::ClientInfo;
+use crate::model;
+use crate::model::audit::{Action, Event};
+use crate::routes::form::{FormPage, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
+use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+
+const MAX_REDIRECT_URIS: usize = 10;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/applications", get(page_applications))
+        .route("/admin/applications", post(do_create))
+        .route("/admin/applications/secret", post(do_reset_secret))
+        .route("/admin/applications/delete", post(do_delete))
+}
+
+async fn page_applications(state: AppState, Admin(session): Admin) -> Result<Response, AppError> {
+    let markup = applications_page(&state, session, None, &ValidationErrors::new()).await?;
+    Ok(markup.into_response())
+}
+
+async fn applications_page(
+    state: &AppState,
+    session: Session,
+    form: Option<&CreateAppForm>,
+    errors: &ValidationErrors,
+) -> Result<maud::Markup, AppError> {
+    let apps = model::oauth::list_apps(&state.db).await?;
+    let name = form.map(|f| f.name.as_str());
+    let redirect_uris = form.map(|f| f.redirect_uris.as_str()).unwrap_or_default();
+    let confidential = form.is_none_or(|f| f.confidential.is_some());
+
+    let markup = maud::html! {
+        (super::admin_nav("applications"))
+
+        h2 .text-xl .mt-4 .mb-2 { "OAuth applications" }
+        p .text-gray-600 .mb-4 {
+            "Registered applications can ask users for access to their accounts through "
+            span .font-mono { "/oauth/authorize" } "."
+        }
+
+        @if apps.is_empty() {
+            p .text-gray-600 .mb-4 { "No applications registered." }
+        } @else {
+            div .mb-4 {
+                @for app in &apps {
+                    div .border-solid .border-1 .border-gray-300 .p-2 .mb-2 .flex .justify-between .items-start {
+                        div .flex-1 .overflow-hidden {
+                            div .font-semibold .mb-1 {
+                                (app.name)
+                                span .ml-2 .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded {
+                                    @if app.is_confidential() { "confidential" } @else { "public" }
+                                }
+                            }
+                            div .font-mono .text-sm { "client_id: " (app.client_id) }
+                            @for uri in &app.redirect_uris {
+                                div .font-mono .text-sm .text-gray-600 .break-all { (uri) }
+                            }
+                            div .text-sm .text-gray-600 .mt-1 {
+                                "Registered " (app.created_at.date())
+                                @if let Some(created_by) = &app.created_by {
+                                    " by " (created_by)
+                                }
+                            }
+                        }
+                        div .ml-2 .flex .flex-col .items-end .gap-1 {
+                            @if app.is_confidential() {
+                                form method="post" action="/admin/applications/secret" {
+                                    (form::csrf_field())
+                                    input type="hidden" name="id" value=(app.id);
+                                    button .text-blue-600 .hover:underline .text-sm type="submit" { "new secret" }
+                                }
+                            }
+                            form method="post" action="/admin/applications/delete" {
+                                (form::csrf_field())
+                                input type="hidden" name="id" value=(app.id);
+                                button .text-red-600 .hover:underline .text-sm type="submit" { "delete" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        h3 .text-lg .mt-6 .mb-2 { "Register application" }
+        form method="post" .max-w-md {
+            (form::csrf_field())
+            div .mb-2 {
+                label for="name" .block .mb-1 { "Name" }
+                (form::input::<CreateAppForm>("name").value(name))
+                (field_errors(errors, "name"))
+            }
+            div .mb-2 {
+                label for="redirect_uris" .block .mb-1 { "Redirect URIs" }
+                (form::textarea::<CreateAppForm>("redirect_uris")
+                    .rows(3)
+                    .value(redirect_uris)
+                    .placeholder("https://tool.example.com/oauth/callback")
+                    .class("font-mono text-sm"))
+                (field_errors(errors, "redirect_uris"))
+            }
+            p .text-sm .text-gray-600 .mb-2 { "One per line. Requests must use one of them exactly." }
+            div .mb-3 {
+                label {
+                    input type="checkbox" name="confidential" value="on" checked[confidential];
+                    " Confidential client"
+                }
+                p .text-sm .text-gray-600 {
+                    "Server-side applications that can keep a secret. Leave unchecked for "
+                    "command line and browser applications."
+                }
+            }
+            input
+                .text-neutral-50
+                .bg-blue-500
+                .border-neutral-700
+                .border-solid
+                .border-1
+                .px-3
+                .py-1
+                type="submit"
+                value="Register";
+        }
+    };
+
+    Ok(shell::document(markup, "applications", session))
+}
+
+fn credentials_page(
+    session: Session,
+    name: &str,
+    client_id: Option<&str>,
+    secret: Option<&str>,
+) -> maud::Markup {
+    let markup = maud::html! {
+        (super::admin_nav("applications"))
+
+        h2 .text-xl .mt-4 .mb-4 { (name) }
+        @if let Some(client_id) = client_id {
+            div .mb-2 {
+                div .text-sm .text-gray-600 { "Client ID" }
+                div .font-mono .break-all { (client_id) }
+            }
+        }
+        @if let Some(secret) = secret {
+            div .mb-4 {
+                div .text-sm .text-gray-600 { "Client secret" }
+                div .font-mono .break-all { (secret) }
+            }
+            p .mb-4 { "Copy the secret now. It will not be shown again." }
+        }
+        a .text-blue-600 .hover:underline href="/admin/applications" { "Done" }
+    };
+
+    shell::document(markup, "applications", session)
+}
+
+#[derive(Deserialize, Validate)]
+struct CreateAppForm {
+    #[validate(length(min = 1, max = 100))]
+    #[validate(non_control_character)]
+    name: String,
+    #[validate(custom(function = "validate_redirect_uris"))]
+    redirect_uris: String,
+    #[serde(default)]
+    confidential: Option<String>,
+}
+
+impl FormPage for CreateAppForm {
+    async fn render(
+        &self,
+        state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        let session = session.ok_or_else(|| anyhow::anyhow!("missing session"))?;
+        applications_page(state, session, Some(self), errors).await
+    }
+}
+
+fn redirect_uri_lines(value: &str) -> Vec<String> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Redirect URIs must be absolute http(s) URLs without a fragment, which
+/// the authorization response could not be appended to.
+fn validate_redirect_uris(value: &str) -> Result<(), ValidationError> {
+    let uris = redirect_uri_lines(value);
+
+    if uris.is_empty() || uris.len() > MAX_REDIRECT_URIS {
+        return Err(ValidationError::new("redirect_uri_count").add_param("max", MAX_REDIRECT_URIS));
+    }
+
+    for uri in &uris {
+        let valid = Url::parse(uri).is_ok_and(|url| {
+            matches!(url.scheme(), "http" | "https") && url.has_host() && url.fragment().is_none()
+        });
+
+        if !valid {
+            return Err(ValidationError::new("redirect_uri").add_param("uri", uri));
+        }
+    }
+
+    Ok(())
+}
+
+async fn do_create(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    ValidatedForm(form): ValidatedForm<CreateAppForm>,
+) -> Result<maud::Markup, AppError> {
+    let name = form.name.trim();
+    let (client_id, secret) = model::oauth::create_app(
+        &state.db,
+        name,
+        &redirect_uri_lines(&form.redirect_uris),
+        form.confidential.is_some(),
+        session.id,
+    )
+    .await?;
+
+    let event = Event::admin(Action::ApplicationCreated, session.id, None, client.ip).detail(name);
+    model::audit::record(&state.db, event).await?;
+
+    Ok(credentials_page(
+        session,
+        name,
+        Some(&client_id),
+        secret.as_deref(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct AppForm {
+    id: i32,
+}
+
+async fn do_reset_secret(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Form(form): Form<AppForm>,
+) -> Result<Response, AppError> {
+    let Some(secret) = model::oauth::reset_secret(&state.db, form.id).await? else {
+        return Ok(Redirect::to("/admin/applications").into_response());
+    };
+
+    let detail = format!("application {}", form.id);
+    let event =
+        Event::admin(Action::ApplicationSecretReset, session.id, None, client.ip).detail(&detail);
+    model::audit::record(&state.db, event).await?;
+
+    Ok(credentials_page(session, "New client secret", None, Some(&secret)).into_response())
+}
+
+async fn do_delete(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Form(form): Form<AppForm>,
+) -> Result<Redirect, AppError> {
+    model::oauth::delete_app(&state.db, form.id).await?;
+
+    let detail = format!("application {}", form.id);
+    let event =
+        Event::admin(Action::ApplicationDeleted, session.id, None, client.ip).detail(&detail);
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to("/admin/applications"))
+}
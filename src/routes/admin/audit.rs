@@ -0,0 +1,173 @@
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Query;
+use axum::http::{HeaderValue, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+
+use super::{Admin, format_time};
+use crate::model;
+use crate::model::audit::Entry;
+use crate::routes::{self, AppError, shell};
+use crate::state::AppState;
+
+const PAGE_SIZE: i64 = 100;
+
+/// Entries fetched at a time while exporting the whole log.
+const EXPORT_BATCH: i64 = 1000;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/audit", get(page_audit))
+        .route("/admin/audit/export", get(export))
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    page: Option<i64>,
+}
+
+async fn page_audit(
+    state: AppState,
+    Admin(session): Admin,
+    Query(query): Query<AuditQuery>,
+) -> Result<Response, AppError> {
+    let (page, offset) = routes::paginate(query.page, PAGE_SIZE);
+
+    // Fetch one extra row to find out whether there is a next page.
+    let mut entries = model::audit::list(&state.db, PAGE_SIZE + 1, offset).await?;
+    let has_next = entries.len() as i64 > PAGE_SIZE;
+    entries.truncate(PAGE_SIZE as usize);
+
+    let markup = maud::html! {
+        (super::admin_nav("audit"))
+
+        h2 .text-xl .mt-4 .mb-2 { "Audit log" }
+        p .text-gray-600 .mb-4 {
+            "Security-relevant events across the site, newest first. "
+            a .text-blue-600 .hover:underline href="/admin/audit/export" { "Export as JSON lines" }
+        }
+
+        @if entries.is_empty() {
+            p .text-gray-600 .mb-4 { "No entries." }
+        } @else {
+            table .w-full .text-sm .mb-4 {
+                thead {
+                    tr .text-left .border-b .border-gray-300 {
+                        th .py-1 { "Time" }
+                        th .py-1 { "Event" }
+                        th .py-1 { "By" }
+                        th .py-1 { "Account" }
+                        th .py-1 { "Address" }
+                    }
+                }
+                tbody {
+                    @for entry in &entries {
+                        tr .border-b .border-gray-200 {
+                            td .py-1 .whitespace-nowrap { (format_time(entry.created_at)) }
+                            td .py-1 {
+                                (entry.describe())
+                                @if !entry.detail.is_empty() {
+                                    div .text-gray-500 .truncate .max-w-md { (entry.detail) }
+                                }
+                            }
+                            td .py-1 { (user_link(entry.actor.as_deref())) }
+                            td .py-1 { (user_link(entry.username.as_deref())) }
+                            td .py-1 .font-mono { (entry.ip.as_deref().unwrap_or("-")) }
+                        }
+                    }
+                }
+            }
+        }
+
+        div .flex .gap-4 {
+            @if page > 1 {
+                a .text-blue-600 .hover:underline href={ "/admin/audit?page=" (page - 1) } { "← Previous" }
+            }
+            @if has_next {
+                a .text-blue-600 .hover:underline href={ "/admin/audit?page=" (page + 1) } { "Next →" }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "audit log", session).into_response())
+}
+
+fn user_link(username: Option<&str>) -> maud::Markup {
+    maud::html! {
+        @if let Some(username) = username {
+            a .text-blue-600 .hover:underline href={ "/admin/users/" (username) } { (username) }
+        } @else {
+            "-"
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportEntry<'a> {
+    id: i64,
+    time: String,
+    action: &'a str,
+    actor: Option<&'a str>,
+    user: Option<&'a str>,
+    ip: Option<&'a str>,
+    detail: &'a str,
+}
+
+impl<'a> From<&'a Entry> for ExportEntry<'a> {
+    fn from(entry: &'a Entry) -> Self {
+        Self {
+            id: entry.id,
+            time: entry.created_at.format(&Rfc3339).unwrap_or_default(),
+            action: &entry.action,
+            actor: entry.actor.as_deref(),
+            user: entry.username.as_deref(),
+            ip: entry.ip.as_deref(),
+            detail: &entry.detail,
+        }
+    }
+}
+
+/// The whole log, oldest first, one JSON object per line. It is read in
+/// batches so that large logs are not held in memory.
+async fn export(state: AppState, _admin: Admin) -> Response {
+    let db = state.db.clone();
+    let lines = stream::try_unfold(Some(0), move |after| {
+        let db = db.clone();
+        async move {
+            let Some(after) = after else {
+                return Ok(None);
+            };
+
+            let entries = model::audit::list_after(&db, after, EXPORT_BATCH).await?;
+            let Some(last) = entries.last() else {
+                return Ok(None);
+            };
+            let next = (entries.len() as i64 == EXPORT_BATCH).then_some(last.id);
+
+            let mut buf = Vec::new();
+            for entry in &entries {
+                serde_json::to_writer(&mut buf, &ExportEntry::from(entry))?;
+                buf.push(b'\n');
+            }
+
+            Ok::<_, anyhow::Error>(Some((buf, next)))
+        }
+    });
+
+    let mut response = Body::from_stream(lines).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"audit-log.jsonl\""),
+    );
+
+    response
+}
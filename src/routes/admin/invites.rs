@@ -0,0 +1,147 @@
+use axum::Router;
+use axum::extract::{Form, Query};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+
+use super::Admin;
+use crate::config::RegistrationMode;
+use crate::middleware::client::ClientInfo;
+use crate::model;
+use crate::model::audit::{Action, Event};
+use crate::routes::{self, AppError, form, shell};
+use crate::state::AppState;
+
+const PAGE_SIZE: i64 = 50;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/invites", get(page_invites))
+        .route("/admin/invites/revoke", post(do_revoke))
+}
+
+#[derive(Deserialize)]
+struct InvitesQuery {
+    page: Option<i64>,
+}
+
+async fn page_invites(
+    state: AppState,
+    Admin(session): Admin,
+    Query(query): Query<InvitesQuery>,
+) -> Result<Response, AppError> {
+    let (page, offset) = routes::paginate(query.page, PAGE_SIZE);
+
+    // Fetch one extra row to find out whether there is a next page.
+    let mut invites = model::invite::list(&state.db, PAGE_SIZE + 1, offset).await?;
+    let has_next = invites.len() as i64 > PAGE_SIZE;
+    invites.truncate(PAGE_SIZE as usize);
+
+    let registration = &state.config.registration;
+    let mode = match registration.mode {
+        RegistrationMode::Open => "open to everyone",
+        RegistrationMode::Closed => "closed",
+        RegistrationMode::Invite => "by invite only",
+        RegistrationMode::Domain => "limited to allowed email domains",
+    };
+
+    let markup = maud::html! {
+        (super::admin_nav("invites"))
+
+        h2 .text-xl .mt-4 .mb-2 { "Invites" }
+        p .text-gray-600 .mb-4 {
+            "Registration is " (mode) "."
+            @if registration.user_invites {
+                " Every user can issue invites."
+            } @else {
+                " Only administrators can issue invites."
+            }
+            " Issue your own from "
+            a .text-blue-600 .hover:underline href="/meta/invites" { "your settings" }
+            "."
+        }
+
+        @if invites.is_empty() {
+            p .text-gray-600 .mb-4 { "No invites." }
+        } @else {
+            table .w-full .text-sm .mb-4 {
+                thead {
+                    tr .text-left .border-b .border-gray-300 {
+                        th .py-1 { "Issued by" }
+                        th .py-1 { "Note" }
+                        th .py-1 { "Created" }
+                        th .py-1 { "Status" }
+                        th .py-1 {}
+                    }
+                }
+                tbody {
+                    @for invite in &invites {
+                        tr .border-b .border-gray-200 {
+                            td .py-1 {
+                                a .text-blue-600 .hover:underline href={ "/admin/users/" (invite.created_by) } {
+                                    (invite.created_by)
+                                }
+                            }
+                            td .py-1 { (invite.note) }
+                            td .py-1 { (invite.created_at.date()) }
+                            td .py-1 {
+                                @if let Some(used_at) = invite.used_at {
+                                    "used " (used_at.date())
+                                    @if let Some(used_by) = &invite.used_by {
+                                        " by "
+                                        a .text-blue-600 .hover:underline href={ "/admin/users/" (used_by) } {
+                                            (used_by)
+                                        }
+                                    }
+                                } @else if invite.is_expired() {
+                                    span .text-gray-500 { "expired" }
+                                } @else {
+                                    "expires " (invite.expires.date())
+                                }
+                            }
+                            td .py-1 .text-right {
+                                @if invite.used_at.is_none() {
+                                    form method="post" action="/admin/invites/revoke" {
+                                        (form::csrf_field())
+                                        input type="hidden" name="id" value=(invite.id);
+                                        button .text-red-600 .hover:underline type="submit" { "revoke" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        div .flex .gap-4 {
+            @if page > 1 {
+                a .text-blue-600 .hover:underline href={ "/admin/invites?page=" (page - 1) } { "← Previous" }
+            }
+            @if has_next {
+                a .text-blue-600 .hover:underline href={ "/admin/invites?page=" (page + 1) } { "Next →" }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "invites", session).into_response())
+}
+
+#[derive(Deserialize)]
+struct RevokeForm {
+    id: i32,
+}
+
+async fn do_revoke(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Form(form): Form<RevokeForm>,
+) -> Result<Redirect, AppError> {
+    model::invite::revoke(&state.db, form.id, None).await?;
+
+    let detail = format!("invite {}", form.id);
+    let event = Event::admin(Action::InviteRevoked, session.id, None, client.ip).detail(&detail);
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to("/admin/invites"))
+}
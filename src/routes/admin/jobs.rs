@@ -0,0 +1,75 @@
+use axum::Router;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use time::OffsetDateTime;
+
+use super::{Admin, format_time};
+use crate::jobs;
+use crate::routes::{AppError, shell};
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/admin/jobs", get(page_jobs))
+}
+
+async fn page_jobs(state: AppState, Admin(session): Admin) -> Result<Response, AppError> {
+    let jobs = jobs::status(&state).await?;
+    let now = OffsetDateTime::now_utc();
+
+    let markup = maud::html! {
+        (super::admin_nav("jobs"))
+
+        h2 .text-xl .mt-4 .mb-2 { "Scheduled jobs" }
+        p .text-gray-600 .mb-4 {
+            "Jobs are checked every few minutes and run once their interval has passed "
+            "since they last completed."
+        }
+
+        table .w-full .text-sm .mb-4 {
+            thead {
+                tr .text-left .border-b .border-gray-300 {
+                    th .py-1 { "Job" }
+                    th .py-1 { "Interval" }
+                    th .py-1 { "Last run" }
+                    th .py-1 { "Status" }
+                }
+            }
+            tbody {
+                @for job in &jobs {
+                    tr .border-b .border-gray-200 {
+                        td .py-1 .font-mono { (job.name) }
+                        td .py-1 { (format_interval(job.interval)) }
+                        td .py-1 {
+                            @match job.last_run {
+                                Some(last_run) => (format_time(last_run)),
+                                None => span .text-gray-500 { "never" },
+                            }
+                        }
+                        td .py-1 {
+                            @match job.last_run {
+                                // Allow for the scheduler's check interval
+                                // before calling a job late.
+                                Some(last_run) if (now - last_run).whole_seconds() > (job.interval * 2 + 600) as i64 => {
+                                    span .text-red-600 { "overdue" }
+                                }
+                                Some(_) => span .text-green-700 { "ok" },
+                                None => span .text-gray-500 { "pending" },
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "jobs", session).into_response())
+}
+
+fn format_interval(secs: u64) -> String {
+    match secs {
+        s if s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
@@ -0,0 +1,135 @@
+mod applications;
+mod audit;
+mod invites;
+mod jobs;
+mod pastes;
+mod repositories;
+mod users;
+
+use axum::Router;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::get;
+use time::OffsetDateTime;
+
+use crate::middleware::auth::Session;
+use crate::routes::AppError;
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .merge(users::routes())
+        .merge(repositories::routes())
+        .merge(pastes::routes())
+        .merge(applications::routes())
+        .merge(invites::routes())
+        .merge(jobs::routes())
+        .merge(audit::routes())
+        .route("/admin", get(admin_redirect))
+}
+
+/// A logged in administrator. Anyone else is turned away.
+pub struct Admin(pub Session);
+
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        if !session.is_admin {
+            return Err(AppError::Forbidden.into_response());
+        }
+
+        Ok(Admin(session))
+    }
+}
+
+fn admin_nav(current: &str) -> maud::Markup {
+    let items = [
+        ("users", "/admin/users"),
+        ("repositories", "/admin/repositories"),
+        ("pastes", "/admin/pastes"),
+        ("applications", "/admin/applications"),
+        ("invites", "/admin/invites"),
+        ("jobs", "/admin/jobs"),
+        ("audit", "/admin/audit"),
+    ];
+
+    maud::html! {
+        div .border-b .border-gray-300 .mb-3 {
+            ul .flex .gap-1 .text-sm {
+                @for (name, href) in items {
+                    @if name == current {
+                        li {
+                            a
+                                .block
+                                .px-2
+                                .py-1
+                                .bg-gray-200
+                                .text-black
+                                .border
+                                .border-gray-300
+                                href=(href)
+                            {
+                                (name)
+                            }
+                        }
+                    } @else {
+                        li {
+                            a
+                                .block
+                                .px-2
+                                .py-1
+                                .text-gray-600
+                                .hover:text-black
+                                .hover:bg-gray-100
+                                .border
+                                .border-transparent
+                                href=(href)
+                            {
+                                (name)
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+    format!(
+        "{} {:02}:{:02} UTC",
+        time.date(),
+        time.hour(),
+        time.minute()
+    )
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+async fn admin_redirect() -> Redirect {
+    Redirect::to("/admin/users")
+}
@@ -0,0 +1,86 @@
+use axum::Router;
+use axum::extract::Query;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde::Deserialize;
+
+use super::{Admin, format_size};
+use crate::model;
+use crate::routes::{self, AppError, shell};
+use crate::state::AppState;
+
+const PAGE_SIZE: i64 = 50;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/admin/pastes", get(page_pastes))
+}
+
+#[derive(Deserialize)]
+struct PastesQuery {
+    page: Option<i64>,
+}
+
+async fn page_pastes(
+    state: AppState,
+    Admin(session): Admin,
+    Query(query): Query<PastesQuery>,
+) -> Result<Response, AppError> {
+    let (page, offset) = routes::paginate(query.page, PAGE_SIZE);
+
+    // Fetch one extra row to find out whether there is a next page.
+    let mut pastes = model::paste::list_by_size(&state.db, PAGE_SIZE + 1, offset).await?;
+    let has_next = pastes.len() as i64 > PAGE_SIZE;
+    pastes.truncate(PAGE_SIZE as usize);
+
+    let markup = maud::html! {
+        (super::admin_nav("pastes"))
+
+        h2 .text-xl .mt-4 .mb-4 { "Pastes" }
+
+        @if pastes.is_empty() {
+            p .text-gray-600 .mb-4 { "No pastes." }
+        } @else {
+            table .w-full .text-sm .mb-4 {
+                thead {
+                    tr .text-left .border-b .border-gray-300 {
+                        th .py-1 { "Paste" }
+                        th .py-1 { "Visibility" }
+                        th .py-1 { "Created" }
+                        th .py-1 .text-right { "Files" }
+                        th .py-1 .text-right { "Size" }
+                    }
+                }
+                tbody {
+                    @for paste in &pastes {
+                        tr .border-b .border-gray-200 {
+                            td .py-1 .font-mono {
+                                a .text-blue-600 .hover:underline href={ "/admin/users/" (paste.username) } {
+                                    (paste.username)
+                                }
+                                "/"
+                                a .text-blue-600 .hover:underline href={ "/~" (paste.username) "/paste/" (paste.id) } {
+                                    (paste.id)
+                                }
+                            }
+                            td .py-1 { (paste.visibility) }
+                            td .py-1 { (paste.created_at.date()) }
+                            td .py-1 .text-right { (paste.file_count) }
+                            td .py-1 .text-right { (format_size(paste.size.max(0) as u64)) }
+                        }
+                    }
+                }
+            }
+        }
+
+        div .flex .gap-4 {
+            @if page > 1 {
+                a .text-blue-600 .hover:underline href={ "/admin/pastes?page=" (page - 1) } { "← Previous" }
+            }
+            @if has_next {
+                a .text-blue-600 .hover:underline href={ "/admin/pastes?page=" (page + 1) } { "Next →" }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "pastes", session).into_response())
+}
@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use axum::Router;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use tokio::task;
+
+use super::{Admin, format_size};
+use crate::routes::{AppError, shell};
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/admin/repositories", get(page_repositories))
+}
+
+struct Repository {
+    owner: String,
+    name: String,
+    size: u64,
+    lfs_size: u64,
+}
+
+async fn page_repositories(state: AppState, Admin(session): Admin) -> Result<Response, AppError> {
+    let repository_path = state.config.git.repository_path.clone();
+    let lfs_path = state.config.git.lfs_path.clone();
+    let repositories = task::spawn_blocking(move || scan(&repository_path, &lfs_path))
+        .await
+        .map_err(anyhow::Error::from)?
+        .map_err(anyhow::Error::from)?;
+
+    let total: u64 = repositories.iter().map(|r| r.size + r.lfs_size).sum();
+
+    let markup = maud::html! {
+        (super::admin_nav("repositories"))
+
+        h2 .text-xl .mt-4 .mb-2 { "Repositories" }
+        p .text-gray-600 .mb-4 {
+            (repositories.len()) " repositories using " (format_size(total)) ", largest first."
+        }
+
+        @if !repositories.is_empty() {
+            table .w-full .text-sm .mb-4 {
+                thead {
+                    tr .text-left .border-b .border-gray-300 {
+                        th .py-1 { "Repository" }
+                        th .py-1 .text-right { "Git" }
+                        th .py-1 .text-right { "LFS" }
+                    }
+                }
+                tbody {
+                    @for repository in &repositories {
+                        tr .border-b .border-gray-200 {
+                            td .py-1 .font-mono {
+                                a .text-blue-600 .hover:underline href={ "/admin/users/" (repository.owner) } {
+                                    (repository.owner)
+                                }
+                                "/" (repository.name)
+                            }
+                            td .py-1 .text-right { (format_size(repository.size)) }
+                            td .py-1 .text-right { (format_size(repository.lfs_size)) }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "repositories", session).into_response())
+}
+
+/// Find every repository on disk, as `<owner>/<name>` directories under the
+/// repository path, along with the LFS objects stored for it.
+fn scan(repository_path: &Path, lfs_path: &Path) -> io::Result<Vec<Repository>> {
+    let mut repositories = Vec::new();
+
+    for owner in subdirectories(repository_path)? {
+        for repo in subdirectories(&owner)? {
+            let owner_name = file_name(&owner);
+            let name = file_name(&repo);
+            let lfs_size = dir_size(&lfs_path.join(&owner_name).join(&name))?;
+
+            repositories.push(Repository {
+                size: dir_size(&repo)?,
+                lfs_size,
+                owner: owner_name,
+                name,
+            });
+        }
+    }
+
+    repositories.sort_by_key(|r| std::cmp::Reverse(r.size + r.lfs_size));
+    Ok(repositories)
+}
+
+fn subdirectories(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+
+    Ok(dirs)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The total size of the files under a directory. Symbolic links are not
+/// followed.
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
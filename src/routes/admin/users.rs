@@ -0,0 +1,480 @@
+use axum::Router;
+use axum::extract::{Form, Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+
+use super::{Admin, format_time};
+use crate::middleware::auth::Session;
+use crate::middleware::client::ClientInfo;
+use crate::model::audit::{Action, Event};
+use crate::model::user::UserSummary;
+use crate::routes::form::{Locale, field_errors};
+use crate::routes::{self, AppError, form, shell};
+use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+use crate::{account, model};
+
+const PAGE_SIZE: i64 = 50;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/users", get(page_users))
+        .route("/admin/users/{username}", get(page_user))
+        .route("/admin/users/{username}/suspend", post(do_suspend))
+        .route("/admin/users/{username}/unsuspend", post(do_unsuspend))
+        .route("/admin/users/{username}/admin", post(do_set_admin))
+        .route("/admin/users/{username}/delete", post(do_delete))
+        .route("/admin/users/{username}/keys/revoke", post(do_revoke_key))
+        .route(
+            "/admin/users/{username}/sessions/revoke",
+            post(do_revoke_session),
+        )
+        .route(
+            "/admin/users/{username}/sessions/revoke-all",
+            post(do_revoke_all_sessions),
+        )
+}
+
+#[derive(Deserialize)]
+struct UsersQuery {
+    q: Option<String>,
+    page: Option<i64>,
+}
+
+async fn page_users(
+    state: AppState,
+    Admin(session): Admin,
+    Query(query): Query<UsersQuery>,
+) -> Result<Response, AppError> {
+    let search = query.q.as_deref().map(str::trim).unwrap_or_default();
+    let (page, offset) = routes::paginate(query.page, PAGE_SIZE);
+
+    // Fetch one extra row to find out whether there is a next page.
+    let mut users = model::user::search(&state.db, search, PAGE_SIZE + 1, offset).await?;
+    let has_next = users.len() as i64 > PAGE_SIZE;
+    users.truncate(PAGE_SIZE as usize);
+
+    let markup = maud::html! {
+        (super::admin_nav("users"))
+
+        form method="get" action="/admin/users" .mt-4 .mb-4 .flex .gap-2 {
+            input
+                .border-solid
+                .border-1
+                .border-gray-300
+                .grow
+                .p-2
+                type="search"
+                name="q"
+                placeholder="Search usernames, emails and names"
+                value=(search);
+            input
+                .text-neutral-50
+                .bg-blue-500
+                .hover:bg-blue-600
+                .border-neutral-700
+                .border-solid
+                .border-1
+                .px-4
+                .py-2
+                .cursor-pointer
+                type="submit"
+                value="Search";
+        }
+
+        @if users.is_empty() {
+            p .text-gray-600 .mb-4 { "No users match your search." }
+        } @else {
+            div .mb-4 {
+                @for user in &users {
+                    div .border-solid .border-1 .border-gray-300 .p-2 .mb-2 .flex .justify-between .items-center {
+                        div .flex-1 .overflow-hidden {
+                            a .font-semibold .text-blue-600 .hover:underline href={ "/admin/users/" (user.username) } {
+                                (user.username)
+                            }
+                            span .text-gray-600 .ml-2 { (user.display_name) }
+                            (badges(user))
+                            div .text-sm .text-gray-600 { (user.email) }
+                        }
+                        span .text-gray-500 .text-sm { (user.created_at.date()) }
+                    }
+                }
+            }
+        }
+
+        div .flex .gap-4 {
+            @if page > 1 {
+                a .text-blue-600 .hover:underline href=(page_href(search, page - 1)) { "← Previous" }
+            }
+            @if has_next {
+                a .text-blue-600 .hover:underline href=(page_href(search, page + 1)) { "Next →" }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "users", session).into_response())
+}
+
+fn page_href(search: &str, page: i64) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if !search.is_empty() {
+        query.append_pair("q", search);
+    }
+    query.append_pair("page", &page.to_string());
+    format!("/admin/users?{}", query.finish())
+}
+
+fn badges(user: &UserSummary) -> maud::Markup {
+    maud::html! {
+        @if user.is_admin {
+            span .ml-2 .text-xs .bg-blue-100 .text-blue-800 .px-2 .py-1 .rounded { "admin" }
+        }
+        @if user.suspended_at.is_some() {
+            span .ml-2 .text-xs .bg-red-100 .text-red-800 .px-2 .py-1 .rounded { "suspended" }
+        }
+    }
+}
+
+async fn page_user(
+    state: AppState,
+    Admin(session): Admin,
+    Path(username): Path<String>,
+) -> Result<Response, AppError> {
+    let user = get_user(&state, &username).await?;
+    let markup = user_page(&state, session, &user, &ValidationErrors::new()).await?;
+    Ok(markup.into_response())
+}
+
+async fn user_page(
+    state: &AppState,
+    session: Session,
+    user: &UserSummary,
+    errors: &ValidationErrors,
+) -> Result<maud::Markup, AppError> {
+    let keys = model::user::get_user_keys(&state.db, user.id).await?;
+    let sessions = model::session::list_for_user(&state.db, user.id).await?;
+    let paste_storage = model::paste::storage_used(&state.db, user.id).await?;
+    let base = format!("/admin/users/{}", user.username);
+    let is_self = user.id == session.id;
+
+    let markup = maud::html! {
+        (super::admin_nav("users"))
+
+        h2 .text-xl .mt-4 .mb-2 {
+            (user.username)
+            (badges(user))
+        }
+        div .mb-4 .text-gray-600 {
+            div { (user.display_name) " <" (user.email) ">" }
+            div { "Registered " (format_time(user.created_at)) }
+            @if let Some(suspended_at) = user.suspended_at {
+                div { "Suspended " (format_time(suspended_at)) }
+            }
+            div { "Pastes use " (super::format_size(paste_storage.max(0) as u64)) }
+        }
+
+        @if !is_self {
+            div .flex .gap-4 .mb-4 {
+                @if user.suspended_at.is_some() {
+                    form method="post" action={ (base) "/unsuspend" } {
+                        (form::csrf_field())
+                        button .text-blue-600 .hover:underline type="submit" { "unsuspend" }
+                    }
+                } @else {
+                    form method="post" action={ (base) "/suspend" } {
+                        (form::csrf_field())
+                        button .text-red-600 .hover:underline type="submit" { "suspend" }
+                    }
+                }
+                form method="post" action={ (base) "/admin" } {
+                    (form::csrf_field())
+                    @if user.is_admin {
+                        input type="hidden" name="is_admin" value="false";
+                        button .text-blue-600 .hover:underline type="submit" { "remove admin" }
+                    } @else {
+                        input type="hidden" name="is_admin" value="true";
+                        button .text-blue-600 .hover:underline type="submit" { "make admin" }
+                    }
+                }
+            }
+        }
+
+        h3 .text-lg .mt-6 .mb-2 { "SSH keys" }
+        @if keys.is_empty() {
+            p .text-gray-600 .mb-4 { "No SSH keys." }
+        } @else {
+            div .mb-4 {
+                @for key in &keys {
+                    div .border-solid .border-1 .border-gray-300 .p-2 .mb-2 .flex .justify-between .items-start {
+                        div .flex-1 .overflow-hidden {
+                            div .font-semibold .mb-1 {
+                                (key.name)
+                                @if key.managed {
+                                    span .ml-2 .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded { "managed" }
+                                }
+                            }
+                            div .font-mono .text-sm .break-all {
+                                span .text-gray-600 { (key.key_type) " " }
+                                (key.fingerprint)
+                            }
+                            div .text-sm .text-gray-600 .mt-1 {
+                                @if let Some(last_used_at) = key.last_used_at {
+                                    "last used " (format_time(last_used_at))
+                                } @else {
+                                    "never used"
+                                }
+                                @if let Some(expires_at) = key.expires_at {
+                                    ", expires " (format_time(expires_at))
+                                }
+                            }
+                        }
+                        form method="post" action={ (base) "/keys/revoke" } .ml-2 {
+                            (form::csrf_field())
+                            input type="hidden" name="key_type" value=(key.key_type);
+                            input type="hidden" name="encoded" value=(key.encoded);
+                            button .text-red-600 .hover:underline .text-sm type="submit" { "revoke" }
+                        }
+                    }
+                }
+            }
+        }
+
+        h3 .text-lg .mt-6 .mb-2 { "Sessions" }
+        @if sessions.is_empty() {
+            p .text-gray-600 .mb-4 { "No active sessions." }
+        } @else {
+            div .mb-2 {
+                @for item in &sessions {
+                    div .border-solid .border-1 .border-gray-300 .p-2 .mb-2 .flex .justify-between .items-start {
+                        div .flex-1 .overflow-hidden {
+                            div .font-semibold .mb-1 { (item.ip.as_deref().unwrap_or("unknown address")) }
+                            div .text-sm .text-gray-600 .truncate {
+                                (item.user_agent.as_deref().unwrap_or("unknown browser"))
+                            }
+                            div .text-sm .text-gray-600 .mt-1 {
+                                "Signed in " (format_time(item.created_at)) ", last seen " (format_time(item.last_seen))
+                            }
+                        }
+                        form method="post" action={ (base) "/sessions/revoke" } .ml-2 {
+                            (form::csrf_field())
+                            input type="hidden" name="id" value=(item.id);
+                            button .text-red-600 .hover:underline .text-sm type="submit" { "revoke" }
+                        }
+                    }
+                }
+            }
+            form method="post" action={ (base) "/sessions/revoke-all" } .mb-4 {
+                (form::csrf_field())
+                button .text-red-600 .hover:underline type="submit" { "revoke all sessions" }
+            }
+        }
+
+        @if !is_self {
+            h3 .text-lg .mt-6 .mb-2 { "Delete user" }
+            p .text-gray-600 .mb-2 {
+                "Deletes the account, its pastes, repositories and LFS objects. This cannot be undone."
+            }
+            form method="post" action={ (base) "/delete" } .max-w-md {
+                (form::csrf_field())
+                div .mb-2 {
+                    label for="confirm" .block .mb-1 { "Type the username to confirm" }
+                    input .border-solid .border-1 .border-gray-300 .p-2 .w-full
+                        type="text" name="confirm" id="confirm" autocomplete="off";
+                    (field_errors(errors, "confirm"))
+                }
+                input
+                    .text-neutral-50
+                    .bg-red-600
+                    .hover:bg-red-700
+                    .border-neutral-700
+                    .border-solid
+                    .border-1
+                    .px-3
+                    .py-1
+                    .cursor-pointer
+                    type="submit"
+                    value="Delete user";
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "users", session))
+}
+
+async fn get_user(state: &AppState, username: &str) -> Result<UserSummary, AppError> {
+    model::user::get_summary(&state.db, username)
+        .await?
+        .ok_or(AppError::NotFound)
+}
+
+/// Look up the target of an action that admins may not take against their
+/// own account.
+async fn get_other_user(
+    state: &AppState,
+    session: &Session,
+    username: &str,
+) -> Result<UserSummary, AppError> {
+    let user = get_user(state, username).await?;
+    if user.id == session.id {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(user)
+}
+
+fn user_href(user: &UserSummary) -> String {
+    format!("/admin/users/{}", user.username)
+}
+
+async fn do_suspend(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Path(username): Path<String>,
+) -> Result<Redirect, AppError> {
+    let user = get_other_user(&state, &session, &username).await?;
+    model::user::suspend(&state.db, user.id).await?;
+
+    let event = Event::admin(Action::UserSuspended, session.id, Some(user.id), client.ip);
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to(&user_href(&user)))
+}
+
+async fn do_unsuspend(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Path(username): Path<String>,
+) -> Result<Redirect, AppError> {
+    let user = get_other_user(&state, &session, &username).await?;
+    model::user::unsuspend(&state.db, user.id).await?;
+
+    let event = Event::admin(
+        Action::UserUnsuspended,
+        session.id,
+        Some(user.id),
+        client.ip,
+    );
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to(&user_href(&user)))
+}
+
+#[derive(Deserialize)]
+struct SetAdminForm {
+    is_admin: bool,
+}
+
+async fn do_set_admin(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Path(username): Path<String>,
+    Form(form): Form<SetAdminForm>,
+) -> Result<Redirect, AppError> {
+    let user = get_other_user(&state, &session, &username).await?;
+    model::user::set_admin(&state.db, user.id, form.is_admin).await?;
+
+    let action = if form.is_admin {
+        Action::AdminGranted
+    } else {
+        Action::AdminRevoked
+    };
+    let event = Event::admin(action, session.id, Some(user.id), client.ip);
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to(&user_href(&user)))
+}
+
+#[derive(Deserialize)]
+struct DeleteForm {
+    confirm: String,
+}
+
+async fn do_delete(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Path(username): Path<String>,
+    Locale(catalog): Locale,
+    Form(form): Form<DeleteForm>,
+) -> Result<Response, AppError> {
+    let user = get_other_user(&state, &session, &username).await?;
+
+    if form.confirm.trim() != user.username {
+        let mut errors = ValidationErrors::new();
+        errors.add("confirm", ValidationError::new("username_mismatch"));
+        let markup = user_page(&state, session, &user, &errors.localize(catalog)).await?;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, markup).into_response());
+    }
+
+    // Recorded first, while the account's username can still be looked up.
+    let event = Event::admin(Action::UserDeleted, session.id, Some(user.id), client.ip);
+    model::audit::record(&state.db, event).await?;
+
+    account::delete(&state, user.id, &user.username).await?;
+    Ok(Redirect::to("/admin/users").into_response())
+}
+
+#[derive(Deserialize)]
+struct RevokeKeyForm {
+    key_type: String,
+    encoded: String,
+}
+
+async fn do_revoke_key(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Path(username): Path<String>,
+    Form(form): Form<RevokeKeyForm>,
+) -> Result<Redirect, AppError> {
+    let user = get_user(&state, &username).await?;
+    let revoked =
+        model::user::revoke_user_key(&state.db, user.id, &form.key_type, &form.encoded).await?;
+    if !revoked {
+        return Ok(Redirect::to(&user_href(&user)));
+    }
+
+    let key = format!("{} {}", form.key_type, form.encoded);
+    let event = Event::admin(Action::KeyRemoved, session.id, Some(user.id), client.ip).detail(&key);
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to(&user_href(&user)))
+}
+
+#[derive(Deserialize)]
+struct RevokeSessionForm {
+    id: i64,
+}
+
+async fn do_revoke_session(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Path(username): Path<String>,
+    Form(form): Form<RevokeSessionForm>,
+) -> Result<Redirect, AppError> {
+    let user = get_user(&state, &username).await?;
+    model::session::revoke(&state.db, user.id, form.id).await?;
+
+    let detail = format!("session {}", form.id);
+    let event =
+        Event::admin(Action::SessionRevoked, session.id, Some(user.id), client.ip).detail(&detail);
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to(&user_href(&user)))
+}
+
+async fn do_revoke_all_sessions(
+    state: AppState,
+    Admin(session): Admin,
+    client: ClientInfo,
+    Path(username): Path<String>,
+) -> Result<Redirect, AppError> {
+    let user = get_user(&state, &username).await?;
+    model::session::delete_for_user(&state.db, user.id).await?;
+
+    let event = Event::admin(Action::SessionRevoked, session.id, Some(user.id), client.ip)
+        .detail("all sessions");
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to(&user_href(&user)))
+}
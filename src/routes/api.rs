@@ -0,0 +1,141 @@
+use axum::Router;
+use axum::extract::{FromRequestParts, Json};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde::Serialize;
+
+use crate::model;
+use crate::model::oauth::{AccessToken, Scope};
+use crate::routes::AppError;
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/user", get(user))
+        .route("/api/user/keys", get(user_keys))
+}
+
+/// An OAuth access token from the `Authorization` header.
+struct Token(AccessToken);
+
+impl FromRequestParts<AppState> for Token {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        let Some(token) = token else {
+            return Err(unauthorized(None));
+        };
+
+        match model::oauth::use_access_token(&state.db, token.trim()).await {
+            Ok(Some(token)) => Ok(Token(token)),
+            Ok(None) => Err(unauthorized(Some("invalid_token"))),
+            Err(err) => Err(AppError::from(err).into_response()),
+        }
+    }
+}
+
+impl Token {
+    /// The response to send if the token was not granted a scope.
+    fn insufficient_scope(&self, scope: Scope) -> Option<Response> {
+        if self.0.scopes.contains(&scope) {
+            return None;
+        }
+
+        let challenge = format!(
+            "Bearer error=\"insufficient_scope\", scope=\"{}\"",
+            scope.as_str()
+        );
+        let mut response = StatusCode::FORBIDDEN.into_response();
+        if let Ok(value) = HeaderValue::from_str(&challenge) {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, value);
+        }
+
+        Some(response)
+    }
+}
+
+fn unauthorized(error: Option<&str>) -> Response {
+    let challenge = match error {
+        Some(error) => format!("Bearer realm=\"conduit\", error=\"{}\"", error),
+        None => "Bearer realm=\"conduit\"".to_owned(),
+    };
+
+    let mut response = StatusCode::UNAUTHORIZED.into_response();
+    if let Ok(value) = HeaderValue::from_str(&challenge) {
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, value);
+    }
+
+    response
+}
+
+#[derive(Serialize)]
+struct User {
+    id: String,
+    username: String,
+    display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_verified: Option<bool>,
+}
+
+async fn user(state: AppState, token: Token) -> Result<Response, AppError> {
+    if let Some(response) = token.insufficient_scope(Scope::Profile) {
+        return Ok(response);
+    }
+
+    let Some(profile) = model::user::get_profile(&state.db, token.0.user_id).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let email = token.0.scopes.contains(&Scope::Email);
+    let user = User {
+        id: token.0.user_id.to_string(),
+        username: profile.username,
+        display_name: profile.display_name,
+        email: email.then_some(profile.email),
+        email_verified: email.then_some(profile.email_verified),
+    };
+
+    Ok(Json(user).into_response())
+}
+
+#[derive(Serialize)]
+struct Key {
+    name: String,
+    key: String,
+    fingerprint: String,
+}
+
+async fn user_keys(state: AppState, token: Token) -> Result<Response, AppError> {
+    if let Some(response) = token.insufficient_scope(Scope::Keys) {
+        return Ok(response);
+    }
+
+    let keys = model::user::get_user_keys(&state.db, token.0.user_id).await?;
+    let keys: Vec<_> = keys
+        .into_iter()
+        .map(|key| Key {
+            name: key.name,
+            key: format!("{} {}", key.key_type, key.encoded),
+            fingerprint: key.fingerprint,
+        })
+        .collect();
+
+    Ok(Json(keys).into_response())
+}
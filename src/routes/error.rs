@@ -1,10 +1,18 @@
-use axum::http::StatusCode;
+use std::time::Duration;
+
+use axum::http::{StatusCode, header};
 use axum::response::{IntoResponse, Response};
 use thiserror::Error;
 
 // TODO: improve error handling
 #[derive(Debug, Error)]
 pub enum AppError {
+    #[error("not found")]
+    NotFound,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("too many requests")]
+    TooManyRequests(Duration),
     #[error("internal server error: {0}")]
     Internal(#[from] anyhow::Error),
 }
@@ -12,6 +20,17 @@ pub enum AppError {
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         match self {
+            AppError::NotFound => (StatusCode::NOT_FOUND, "Not Found").into_response(),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden").into_response(),
+            AppError::TooManyRequests(retry_after) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    header::RETRY_AFTER,
+                    retry_after.as_secs().max(1).to_string(),
+                )],
+                "Too Many Requests",
+            )
+                .into_response(),
             AppError::Internal(err) => {
                 let message = format!("{}", err);
                 (StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
@@ -0,0 +1,292 @@
+use std::convert::Infallible;
+
+use axum::Json;
+use axum::extract::{Form, FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::middleware::auth::Session;
+use crate::middleware::csrf;
+use crate::routes::AppError;
+use crate::state::AppState;
+use crate::validate::{
+    Catalog, Constraints, FieldConstraints, Validate, ValidateAsync, ValidationErrors,
+};
+
+/// A form that knows how to render the page it was submitted from, so that
+/// validation failures can be shown next to the offending fields.
+pub trait FormPage: Validate + ValidateAsync + DeserializeOwned + Send + Sync {
+    fn render(
+        &self,
+        state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> impl Future<Output = Result<maud::Markup, AppError>> + Send;
+}
+
+/// Extracts and validates a url-encoded form, running both the synchronous
+/// and the state-dependent validators. If validation fails the originating
+/// page is re-rendered with the submitted values and errors, or the errors
+/// are returned as JSON to clients that ask for it. Messages are localized
+/// according to `Accept-Language`.
+pub struct ValidatedForm<T>(pub T);
+
+impl<T: FormPage> FromRequest<AppState> for ValidatedForm<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        let session = req.extensions().get::<Session>().cloned();
+        let json = wants_json(req.headers());
+        let Locale(catalog) = Locale::from_headers(req.headers());
+        let Form(form) = Form::<T>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let mut errors = form.validate().err().unwrap_or_default();
+        if let Err(async_errors) = form.validate_async(state).await {
+            errors.merge(async_errors);
+        }
+
+        if errors.is_empty() {
+            return Ok(Self(form));
+        }
+
+        if json {
+            Err(json_errors(&errors.localize(catalog)))
+        } else {
+            Err(rerender(&form, state, session, Locale(catalog), errors).await)
+        }
+    }
+}
+
+/// The message catalog negotiated from `Accept-Language`, for handlers that
+/// report errors of their own.
+#[derive(Clone, Copy)]
+pub struct Locale(pub &'static Catalog);
+
+impl Locale {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        Self(Catalog::negotiate(
+            headers
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok()),
+        ))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Locale {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    errors: &'a ValidationErrors,
+}
+
+/// The JSON counterpart of a re-rendered form page.
+pub fn json_errors(errors: &ValidationErrors) -> Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorBody { errors })).into_response()
+}
+
+/// For optional text inputs, which browsers submit as empty strings. Use
+/// with `#[serde(default, deserialize_with = "form::empty_as_none")]`.
+pub fn empty_as_none<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    let value = value.trim();
+    Ok((!value.is_empty()).then(|| value.to_owned()))
+}
+
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Re-render a form page with errors found after extraction, such as those
+/// that need the database to detect. Messages are looked up in the locale's
+/// catalog.
+pub async fn rerender<T: FormPage>(
+    form: &T,
+    state: &AppState,
+    session: Option<Session>,
+    Locale(catalog): Locale,
+    errors: ValidationErrors,
+) -> Response {
+    let errors = errors.localize(catalog);
+    match form.render(state, session, &errors).await {
+        Ok(markup) => (StatusCode::UNPROCESSABLE_ENTITY, markup).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// The hidden field carrying the CSRF token, which every POST form starts
+/// with: the middleware reads no further than the first field. Empty outside
+/// of the CSRF middleware.
+pub fn csrf_field() -> maud::Markup {
+    maud::html! {
+        @if let Some(token) = csrf::token() {
+            input type="hidden" name=(csrf::FIELD_NAME) value=(token);
+        }
+    }
+}
+
+pub fn field_errors(errors: &ValidationErrors, field: &str) -> maud::Markup {
+    maud::html! {
+        @if let Some(errors) = errors.field_errors().get(field) {
+            @for error in errors {
+                p .text-red-600 .text-sm .mt-1 { (error) }
+            }
+        }
+    }
+}
+
+const FIELD_CLASSES: &str = "border-solid border-1 border-gray-300 w-full p-2";
+
+/// An `<input>` whose type and constraint attributes are derived from the
+/// `Validate` rules of the form it belongs to.
+pub struct Input<'a> {
+    name: &'a str,
+    kind: &'a str,
+    value: Option<&'a str>,
+    placeholder: Option<&'a str>,
+    autocomplete: Option<&'a str>,
+    class: &'a str,
+    constraints: FieldConstraints,
+}
+
+pub fn input<T: Constraints>(name: &str) -> Input<'_> {
+    Input {
+        name,
+        kind: "text",
+        value: None,
+        placeholder: None,
+        autocomplete: None,
+        class: "",
+        constraints: T::constraints(name),
+    }
+}
+
+impl<'a> Input<'a> {
+    /// The input type used when the validators do not imply one.
+    pub fn kind(mut self, kind: &'a str) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn value(mut self, value: Option<&'a str>) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn placeholder(mut self, placeholder: &'a str) -> Self {
+        self.placeholder = Some(placeholder);
+        self
+    }
+
+    pub fn autocomplete(mut self, autocomplete: &'a str) -> Self {
+        self.autocomplete = Some(autocomplete);
+        self
+    }
+
+    /// Extra classes on top of the default field styling.
+    pub fn class(mut self, class: &'a str) -> Self {
+        self.class = class;
+        self
+    }
+}
+
+impl maud::Render for Input<'_> {
+    fn render(&self) -> maud::Markup {
+        let c = &self.constraints;
+        maud::html! {
+            input
+                class={ (FIELD_CLASSES) " " (self.class) }
+                type=(c.input_type.unwrap_or(self.kind))
+                id=(self.name)
+                name=(self.name)
+                value=[self.value]
+                placeholder=[self.placeholder]
+                autocomplete=[self.autocomplete]
+                required[c.required]
+                minlength=[c.min_length]
+                maxlength=[c.max_length]
+                min=[c.min]
+                max=[c.max]
+                pattern=[c.pattern];
+        }
+    }
+}
+
+/// A `<textarea>` with constraint attributes derived like those of [`Input`].
+pub struct TextArea<'a> {
+    name: &'a str,
+    rows: u32,
+    value: &'a str,
+    placeholder: Option<&'a str>,
+    class: &'a str,
+    constraints: FieldConstraints,
+}
+
+pub fn textarea<T: Constraints>(name: &str) -> TextArea<'_> {
+    TextArea {
+        name,
+        rows: 4,
+        value: "",
+        placeholder: None,
+        class: "",
+        constraints: T::constraints(name),
+    }
+}
+
+impl<'a> TextArea<'a> {
+    pub fn rows(mut self, rows: u32) -> Self {
+        self.rows = rows;
+        self
+    }
+
+    pub fn value(mut self, value: &'a str) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn placeholder(mut self, placeholder: &'a str) -> Self {
+        self.placeholder = Some(placeholder);
+        self
+    }
+
+    /// Extra classes on top of the default field styling.
+    pub fn class(mut self, class: &'a str) -> Self {
+        self.class = class;
+        self
+    }
+}
+
+impl maud::Render for TextArea<'_> {
+    fn render(&self) -> maud::Markup {
+        let c = &self.constraints;
+        maud::html! {
+            textarea
+                class={ (FIELD_CLASSES) " " (self.class) }
+                id=(self.name)
+                name=(self.name)
+                rows=(self.rows)
+                placeholder=[self.placeholder]
+                required[c.required]
+                minlength=[c.min_length]
+                maxlength=[c.max_length]
+            {
+                (self.value)
+            }
+        }
+    }
+}
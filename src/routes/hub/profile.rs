@@ -1,17 +1,33 @@
 use axum::Router;
 use axum::extract::Path;
+use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::get;
 
 use crate::middleware::auth::Session;
-use crate::routes::shell;
+use crate::model;
+use crate::routes::{AppError, shell};
 use crate::state::AppState;
 
 pub fn routes() -> Router<AppState> {
     Router::new().route("/~{name}", get(page_profile))
 }
 
-async fn page_profile(session: Option<Session>, Path(name): Path<String>) -> maud::Markup {
+async fn page_profile(
+    state: AppState,
+    session: Option<Session>,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    if model::user::get_id_by_username(&state.db, &name)
+        .await?
+        .is_none()
+    {
+        return match model::user::resolve_redirect(&state.db, &name).await? {
+            Some(current) => Ok(Redirect::to(&format!("/~{}", current)).into_response()),
+            None => Err(AppError::NotFound),
+        };
+    }
+
     let markup = maud::html! {};
     let title = format!("~{}", name);
-    shell::document(markup, &title, session)
+    Ok(shell::document(markup, &title, session).into_response())
 }
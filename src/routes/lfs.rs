@@ -15,10 +15,10 @@ use tokio::fs;
 use tokio::io::AsyncWriteExt;
 use tokio_util::io::ReaderStream;
 
-use crate::model;
 use crate::routes::AppError;
 use crate::state::AppState;
 use crate::utils::re;
+use crate::{model, validate};
 
 const LFS_CONTENT_TYPE: &str = "application/vnd.git-lfs+json";
 
@@ -36,9 +36,16 @@ pub fn routes() -> Router<AppState> {
 struct BatchRequest {
     operation: String,
     transfers: Option<Vec<String>>,
+    #[serde(rename = "ref")]
+    git_ref: Option<LfsRef>,
     objects: Vec<LfsObjectSpec>,
 }
 
+#[derive(Debug, Deserialize)]
+struct LfsRef {
+    name: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct LfsObjectSpec {
     oid: String,
@@ -92,7 +99,7 @@ async fn batch(
     AxumPath((user, repo)): AxumPath<(String, String)>,
     Json(request): Json<BatchRequest>,
 ) -> Result<Response, AppError> {
-    if !valid_user_repo(&user, &repo) {
+    if !validate::is_repo_path(&user, &repo) {
         return Ok((StatusCode::BAD_REQUEST, "invalid user or repo").into_response());
     }
 
@@ -111,6 +118,16 @@ async fn batch(
         return Ok((StatusCode::BAD_REQUEST, "unsupported operation").into_response());
     }
 
+    if let Some(git_ref) = &request.git_ref
+        && let Err(err) = validate::check_git_ref_name(&git_ref.name)
+    {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("invalid ref: {}", err),
+        )
+            .into_response());
+    }
+
     // Extract auth header to pass through to action links
     let auth_header = headers
         .get(header::AUTHORIZATION)
@@ -224,7 +241,7 @@ async fn download(
     headers: HeaderMap,
     AxumPath((user, repo, oid)): AxumPath<(String, String, String)>,
 ) -> Result<Response, AppError> {
-    if !valid_user_repo(&user, &repo) {
+    if !validate::is_repo_path(&user, &repo) {
         return Ok((StatusCode::BAD_REQUEST, "invalid user or repo").into_response());
     }
 
@@ -267,7 +284,7 @@ async fn upload(
     AxumPath((user, repo, oid)): AxumPath<(String, String, String)>,
     body: Body,
 ) -> Result<Response, AppError> {
-    if !valid_user_repo(&user, &repo) {
+    if !validate::is_repo_path(&user, &repo) {
         return Ok((StatusCode::BAD_REQUEST, "invalid user or repo").into_response());
     }
 
@@ -322,7 +339,7 @@ async fn verify(
     AxumPath((user, repo)): AxumPath<(String, String)>,
     Json(request): Json<LfsObjectSpec>,
 ) -> Result<Response, AppError> {
-    if !valid_user_repo(&user, &repo) {
+    if !validate::is_repo_path(&user, &repo) {
         return Ok((StatusCode::BAD_REQUEST, "invalid user or repo").into_response());
     }
 
@@ -366,12 +383,6 @@ fn json_response<T: Serialize>(status: StatusCode, body: T) -> Response {
     response
 }
 
-fn valid_user_repo(user: &str, repo: &str) -> bool {
-    let user_ok = re!(r"^[a-zA-Z0-9]+$").is_match(user);
-    let repo_ok = re!(r"^[.\-a-zA-Z0-9]+\.git$").is_match(repo);
-    user_ok && repo_ok
-}
-
 fn normalize_oid(oid: &str) -> Option<String> {
     if !re!(r"^[0-9a-fA-F]{64}$").is_match(oid) {
         return None;
@@ -1,56 +1,90 @@
+use std::time::Duration;
+
 use axum::Router;
-use axum::extract::{Form, Query};
+use axum::extract::Query;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
 use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use conduit_derive::Validate;
 use serde::Deserialize;
+use tracing::error;
+use url::form_urlencoded;
 
+use crate::config::RegistrationMode;
 use crate::middleware::auth;
 use crate::middleware::auth::Session;
-use crate::model;
-use crate::routes::{AppError, shell};
+use crate::middleware::client::ClientInfo;
+use crate::model::audit::{Action, Event};
+use crate::model::email::TokenPurpose;
+use crate::model::user::UserId;
+use crate::ratelimit::{Scope, Subject};
+use crate::routes::form::{FormPage, Locale, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+use crate::{ldap, mail, model};
+
+/// How long an unverified user waits for another verification link when
+/// logging in.
+const RESEND_AFTER: time::Duration = time::Duration::minutes(15);
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/login", get(page_login))
         .route("/login", post(do_login))
+        .route("/login/two-factor", post(do_two_factor))
 }
 
-async fn page_login(session: Option<Session>) -> Response {
+async fn page_login(
+    state: AppState,
+    session: Option<Session>,
+    Query(query): Query<LoginQuery>,
+) -> Response {
     if session.is_some() {
         return Redirect::to("/").into_response();
     }
 
+    login_form(
+        &state,
+        None,
+        query.redirect.as_deref(),
+        &ValidationErrors::new(),
+    )
+    .into_response()
+}
+
+fn login_form(
+    state: &AppState,
+    form: Option<&LoginForm>,
+    redirect: Option<&str>,
+    errors: &ValidationErrors,
+) -> maud::Markup {
+    let username = form.map(|f| f.username.as_str());
+    let query = redirect
+        .map(|redirect| {
+            let encoded = form_urlencoded::byte_serialize(redirect.as_bytes());
+            format!("?redirect={}", encoded.collect::<String>())
+        })
+        .unwrap_or_default();
+
     let markup = maud::html! {
         div .max-w-md {
             h2 .text-xl .mb-4 { "Log In" }
 
             form method="post" {
+                (form::csrf_field())
+                (field_errors(errors, "credentials"))
+
                 div .mb-3 {
                     label for="username" .block .mb-1 { "Username" }
-                    input
-                        .border-solid
-                        .border-1
-                        .border-gray-300
-                        .w-full
-                        .p-2
-                        type="text"
-                        name="username"
-                        required;
+                    (form::input::<LoginForm>("username").value(username))
+                    (field_errors(errors, "username"))
                 }
 
                 div .mb-3 {
                     label for="password" .block .mb-1 { "Password" }
-                    input
-                        .border-solid
-                        .border-1
-                        .border-gray-300
-                        .w-full
-                        .p-2
-                        type="password"
-                        name="password"
-                        required;
+                    (form::input::<LoginForm>("password").kind("password"))
+                    (field_errors(errors, "password"))
                 }
 
                 div .mt-4 {
@@ -69,14 +103,34 @@ async fn page_login(session: Option<Session>) -> Response {
                 }
             }
 
-            p .mt-6 .text-gray-600 {
-                "Don't have an account? "
-                a .text-blue-600 .hover:underline href="/register" { "Register" }
+            @if !state.config.oidc.is_empty() {
+                div .mt-6 .flex .flex-col .gap-2 {
+                    @for provider in &state.config.oidc {
+                        a .text-center .border-solid .border-1 .border-gray-300 .px-4 .py-2 .hover:bg-gray-100
+                            href={ "/login/oidc/" (provider.id) (query) }
+                        {
+                            "Sign in with " (provider.name)
+                        }
+                    }
+                }
+            }
+
+            @if mail::enabled(state) {
+                p .mt-6 .text-gray-600 {
+                    a .text-blue-600 .hover:underline href="/reset-password" { "Forgot your password?" }
+                }
+            }
+
+            @if state.config.registration.mode != RegistrationMode::Closed {
+                p .mt-2 .text-gray-600 {
+                    "Don't have an account? "
+                    a .text-blue-600 .hover:underline href="/register" { "Register" }
+                }
             }
         }
     };
 
-    shell::document(markup, "log in", session).into_response()
+    shell::document(markup, "log in", None)
 }
 
 #[derive(Deserialize)]
@@ -84,23 +138,139 @@ struct LoginQuery {
     redirect: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 struct LoginForm {
+    #[validate(length(min = 1, code = "required"))]
     username: String,
+    #[validate(length(min = 1, code = "required"))]
     password: String,
 }
 
+impl FormPage for LoginForm {
+    async fn render(
+        &self,
+        state: &AppState,
+        _session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        Ok(login_form(state, Some(self), None, errors))
+    }
+}
+
 async fn do_login(
     state: AppState,
-    mut jar: CookieJar,
+    client: ClientInfo,
+    jar: CookieJar,
     Query(query): Query<LoginQuery>,
-    Form(login): Form<LoginForm>,
-) -> Result<(CookieJar, Redirect), AppError> {
+    locale: Locale,
+    ValidatedForm(login): ValidatedForm<LoginForm>,
+) -> Result<Response, AppError> {
     let redirect = query.redirect;
-    let LoginForm { username, password } = login;
+    let subjects = [Subject::Ip(client.ip), Subject::username(&login.username)];
+    let mut errors = ValidationErrors::new();
+
+    if let Some(retry_after) = state.limiter.check(Scope::Login, &subjects) {
+        errors.add("credentials", locked_out(retry_after));
+        return Ok(form::rerender(&login, &state, None, locale, errors).await);
+    }
+
+    let Some(user_id) = authenticate(&state, &login).await? else {
+        state.limiter.record(Scope::Login, &subjects);
+        let user_id = model::user::get_id_by_username(&state.db, &login.username).await?;
+        let event = Event {
+            action: Action::LoginFailed,
+            actor: None,
+            user: user_id,
+            ip: Some(client.ip),
+            detail: &login.username,
+        };
+        model::audit::record(&state.db, event).await?;
+
+        errors.add("credentials", ValidationError::new("credentials"));
+        return Ok(form::rerender(&login, &state, None, locale, errors).await);
+    };
+
+    if model::user::is_suspended(&state.db, user_id).await? {
+        errors.add("credentials", ValidationError::new("suspended"));
+        return Ok(form::rerender(&login, &state, None, locale, errors).await);
+    }
+
+    // Registration restricted to a domain relies on the address being
+    // verified, so accounts have to be verified before they can be used.
+    // A fresh link is sent unless one went out recently, so that retrying
+    // does not flood the inbox.
+    if state.config.registration.mode == RegistrationMode::Domain
+        && let Some(profile) = model::user::get_profile(&state.db, user_id).await?
+        && !profile.email_verified
+    {
+        if !model::email::issued_within(&state.db, user_id, TokenPurpose::Verify, RESEND_AFTER)
+            .await?
+        {
+            mail::send_verification(&state, user_id, &profile.username, &profile.email).await?;
+        }
+        errors.add("credentials", ValidationError::new("unverified"));
+        return Ok(form::rerender(&login, &state, None, locale, errors).await);
+    }
 
-    let user_id = model::user::login(&state.db, &username, &password).await?;
-    let session = model::session::create(&state.db, user_id).await?;
+    if model::two_factor::is_enabled(&state.db, user_id).await? {
+        return two_factor_prompt(&state, user_id, redirect).await;
+    }
+
+    state.limiter.reset(Scope::Login, &subjects[1]);
+    start_session(&state, client, jar, user_id, redirect).await
+}
+
+/// Check the password against the directory, if one is configured, and
+/// then against local accounts. Local accounts are also the fallback when
+/// the directory is unavailable.
+async fn authenticate(state: &AppState, login: &LoginForm) -> Result<Option<UserId>, AppError> {
+    if let Some(config) = &state.config.ldap {
+        match ldap::login(state, config, &login.username, &login.password).await {
+            Ok(ldap::Login::User(user_id)) => return Ok(Some(user_id)),
+            Ok(ldap::Login::Denied) => return Ok(None),
+            Ok(ldap::Login::Unknown) => (),
+            Err(err) => error!("LDAP login failed, trying local accounts: {:?}", err),
+        }
+    }
+
+    Ok(model::user::login(&state.db, &login.username, &login.password).await?)
+}
+
+/// Ask for the second factor of a user whose first one checked out.
+pub(super) async fn two_factor_prompt(
+    state: &AppState,
+    user_id: UserId,
+    redirect: Option<String>,
+) -> Result<Response, AppError> {
+    let form = TwoFactorForm {
+        challenge: model::two_factor::create_challenge(&state.db, user_id).await?,
+        code: String::new(),
+        redirect,
+    };
+
+    Ok(two_factor_form(&form, &ValidationErrors::new()).into_response())
+}
+
+fn locked_out(retry_after: Duration) -> ValidationError {
+    let minutes = retry_after.as_secs().div_ceil(60).max(1);
+    ValidationError::new("locked_out").add_param("minutes", minutes)
+}
+
+pub(super) async fn start_session(
+    state: &AppState,
+    client: ClientInfo,
+    mut jar: CookieJar,
+    user_id: UserId,
+    redirect: Option<String>,
+) -> Result<Response, AppError> {
+    // Also covers users suspended while in the middle of logging in.
+    if model::user::is_suspended(&state.db, user_id).await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let session =
+        model::session::create(&state.db, user_id, client.ip, client.user_agent.as_deref()).await?;
+    model::audit::record(&state.db, Event::new(Action::Login, user_id, client.ip)).await?;
 
     let cookie = Cookie::build((auth::COOKIE_NAME, session.token))
         .http_only(true)
@@ -109,6 +279,144 @@ async fn do_login(
         .expires(session.expires);
 
     jar = jar.add(cookie);
-    let destination = redirect.unwrap_or_else(|| "/".to_string());
-    Ok((jar, Redirect::to(&destination)))
+    let destination = redirect.as_deref().map_or("/", local_redirect);
+    Ok((jar, Redirect::to(destination)).into_response())
+}
+
+/// Where to send a user after logging in. Only paths on this site are
+/// followed; anything else, including protocol-relative URLs such as
+/// `//evil.example`, sends them to the front page.
+fn local_redirect(redirect: &str) -> &str {
+    let local = redirect.starts_with('/')
+        && !redirect.starts_with("//")
+        && !redirect.contains(['\\', '\r', '\n']);
+    if local { redirect } else { "/" }
+}
+
+fn two_factor_form(form: &TwoFactorForm, errors: &ValidationErrors) -> maud::Markup {
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Two-factor authentication" }
+
+            form method="post" action="/login/two-factor" {
+                (form::csrf_field())
+                input type="hidden" name="challenge" value=(form.challenge);
+                @if let Some(redirect) = &form.redirect {
+                    input type="hidden" name="redirect" value=(redirect);
+                }
+                (field_errors(errors, "challenge"))
+
+                div .mb-3 {
+                    label for="code" .block .mb-1 { "Authentication code" }
+                    (form::input::<TwoFactorForm>("code")
+                        .autocomplete("one-time-code")
+                        .placeholder("123456"))
+                    (field_errors(errors, "code"))
+                    p .text-sm .text-gray-500 .mt-1 {
+                        "Enter the code from your authenticator app, or one of your recovery codes."
+                    }
+                }
+
+                div .mt-4 {
+                    input
+                        .text-neutral-50
+                        .bg-blue-500
+                        .hover:bg-blue-600
+                        .border-neutral-700
+                        .border-solid
+                        .border-1
+                        .px-4
+                        .py-2
+                        .cursor-pointer
+                        type="submit"
+                        value="Verify";
+                }
+            }
+        }
+    };
+
+    shell::document(markup, "log in", None)
+}
+
+#[derive(Deserialize, Validate)]
+struct TwoFactorForm {
+    challenge: String,
+    #[validate(length(min = 1, code = "required"))]
+    code: String,
+    #[serde(default, deserialize_with = "form::empty_as_none")]
+    redirect: Option<String>,
+}
+
+impl FormPage for TwoFactorForm {
+    async fn render(
+        &self,
+        _state: &AppState,
+        _session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        Ok(two_factor_form(self, errors))
+    }
+}
+
+async fn do_two_factor(
+    state: AppState,
+    client: ClientInfo,
+    jar: CookieJar,
+    locale: Locale,
+    ValidatedForm(form): ValidatedForm<TwoFactorForm>,
+) -> Result<Response, AppError> {
+    let mut errors = ValidationErrors::new();
+
+    let Some(user_id) = model::two_factor::attempt_challenge(&state.db, &form.challenge).await?
+    else {
+        errors.add("challenge", ValidationError::new("login_expired"));
+        return Ok(form::rerender(&form, &state, None, locale, errors).await);
+    };
+
+    // Knowing the password gets a fresh challenge every time, so guessing
+    // codes counts against the same limits as guessing passwords.
+    let user = model::user::get_by_id(&state.db, user_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let subjects = [Subject::Ip(client.ip), Subject::username(&user.username)];
+
+    if let Some(retry_after) = state.limiter.check(Scope::Login, &subjects) {
+        errors.add("code", locked_out(retry_after));
+        return Ok(form::rerender(&form, &state, None, locale, errors).await);
+    }
+
+    if !model::two_factor::verify(&state.db, user_id, &form.code).await? {
+        state.limiter.record(Scope::Login, &subjects);
+        let event = Event {
+            action: Action::LoginFailed,
+            actor: None,
+            user: Some(user_id),
+            ip: Some(client.ip),
+            detail: "invalid two-factor code",
+        };
+        model::audit::record(&state.db, event).await?;
+
+        errors.add("code", ValidationError::new("invalid_code"));
+        return Ok(form::rerender(&form, &state, None, locale, errors).await);
+    }
+
+    state.limiter.reset(Scope::Login, &subjects[1]);
+    model::two_factor::complete_challenge(&state.db, &form.challenge).await?;
+    start_session(&state, client, jar, user_id, form.redirect).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_stay_on_site() {
+        assert_eq!(local_redirect("/paste/abc?raw=1"), "/paste/abc?raw=1");
+        assert_eq!(local_redirect("/"), "/");
+        assert_eq!(local_redirect("//evil.example"), "/");
+        assert_eq!(local_redirect("/\\evil.example"), "/");
+        assert_eq!(local_redirect("https://evil.example/"), "/");
+        assert_eq!(local_redirect("paste/abc"), "/");
+        assert_eq!(local_redirect(""), "/");
+    }
 }
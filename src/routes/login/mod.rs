@@ -1,6 +1,9 @@
 mod login;
 mod logout;
+mod oidc;
 mod register;
+mod reset;
+mod verify;
 
 use axum::Router;
 
@@ -10,5 +13,8 @@ pub fn routes() -> Router<AppState> {
     Router::new()
         .merge(login::routes())
         .merge(logout::routes())
+        .merge(oidc::routes())
         .merge(register::routes())
+        .merge(reset::routes())
+        .merge(verify::routes())
 }
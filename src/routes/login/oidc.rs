@@ -0,0 +1,476 @@
+use axum::Router;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use serde::Deserialize;
+use tracing::warn;
+
+use super::login;
+use crate::config::{OidcProvider, RegistrationMode};
+use crate::middleware::auth::Session;
+use crate::middleware::client::ClientInfo;
+use crate::model::identity::NewUser;
+use crate::model::user::UserId;
+use crate::oidc::Identity;
+use crate::routes::{AppError, shell};
+use crate::state::AppState;
+use crate::{mail, model, oidc, registration, validate};
+
+/// Ties the authorization request to the browser that started it, so that
+/// a callback URL cannot be replayed in someone else's session.
+const STATE_COOKIE: &str = "conduit_oidc_state";
+const STATE_COOKIE_PATH: &str = "/login/oidc";
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/login/oidc/{provider}", get(do_begin))
+        .route("/login/oidc/{provider}/link", post(do_begin_link))
+        .route("/login/oidc/{provider}/callback", get(do_callback))
+}
+
+#[derive(Deserialize)]
+struct BeginQuery {
+    redirect: Option<String>,
+}
+
+async fn do_begin(
+    state: AppState,
+    session: Option<Session>,
+    jar: CookieJar,
+    Path(provider): Path<String>,
+    Query(query): Query<BeginQuery>,
+) -> Result<Response, AppError> {
+    if session.is_some() {
+        return Ok(Redirect::to("/").into_response());
+    }
+
+    let provider = oidc::provider(&state, &provider).ok_or(AppError::NotFound)?;
+    authorize(&state, jar, provider, None, query.redirect).await
+}
+
+async fn do_begin_link(
+    state: AppState,
+    session: Session,
+    jar: CookieJar,
+    Path(provider): Path<String>,
+) -> Result<Response, AppError> {
+    let provider = oidc::provider(&state, &provider).ok_or(AppError::NotFound)?;
+    authorize(&state, jar, provider, Some(session.id), None).await
+}
+
+async fn authorize(
+    state: &AppState,
+    jar: CookieJar,
+    provider: &OidcProvider,
+    link_user_id: Option<UserId>,
+    redirect: Option<String>,
+) -> Result<Response, AppError> {
+    let (url, csrf_state) = match oidc::begin(state, provider, link_user_id, redirect).await {
+        Ok(request) => request,
+        Err(err) => {
+            warn!("failed to start sign-in through {}: {:?}", provider.id, err);
+            return Ok(failure(
+                provider,
+                "The identity provider could not be reached. Try again later.",
+            ));
+        }
+    };
+
+    let cookie = Cookie::build((STATE_COOKIE, csrf_state))
+        .path(STATE_COOKIE_PATH)
+        .http_only(true)
+        .secure(cfg!(not(debug_assertions)))
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(10));
+
+    Ok((jar.add(cookie), Redirect::to(url.as_str())).into_response())
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+async fn do_callback(
+    state: AppState,
+    session: Option<Session>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Path(provider): Path<String>,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Response, AppError> {
+    let provider = oidc::provider(&state, &provider).ok_or(AppError::NotFound)?;
+    let expected_state = jar
+        .get(STATE_COOKIE)
+        .map(|cookie| cookie.value().to_owned());
+    let jar = jar.remove(Cookie::build(STATE_COOKIE).path(STATE_COOKIE_PATH));
+
+    if let Some(error) = query.error {
+        let message = query.error_description.unwrap_or(error);
+        return Ok((jar, failure(provider, &message)).into_response());
+    }
+
+    let pending = match (query.code.is_some(), query.state) {
+        (true, Some(csrf_state)) if expected_state.as_deref() == Some(csrf_state.as_str()) => {
+            model::identity::take_state(&state.db, &csrf_state, &provider.id).await?
+        }
+        _ => None,
+    };
+
+    let (Some(pending), Some(code)) = (pending, query.code) else {
+        let message =
+            "This sign-in attempt has expired or was started in another browser. Please try again.";
+        return Ok((jar, failure(provider, message)).into_response());
+    };
+
+    let identity = match oidc::complete(&state, provider, &pending, code).await {
+        Ok(identity) => identity,
+        Err(err) => {
+            warn!("sign-in through {} failed: {:?}", provider.id, err);
+            let message = "Your sign-in could not be verified. Please try again.";
+            return Ok((jar, failure(provider, message)).into_response());
+        }
+    };
+
+    if let Some(user_id) = pending.link_user_id {
+        if session.map(|session| session.id) != Some(user_id) {
+            return Err(AppError::Forbidden);
+        }
+
+        let linked = model::identity::link(
+            &state.db,
+            user_id,
+            &provider.id,
+            &identity.subject,
+            identity.email.as_deref(),
+        )
+        .await?;
+
+        if !linked {
+            let message = format!(
+                "This {} account is already linked to another user, or you have already linked a different one.",
+                provider.name
+            );
+            return Ok((jar, failure(provider, &message)).into_response());
+        }
+
+        return Ok((jar, Redirect::to("/meta/security")).into_response());
+    }
+
+    if session.is_some() {
+        return Ok((jar, Redirect::to("/")).into_response());
+    }
+
+    let user_id = match model::identity::login(&state.db, &provider.id, &identity.subject).await? {
+        Some(user_id) => user_id,
+        None => match provision(&state, provider, identity).await? {
+            Ok(user_id) => user_id,
+            Err(message) => return Ok((jar, failure(provider, &message)).into_response()),
+        },
+    };
+
+    if model::user::is_suspended(&state.db, user_id).await? {
+        let message = "This account has been suspended.";
+        return Ok((jar, failure(provider, message)).into_response());
+    }
+
+    // The provider stands in for the password, not for a second factor.
+    if model::two_factor::is_enabled(&state.db, user_id).await? {
+        let prompt = login::two_factor_prompt(&state, user_id, pending.redirect).await?;
+        return Ok((jar, prompt).into_response());
+    }
+
+    login::start_session(&state, client, jar, user_id, pending.redirect).await
+}
+
+/// Create an account for someone signing in for the first time, if the
+/// registration mode allows it. Accounts are never linked by email address,
+/// since the provider may not have verified it; existing users link from
+/// their security settings instead.
+async fn provision(
+    state: &AppState,
+    provider: &OidcProvider,
+    identity: Identity,
+) -> Result<Result<UserId, String>, AppError> {
+    let Some(email) = identity.email else {
+        return Ok(Err(format!(
+            "{} did not share an email address, which is needed to create an account.",
+            provider.name
+        )));
+    };
+
+    if !registration::allows_provisioning(state, &email).await? {
+        let message = match state.config.registration.mode {
+            RegistrationMode::Domain => format!(
+                "Only email addresses at {} may be used to create an account.",
+                state.config.registration.allowed_domains.join(", ")
+            ),
+            _ => "New accounts cannot be created here. Please contact an administrator.".to_owned(),
+        };
+        return Ok(Err(message));
+    }
+
+    // Domain mode relies on addresses being verified, and the account would
+    // be usable before the verification link is followed.
+    if !identity.email_verified && registration::mode(state).await? == RegistrationMode::Domain {
+        return Ok(Err(format!(
+            "{} has not verified your email address, which is needed to create an account.",
+            provider.name
+        )));
+    }
+
+    if model::user::get_id_by_email(&state.db, &email)
+        .await?
+        .is_some()
+    {
+        return Ok(Err(format!(
+            "An account with the email address {} already exists. Log in with your password, then link your {} account from your security settings.",
+            email, provider.name
+        )));
+    }
+
+    let wanted = identity
+        .username
+        .as_deref()
+        .or_else(|| email.split('@').next())
+        .unwrap_or_default();
+
+    let Some(username) = available_username(state, wanted).await? else {
+        return Ok(Err(
+            "No username could be chosen for your account. Please contact an administrator."
+                .to_owned(),
+        ));
+    };
+
+    let user_id = model::identity::provision(
+        &state.db,
+        NewUser {
+            display_name: identity.display_name.unwrap_or_else(|| username.clone()),
+            username: username.clone(),
+            email: email.clone(),
+            email_verified: identity.email_verified,
+            provider: provider.id.clone(),
+            subject: identity.subject,
+        },
+    )
+    .await?;
+
+    if !identity.email_verified {
+        mail::send_verification(state, user_id, &username, &email).await?;
+    }
+
+    Ok(Ok(user_id))
+}
+
+/// The wanted username if it is free, or else the first free one with a
+/// numeric suffix. Characters not allowed in usernames are dropped.
+async fn available_username(state: &AppState, wanted: &str) -> Result<Option<String>, AppError> {
+    let base: String = wanted
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(validate::USERNAME_MAX_LENGTH - 3)
+        .collect();
+
+    let base = if base.is_empty() {
+        "user".to_owned()
+    } else {
+        base
+    };
+    let candidates =
+        std::iter::once(base.clone()).chain((2..100).map(|n| format!("{}-{}", base, n)));
+
+    for candidate in candidates {
+        if validate::is_username(&candidate)
+            && model::user::get_id_by_username(&state.db, &candidate)
+                .await?
+                .is_none()
+        {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+fn failure(provider: &OidcProvider, message: &str) -> Response {
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Sign in with " (provider.name) }
+            p .mb-4 .text-red-600 { (message) }
+            p .text-gray-600 {
+                a .text-blue-600 .hover:underline href="/login" { "Back to log in" }
+            }
+        }
+    };
+
+    (
+        StatusCode::BAD_REQUEST,
+        shell::document(markup, "log in", None),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+
+    fn identity(subject: &str, username: Option<&str>, email: Option<&str>) -> Identity {
+        Identity {
+            subject: subject.to_owned(),
+            username: username.map(ToOwned::to_owned),
+            email: email.map(ToOwned::to_owned),
+            email_verified: true,
+            display_name: None,
+        }
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres container"]
+    async fn provisioning(db: PgPool) {
+        let state = AppState::for_tests(db);
+        let provider = oidc::provider(&state, "mock").unwrap();
+
+        let first = identity("one", Some("Dev User!"), Some("dev@example.com"));
+        let user_id = provision(&state, provider, first).await.unwrap().unwrap();
+        assert_eq!(
+            model::identity::login(&state.db, "mock", "one")
+                .await
+                .unwrap(),
+            Some(user_id)
+        );
+
+        let profile = model::user::get_profile(&state.db, user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile.username, "DevUser");
+        assert_eq!(profile.display_name, "DevUser");
+        assert!(profile.email_verified);
+
+        // Taken usernames get a suffix, and missing ones come from the email.
+        let second = identity("two", Some("DevUser"), Some("other@example.com"));
+        let second = provision(&state, provider, second).await.unwrap().unwrap();
+        let third = identity("three", None, Some("third@example.com"));
+        let third = provision(&state, provider, third).await.unwrap().unwrap();
+        for (user_id, username) in [(second, "DevUser-2"), (third, "third")] {
+            let profile = model::user::get_profile(&state.db, user_id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(profile.username, username);
+        }
+
+        // Accounts are never taken over by email address.
+        let taken = identity("four", Some("four"), Some("dev@example.com"));
+        assert!(provision(&state, provider, taken).await.unwrap().is_err());
+        let no_email = identity("five", Some("five"), None);
+        assert!(
+            provision(&state, provider, no_email)
+                .await
+                .unwrap()
+                .is_err()
+        );
+        assert!(
+            model::identity::find_user(&state.db, "mock", "four")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres container"]
+    async fn registration_modes(db: PgPool) {
+        let closed = AppState::for_tests_with(db.clone(), |config| {
+            config.registration.mode = RegistrationMode::Closed;
+        });
+        let provider = oidc::provider(&closed, "mock").unwrap();
+
+        // The first user of a new instance gets an account regardless.
+        let first = identity("one", Some("first"), Some("first@example.com"));
+        assert!(provision(&closed, provider, first).await.unwrap().is_ok());
+        let second = identity("two", Some("second"), Some("second@example.com"));
+        assert!(provision(&closed, provider, second).await.unwrap().is_err());
+
+        let invite = AppState::for_tests_with(db.clone(), |config| {
+            config.registration.mode = RegistrationMode::Invite;
+        });
+        let second = identity("two", Some("second"), Some("second@example.com"));
+        assert!(provision(&invite, provider, second).await.unwrap().is_err());
+
+        let domain = AppState::for_tests_with(db, |config| {
+            config.registration.mode = RegistrationMode::Domain;
+            config.registration.allowed_domains = vec!["example.org".to_owned()];
+        });
+        let second = identity("two", Some("second"), Some("second@example.com"));
+        assert!(provision(&domain, provider, second).await.unwrap().is_err());
+        let mut unverified = identity("three", Some("third"), Some("third@example.org"));
+        unverified.email_verified = false;
+        assert!(
+            provision(&domain, provider, unverified)
+                .await
+                .unwrap()
+                .is_err()
+        );
+        let third = identity("three", Some("third"), Some("third@EXAMPLE.org"));
+        assert!(provision(&domain, provider, third).await.unwrap().is_ok());
+
+        assert!(
+            model::user::get_id_by_username(&domain.db, "second")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    async fn link(state: &AppState, user_id: UserId, subject: &str) -> bool {
+        model::identity::link(&state.db, user_id, "mock", subject, None)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs the Postgres container"]
+    async fn linking(db: PgPool) {
+        let state = AppState::for_tests(db);
+        let alice = model::user::create(&state.db, "alice", "alice@example.com", "password")
+            .await
+            .unwrap()
+            .unwrap();
+        let bob = model::user::create(&state.db, "bob", "bob@example.com", "password")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(link(&state, alice, "alice-subject").await);
+        assert_eq!(
+            model::identity::login(&state.db, "mock", "alice-subject")
+                .await
+                .unwrap(),
+            Some(alice)
+        );
+
+        // A subject belongs to one user, and a user has one per provider.
+        assert!(!link(&state, bob, "alice-subject").await);
+        assert!(!link(&state, alice, "another-subject").await);
+
+        model::identity::unlink(&state.db, alice, "mock")
+            .await
+            .unwrap();
+        assert!(link(&state, bob, "alice-subject").await);
+        assert_eq!(
+            model::identity::find_user(&state.db, "mock", "alice-subject")
+                .await
+                .unwrap(),
+            Some(bob)
+        );
+    }
+}
@@ -1,13 +1,21 @@
 use axum::Router;
-use axum::extract::Form;
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
+use conduit_derive::Validate;
 use serde::Deserialize;
 
+use crate::config::RegistrationMode;
 use crate::middleware::auth::Session;
-use crate::model;
-use crate::routes::{AppError, shell};
+use crate::middleware::client::client_ip;
+use crate::model::user::Unavailable;
+use crate::ratelimit::{Scope, Subject};
+use crate::routes::form::{FormPage, Locale, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+use crate::{mail, model, registration};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -15,53 +23,95 @@ pub fn routes() -> Router<AppState> {
         .route("/register", post(do_register))
 }
 
-async fn page_register(session: Option<Session>) -> Response {
+#[derive(Deserialize)]
+struct RegisterQuery {
+    invite: Option<String>,
+}
+
+async fn page_register(
+    state: AppState,
+    session: Option<Session>,
+    Query(query): Query<RegisterQuery>,
+) -> Result<Response, AppError> {
     if session.is_some() {
-        return Redirect::to("/").into_response();
+        return Ok(Redirect::to("/").into_response());
+    }
+
+    let mode = registration::mode(&state).await?;
+    if mode == RegistrationMode::Closed {
+        return Ok(closed_page().into_response());
     }
 
+    Ok(register_form(
+        &state,
+        mode,
+        None,
+        query.invite.as_deref(),
+        &ValidationErrors::new(),
+    )
+    .into_response())
+}
+
+fn closed_page() -> maud::Markup {
     let markup = maud::html! {
         div .max-w-md {
             h2 .text-xl .mb-4 { "Register" }
+            p .text-gray-600 { "Registration is closed. Ask an administrator for an account." }
+        }
+    };
+
+    shell::document(markup, "register", None)
+}
+
+fn register_form(
+    state: &AppState,
+    mode: RegistrationMode,
+    form: Option<&Register>,
+    invite: Option<&str>,
+    errors: &ValidationErrors,
+) -> maud::Markup {
+    let username = form.map(|f| f.username.as_str());
+    let email = form.map(|f| f.email.as_str());
+    let invite = form.map(|f| f.invite.as_str()).or(invite);
+
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Register" }
+
+            @if mode == RegistrationMode::Domain {
+                p .text-gray-600 .mb-4 {
+                    "Registration is limited to email addresses at "
+                    (state.config.registration.allowed_domains.join(", "))
+                    "."
+                }
+            }
 
             form method="post" {
+                (form::csrf_field())
+                @if mode == RegistrationMode::Invite {
+                    div .mb-3 {
+                        label for="invite" .block .mb-1 { "Invite code" }
+                        (form::input::<Register>("invite").value(invite))
+                        (field_errors(errors, "invite"))
+                    }
+                }
+
                 div .mb-3 {
                     label for="username" .block .mb-1 { "Username" }
-                    input
-                        .border-solid
-                        .border-1
-                        .border-gray-300
-                        .w-full
-                        .p-2
-                        type="text"
-                        name="username"
-                        required;
+                    (form::input::<Register>("username").value(username))
+                    (field_errors(errors, "username"))
                 }
 
                 div .mb-3 {
                     label for="email" .block .mb-1 { "Email" }
-                    input
-                        .border-solid
-                        .border-1
-                        .border-gray-300
-                        .w-full
-                        .p-2
-                        type="email"
-                        name="email"
-                        required;
+                    (form::input::<Register>("email").value(email))
+                    (field_errors(errors, "email"))
                 }
 
                 div .mb-3 {
                     label for="password" .block .mb-1 { "Password" }
-                    input
-                        .border-solid
-                        .border-1
-                        .border-gray-300
-                        .w-full
-                        .p-2
-                        type="password"
-                        name="password"
-                        required;
+                    (form::input::<Register>("password").kind("password").autocomplete("new-password"))
+                    (field_errors(errors, "password"))
                 }
 
                 div .mt-4 {
@@ -87,26 +137,176 @@ async fn page_register(session: Option<Session>) -> Response {
         }
     };
 
-    shell::document(markup, "register", session).into_response()
+    shell::document(markup, "register", None)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 struct Register {
+    #[validate(username)]
+    #[validate(async_custom(function = "username_available"))]
     username: String,
+    #[validate(email)]
+    #[validate(async_custom(function = "email_available"))]
+    #[validate(async_custom(function = "email_domain_allowed"))]
     email: String,
+    #[validate(length(min = 8))]
     password: String,
+    #[serde(default)]
+    #[validate(async_custom(function = "invite_valid"))]
+    invite: String,
+}
+
+impl FormPage for Register {
+    async fn render(
+        &self,
+        state: &AppState,
+        _session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        let mode = registration::mode(state).await?;
+        Ok(register_form(state, mode, Some(self), None, errors))
+    }
+}
+
+async fn username_available(username: &str, state: &AppState) -> Result<(), ValidationError> {
+    match model::user::get_id_by_username(&state.db, username).await {
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => Err(ValidationError::new("username_taken")),
+        Err(err) => Err(ValidationError::unavailable(err)),
+    }
+}
+
+async fn email_available(email: &str, state: &AppState) -> Result<(), ValidationError> {
+    match model::user::get_id_by_email(&state.db, email).await {
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => Err(ValidationError::new("email_taken")),
+        Err(err) => Err(ValidationError::unavailable(err)),
+    }
+}
+
+async fn email_domain_allowed(email: &str, state: &AppState) -> Result<(), ValidationError> {
+    match registration::mode(state).await {
+        Ok(RegistrationMode::Domain) => (),
+        Ok(_) => return Ok(()),
+        Err(err) => return Err(ValidationError::unavailable(err)),
+    }
+
+    if registration::domain_allowed(state, email) {
+        return Ok(());
+    }
+
+    let allowed = state.config.registration.allowed_domains.join(", ");
+    Err(ValidationError::new("email_domain").add_param("domains", allowed))
+}
+
+async fn invite_valid(invite: &str, state: &AppState) -> Result<(), ValidationError> {
+    match registration::mode(state).await {
+        Ok(RegistrationMode::Invite) => (),
+        Ok(_) => return Ok(()),
+        Err(err) => return Err(ValidationError::unavailable(err)),
+    }
+
+    if invite.is_empty() {
+        return Err(ValidationError::new("invite_required"));
+    }
+
+    match model::invite::is_valid(&state.db, invite).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ValidationError::new("invite_invalid")),
+        Err(err) => Err(ValidationError::unavailable(err)),
+    }
+}
+
+/// Turns away registrations while registration is closed, before the form
+/// is validated.
+struct Open;
+
+impl FromRequestParts<AppState> for Open {
+    type Rejection = AppError;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        if registration::mode(state).await? == RegistrationMode::Closed {
+            return Err(AppError::Forbidden);
+        }
+
+        Ok(Self)
+    }
+}
+
+/// Turns away client addresses with too many rejected registrations before
+/// the form is validated, so that probing for taken usernames and emails is
+/// throttled. Successful registrations do not count.
+struct Throttle([Subject; 1]);
+
+impl Throttle {
+    fn reject(&self, state: &AppState) {
+        state.limiter.record(Scope::Register, &self.0);
+    }
+}
+
+impl FromRequestParts<AppState> for Throttle {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let subjects = [Subject::Ip(client_ip(
+            state,
+            &parts.headers,
+            &parts.extensions,
+        ))];
+
+        if let Some(retry_after) = state.limiter.check(Scope::Register, &subjects) {
+            return Err(AppError::TooManyRequests(retry_after));
+        }
+
+        Ok(Self(subjects))
+    }
 }
 
-// TODO: form input validation
 async fn do_register(
     state: AppState,
-    Form(register): Form<Register>,
-) -> Result<Redirect, AppError> {
+    _open: Open,
+    throttle: Throttle,
+    locale: Locale,
+    register: Result<ValidatedForm<Register>, Response>,
+) -> Result<Response, AppError> {
+    let ValidatedForm(register) = match register {
+        Ok(register) => register,
+        Err(rejection) => {
+            throttle.reject(&state);
+            return Ok(rejection);
+        }
+    };
+
     let Register {
         username,
         email,
         password,
-    } = register;
-    model::user::create(&state.db, &username, &email, &password).await?;
-    Ok(Redirect::to("/login"))
+        invite,
+    } = &register;
+
+    // Someone else may have taken the username or email address, or used up
+    // the invite, since the form was validated.
+    let created = if registration::mode(&state).await? == RegistrationMode::Invite {
+        model::user::create_with_invite(&state.db, username, email, password, invite).await?
+    } else {
+        model::user::create(&state.db, username, email, password).await?
+    };
+
+    let user_id = match created {
+        Ok(user_id) => user_id,
+        Err(unavailable) => {
+            throttle.reject(&state);
+            let (field, code) = match unavailable {
+                Unavailable::Username => ("username", "username_taken"),
+                Unavailable::Email => ("email", "email_taken"),
+                Unavailable::Invite => ("invite", "invite_invalid"),
+            };
+            let mut errors = ValidationErrors::new();
+            errors.add(field, ValidationError::new(code));
+            return Ok(form::rerender(&register, &state, None, locale, errors).await);
+        }
+    };
+
+    mail::send_verification(&state, user_id, username, email).await?;
+    Ok(Redirect::to("/login").into_response())
 }
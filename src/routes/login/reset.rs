@@ -0,0 +1,250 @@
+use axum::Router;
+use axum::extract::{FromRequestParts, Query};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use conduit_derive::Validate;
+use serde::Deserialize;
+
+use crate::middleware::auth::Session;
+use crate::model::email::TokenPurpose;
+use crate::routes::form::{FormPage, Locale, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
+use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+use crate::{mail, model};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/reset-password", get(page_request_reset))
+        .route("/reset-password", post(do_request_reset))
+        .route("/reset-password/confirm", get(page_reset))
+        .route("/reset-password/confirm", post(do_reset))
+}
+
+/// Reset links can only be delivered by mail, so the whole flow is hidden
+/// while mail is not configured.
+struct MailEnabled;
+
+impl FromRequestParts<AppState> for MailEnabled {
+    type Rejection = AppError;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        if !mail::enabled(state) {
+            return Err(AppError::NotFound);
+        }
+
+        Ok(Self)
+    }
+}
+
+async fn page_request_reset(_mail: MailEnabled) -> Response {
+    request_form(None, &ValidationErrors::new()).into_response()
+}
+
+fn request_form(form: Option<&RequestResetForm>, errors: &ValidationErrors) -> maud::Markup {
+    let email = form.map(|f| f.email.as_str());
+
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Reset your password" }
+
+            form method="post" {
+                (form::csrf_field())
+                div .mb-3 {
+                    label for="email" .block .mb-1 { "Email" }
+                    (form::input::<RequestResetForm>("email").value(email))
+                    (field_errors(errors, "email"))
+                }
+
+                div .mt-4 {
+                    input
+                        .text-neutral-50
+                        .bg-blue-500
+                        .hover:bg-blue-600
+                        .border-neutral-700
+                        .border-solid
+                        .border-1
+                        .px-4
+                        .py-2
+                        .cursor-pointer
+                        type="submit"
+                        value="Send reset link";
+                }
+            }
+        }
+    };
+
+    shell::document(markup, "reset password", None)
+}
+
+#[derive(Deserialize, Validate)]
+struct RequestResetForm {
+    #[validate(email)]
+    email: String,
+}
+
+impl FormPage for RequestResetForm {
+    async fn render(
+        &self,
+        _state: &AppState,
+        _session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        Ok(request_form(Some(self), errors))
+    }
+}
+
+async fn do_request_reset(
+    state: AppState,
+    _mail: MailEnabled,
+    ValidatedForm(form): ValidatedForm<RequestResetForm>,
+) -> Result<maud::Markup, AppError> {
+    let email = form.email.trim();
+
+    if let Some(user_id) = model::user::get_id_by_email(&state.db, email).await?
+        && let Some(profile) = model::user::get_profile(&state.db, user_id).await?
+    {
+        mail::send_password_reset(&state, user_id, &profile.username, &profile.email).await?;
+    }
+
+    // The same answer either way, so that the form does not reveal which
+    // addresses have accounts.
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Check your email" }
+            p {
+                "If an account uses " (email) ", a link to reset its password is on its way. "
+                "The link expires in 1 hour."
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "reset password", None))
+}
+
+#[derive(Deserialize)]
+struct ResetQuery {
+    token: String,
+}
+
+async fn page_reset(
+    state: AppState,
+    _mail: MailEnabled,
+    Query(query): Query<ResetQuery>,
+) -> Result<Response, AppError> {
+    if model::email::peek_token(&state.db, &query.token, TokenPurpose::Reset)
+        .await?
+        .is_none()
+    {
+        return Ok((StatusCode::BAD_REQUEST, invalid_link()).into_response());
+    }
+
+    Ok(reset_form(&query.token, &ValidationErrors::new()).into_response())
+}
+
+fn invalid_link() -> maud::Markup {
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Reset your password" }
+            p .text-gray-600 {
+                "This link is invalid or has expired. "
+                a .text-blue-600 .hover:underline href="/reset-password" { "Request a new one" }
+                "."
+            }
+        }
+    };
+
+    shell::document(markup, "reset password", None)
+}
+
+fn reset_form(token: &str, errors: &ValidationErrors) -> maud::Markup {
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Choose a new password" }
+
+            form method="post" action="/reset-password/confirm" {
+                (form::csrf_field())
+                input type="hidden" name="token" value=(token);
+                (field_errors(errors, "token"))
+
+                div .mb-3 {
+                    label for="password" .block .mb-1 { "New password" }
+                    (form::input::<ResetForm>("password").kind("password").autocomplete("new-password"))
+                    (field_errors(errors, "password"))
+                }
+
+                div .mb-3 {
+                    label for="password_confirm" .block .mb-1 { "Confirm new password" }
+                    (form::input::<ResetForm>("password_confirm").kind("password").autocomplete("new-password"))
+                    (field_errors(errors, "password_confirm"))
+                }
+
+                div .mt-4 {
+                    input
+                        .text-neutral-50
+                        .bg-blue-500
+                        .hover:bg-blue-600
+                        .border-neutral-700
+                        .border-solid
+                        .border-1
+                        .px-4
+                        .py-2
+                        .cursor-pointer
+                        type="submit"
+                        value="Reset password";
+                }
+            }
+        }
+    };
+
+    shell::document(markup, "reset password", None)
+}
+
+#[derive(Deserialize, Validate)]
+struct ResetForm {
+    token: String,
+    #[validate(length(min = 8))]
+    password: String,
+    #[validate(must_match(other = "password", code = "password_mismatch"))]
+    password_confirm: String,
+}
+
+impl FormPage for ResetForm {
+    async fn render(
+        &self,
+        _state: &AppState,
+        _session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        Ok(reset_form(&self.token, errors))
+    }
+}
+
+async fn do_reset(
+    state: AppState,
+    _mail: MailEnabled,
+    locale: Locale,
+    ValidatedForm(form): ValidatedForm<ResetForm>,
+) -> Result<Response, AppError> {
+    let Some(owner) =
+        model::email::consume_token(&state.db, &form.token, TokenPurpose::Reset).await?
+    else {
+        let mut errors = ValidationErrors::new();
+        errors.add("token", ValidationError::new("link_expired"));
+        return Ok(form::rerender(&form, &state, None, locale, errors).await);
+    };
+
+    model::user::set_password(&state.db, owner.user_id, &form.password).await?;
+    model::session::delete_for_user(&state.db, owner.user_id).await?;
+    mail::notify(
+        &state,
+        owner.user_id,
+        "Your password was changed",
+        "The password for your account was just reset, and all of your sessions were logged out.",
+    )
+    .await?;
+
+    Ok(Redirect::to("/login").into_response())
+}
@@ -0,0 +1,108 @@
+use axum::Router;
+use axum::extract::{Form, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde::Deserialize;
+
+use crate::middleware::auth::Session;
+use crate::model;
+use crate::model::email::TokenPurpose;
+use crate::routes::{AppError, form, shell};
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/verify-email", get(page_verify).post(do_verify))
+}
+
+#[derive(Deserialize)]
+struct VerifyQuery {
+    token: String,
+}
+
+/// Asks before using up the token, since mail scanners and link previews
+/// fetch links without anyone having clicked them.
+async fn page_verify(
+    state: AppState,
+    session: Option<Session>,
+    Query(query): Query<VerifyQuery>,
+) -> Result<Response, AppError> {
+    let Some(owner) =
+        model::email::peek_token(&state.db, &query.token, TokenPurpose::Verify).await?
+    else {
+        return Ok(invalid_link(session));
+    };
+
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Email verification" }
+            p .mb-4 { "Confirm that " (owner.email) " is your email address." }
+
+            form method="post" action="/verify-email" {
+                (form::csrf_field())
+                input type="hidden" name="token" value=(query.token);
+                input
+                    .text-neutral-50
+                    .bg-blue-500
+                    .hover:bg-blue-600
+                    .border-neutral-700
+                    .border-solid
+                    .border-1
+                    .px-4
+                    .py-2
+                    .cursor-pointer
+                    type="submit"
+                    value="Verify email";
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "verify email", session).into_response())
+}
+
+#[derive(Deserialize)]
+struct VerifyForm {
+    token: String,
+}
+
+async fn do_verify(
+    state: AppState,
+    session: Option<Session>,
+    Form(form): Form<VerifyForm>,
+) -> Result<Response, AppError> {
+    let owner = model::email::consume_token(&state.db, &form.token, TokenPurpose::Verify).await?;
+
+    let verified = match &owner {
+        Some(owner) => model::email::mark_verified(&state.db, owner.user_id, &owner.email).await?,
+        None => false,
+    };
+
+    let Some(owner) = owner.filter(|_| verified) else {
+        return Ok(invalid_link(session));
+    };
+
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Email verification" }
+            p { "Thanks, " (owner.email) " is now verified." }
+        }
+    };
+
+    Ok(shell::document(markup, "verify email", session).into_response())
+}
+
+fn invalid_link(session: Option<Session>) -> Response {
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Email verification" }
+            p .text-gray-600 {
+                "This link is invalid or has expired. You can request a new one from your "
+                a .text-blue-600 .hover:underline href="/meta/profile" { "profile" }
+                "."
+            }
+        }
+    };
+
+    let markup = shell::document(markup, "verify email", session);
+    (StatusCode::BAD_REQUEST, markup).into_response()
+}
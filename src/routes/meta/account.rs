@@ -1,18 +1,231 @@
 use axum::Router;
-use axum::routing::get;
+use axum::body::Body;
+use axum::extract::{Form, Path};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+use tokio::fs;
+use tokio_util::io::ReaderStream;
 
+use crate::middleware::auth;
 use crate::middleware::auth::Session;
-use crate::routes::shell;
+use crate::model::export::Status;
+use crate::routes::form::{Locale, field_errors};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+use crate::{account, model};
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/meta/account", get(page_account))
+    Router::new()
+        .route("/meta/account", get(page_account))
+        .route("/meta/account/export", post(do_export))
+        .route("/meta/account/export/{id}", get(download_export))
+        .route("/meta/account/delete", post(do_delete))
 }
 
-async fn page_account(session: Session) -> maud::Markup {
+async fn page_account(state: AppState, session: Session) -> Result<Response, AppError> {
+    let markup = account_page(&state, session, &ValidationErrors::new()).await?;
+    Ok(markup.into_response())
+}
+
+async fn account_page(
+    state: &AppState,
+    session: Session,
+    errors: &ValidationErrors,
+) -> Result<maud::Markup, AppError> {
+    let exports = model::export::list(&state.db, session.id).await?;
+    let has_password = model::user::has_password(&state.db, session.id).await?;
+    let in_progress = exports
+        .iter()
+        .any(|export| matches!(export.status, Status::Pending | Status::Running));
+
     let markup = maud::html! {
         (super::meta_nav("account"))
+
+        h2 .text-xl .mt-4 .mb-2 { "Export your data" }
+        p .text-gray-600 .mb-4 {
+            "Download an archive of your profile, keys, pastes and list of repositories. "
+            "It is put together in the background and kept for "
+            (state.config.export.expiry_days) " days. We will email you once it is ready."
+        }
+
+        @if !exports.is_empty() {
+            table .w-full .text-sm .mb-4 {
+                thead {
+                    tr .text-left .border-b .border-gray-300 {
+                        th .py-1 { "Requested" }
+                        th .py-1 { "Status" }
+                        th .py-1 {}
+                    }
+                }
+                tbody {
+                    @for export in &exports {
+                        tr .border-b .border-gray-200 {
+                            td .py-1 { (export.created_at.date()) }
+                            td .py-1 {
+                                @match export.status {
+                                    Status::Pending | Status::Running => "in progress",
+                                    Status::Ready => "ready",
+                                    Status::Failed => span .text-red-600 { "failed" },
+                                }
+                            }
+                            td .py-1 .text-right {
+                                @if export.status == Status::Ready {
+                                    a .text-blue-600 .hover:underline href={ "/meta/account/export/" (export.id) } {
+                                        "download"
+                                        @if let Some(size) = export.size {
+                                            " (" ((size.max(0) as u64).div_ceil(1024)) " KiB)"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        @if !in_progress {
+            form method="post" action="/meta/account/export" .mb-4 {
+                (form::csrf_field())
+                input
+                    .text-neutral-50
+                    .bg-blue-500
+                    .border-neutral-700
+                    .border-solid
+                    .border-1
+                    .px-3
+                    .py-1
+                    .cursor-pointer
+                    type="submit"
+                    value="Request export";
+            }
+        }
+
+        h2 .text-xl .mt-6 .mb-2 { "Delete account" }
+        p .text-gray-600 .mb-2 {
+            "Deletes your account, pastes, keys, repositories and LFS objects. This cannot be undone."
+        }
+        form method="post" action="/meta/account/delete" .max-w-md {
+            (form::csrf_field())
+            (field_errors(errors, "account"))
+            div .mb-2 {
+                label for="confirm" .block .mb-1 { "Type your username to confirm" }
+                input .border-solid .border-1 .border-gray-300 .p-2 .w-full
+                    type="text" name="confirm" id="confirm" autocomplete="off";
+                (field_errors(errors, "confirm"))
+            }
+            @if has_password {
+                div .mb-2 {
+                    label for="password" .block .mb-1 { "Password" }
+                    input .border-solid .border-1 .border-gray-300 .p-2 .w-full
+                        type="password" name="password" id="password" autocomplete="current-password";
+                    (field_errors(errors, "password"))
+                }
+            }
+            input
+                .text-neutral-50
+                .bg-red-600
+                .hover:bg-red-700
+                .border-neutral-700
+                .border-solid
+                .border-1
+                .px-3
+                .py-1
+                .cursor-pointer
+                type="submit"
+                value="Delete account";
+        }
     };
 
-    shell::document(markup, "account", session)
+    Ok(shell::document(markup, "account", session))
+}
+
+async fn do_export(state: AppState, session: Session) -> Result<Redirect, AppError> {
+    account::request_export(&state, session.id).await?;
+    Ok(Redirect::to("/meta/account"))
+}
+
+async fn download_export(
+    state: AppState,
+    session: Session,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    if !model::export::is_ready(&state.db, session.id, id).await? {
+        return Err(AppError::NotFound);
+    }
+
+    let file = match fs::File::open(account::export_path(&state.config, id)).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(AppError::NotFound),
+        Err(err) => return Err(anyhow::Error::from(err).into()),
+    };
+
+    let size = file.metadata().await.map_err(anyhow::Error::from)?.len();
+    let mut response = Body::from_stream(ReaderStream::new(file)).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/gzip"),
+    );
+    headers.insert(
+        header::CONTENT_LENGTH,
+        HeaderValue::from_str(&size.to_string()).unwrap(),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!(
+            "attachment; filename=\"conduit-{}-{}.tar.gz\"",
+            session.username, id
+        ))
+        .unwrap(),
+    );
+
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct DeleteForm {
+    confirm: String,
+    #[serde(default)]
+    password: String,
+}
+
+async fn do_delete(
+    state: AppState,
+    session: Session,
+    jar: CookieJar,
+    Locale(catalog): Locale,
+    Form(form): Form<DeleteForm>,
+) -> Result<Response, AppError> {
+    let mut errors = ValidationErrors::new();
+
+    if form.confirm.trim() != session.username {
+        errors.add("confirm", ValidationError::new("username_mismatch"));
+    }
+
+    if model::user::has_password(&state.db, session.id).await?
+        && model::user::login(&state.db, &session.username, &form.password).await?
+            != Some(session.id)
+    {
+        errors.add("password", ValidationError::new("incorrect_password"));
+    }
+
+    // An instance should not be left without anyone to run it.
+    if model::user::is_last_admin(&state.db, session.id).await? {
+        errors.add("account", ValidationError::new("last_admin"));
+    }
+
+    if !errors.is_empty() {
+        let markup = account_page(&state, session, &errors.localize(catalog)).await?;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, markup).into_response());
+    }
+
+    account::delete(&state, session.id, &session.username).await?;
+
+    let jar = jar.remove(auth::COOKIE_NAME);
+    Ok((jar, Redirect::to("/")).into_response())
 }
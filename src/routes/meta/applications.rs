@@ -0,0 +1,86 @@
+use axum::Router;
+use axum::extract::Form;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::middleware::auth::Session;
+use crate::model;
+use crate::routes::{AppError, form, shell};
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/meta/applications", get(page_applications))
+        .route("/meta/applications/revoke", post(do_revoke))
+}
+
+async fn page_applications(state: AppState, session: Session) -> Result<Response, AppError> {
+    let authorizations = model::oauth::list_authorizations(&state.db, session.id).await?;
+
+    let markup = maud::html! {
+        (super::meta_nav("applications"))
+
+        h2 .text-xl .mt-4 .mb-2 { "Authorized applications" }
+        p .text-gray-600 .mb-4 {
+            "These applications can access your account. Revoking access signs them out "
+            "and makes them ask for your permission again."
+        }
+
+        @if authorizations.is_empty() {
+            p .text-gray-600 .mb-4 { "You have not authorized any applications." }
+        } @else {
+            div .mb-4 {
+                @for authorization in &authorizations {
+                    div .border-solid .border-1 .border-gray-300 .p-2 .mb-2 .flex .justify-between .items-start {
+                        div .flex-1 .overflow-hidden {
+                            div .font-semibold .mb-1 { (authorization.app_name) }
+                            ul .text-sm .list-disc .ml-5 {
+                                @for scope in &authorization.scopes {
+                                    li { (scope.description()) }
+                                }
+                            }
+                            div .text-sm .text-gray-600 .mt-1 {
+                                "Authorized " (format_time(authorization.created_at))
+                                @if let Some(last_used) = authorization.last_used {
+                                    ", last used " (format_time(last_used))
+                                }
+                            }
+                        }
+                        form method="post" action="/meta/applications/revoke" .ml-2 {
+                            (form::csrf_field())
+                            input type="hidden" name="app_id" value=(authorization.app_id);
+                            button .text-red-600 .hover:underline .text-sm type="submit" { "revoke" }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "applications", session).into_response())
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+    format!(
+        "{} {:02}:{:02} UTC",
+        time.date(),
+        time.hour(),
+        time.minute()
+    )
+}
+
+#[derive(Deserialize)]
+struct RevokeForm {
+    app_id: i32,
+}
+
+async fn do_revoke(
+    state: AppState,
+    session: Session,
+    Form(form): Form<RevokeForm>,
+) -> Result<Redirect, AppError> {
+    model::oauth::revoke(&state.db, session.id, form.app_id).await?;
+    Ok(Redirect::to("/meta/applications"))
+}
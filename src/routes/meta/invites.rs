@@ -0,0 +1,195 @@
+use axum::Router;
+use axum::extract::Form;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use conduit_derive::Validate;
+use serde::Deserialize;
+use time::Duration;
+
+use crate::config::RegistrationMode;
+use crate::middleware::auth::Session;
+use crate::model;
+use crate::routes::form::{FormPage, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
+use crate::state::AppState;
+use crate::validate::ValidationErrors;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/meta/invites", get(page_invites))
+        .route("/meta/invites", post(do_create))
+        .route("/meta/invites/revoke", post(do_revoke))
+}
+
+/// Administrators can always issue invites, other users only when the
+/// configuration allows it.
+fn can_invite(state: &AppState, session: &Session) -> bool {
+    session.is_admin || state.config.registration.user_invites
+}
+
+async fn page_invites(state: AppState, session: Session) -> Result<Response, AppError> {
+    let markup = invites_page(&state, session, None, &ValidationErrors::new()).await?;
+    Ok(markup.into_response())
+}
+
+async fn invites_page(
+    state: &AppState,
+    session: Session,
+    form: Option<&InviteForm>,
+    errors: &ValidationErrors,
+) -> Result<maud::Markup, AppError> {
+    if !can_invite(state, &session) {
+        let markup = maud::html! {
+            (super::meta_nav("invites"))
+
+            h2 .text-xl .mt-4 .mb-2 { "Invites" }
+            p .text-gray-600 .mb-4 { "Only administrators can invite people to register." }
+        };
+
+        return Ok(shell::document(markup, "invites", session));
+    }
+
+    let invites = model::invite::list_by_creator(&state.db, session.id).await?;
+    let note = form.map(|f| f.note.as_str());
+
+    let markup = maud::html! {
+        (super::meta_nav("invites"))
+
+        h2 .text-xl .mt-4 .mb-2 { "Invites" }
+        p .text-gray-600 .mb-4 {
+            "Each invite lets one person register. Unused invites expire after "
+            (state.config.registration.invite_expiry_days) " days."
+            @if state.config.registration.mode != RegistrationMode::Invite {
+                " Registration does not currently require an invite."
+            }
+        }
+
+        @if invites.is_empty() {
+            p .text-gray-600 .mb-4 { "You have not issued any invites." }
+        } @else {
+            table .w-full .text-sm .mb-4 {
+                thead {
+                    tr .text-left .border-b .border-gray-300 {
+                        th .py-1 { "Note" }
+                        th .py-1 { "Created" }
+                        th .py-1 { "Status" }
+                        th .py-1 {}
+                    }
+                }
+                tbody {
+                    @for invite in &invites {
+                        tr .border-b .border-gray-200 {
+                            td .py-1 { (invite.note) }
+                            td .py-1 { (invite.created_at.date()) }
+                            td .py-1 {
+                                @if let Some(used_at) = invite.used_at {
+                                    "used " (used_at.date())
+                                    @if let Some(used_by) = &invite.used_by {
+                                        " by " (used_by)
+                                    }
+                                } @else if invite.is_expired() {
+                                    span .text-gray-500 { "expired" }
+                                } @else {
+                                    "expires " (invite.expires.date())
+                                }
+                            }
+                            td .py-1 .text-right {
+                                @if invite.used_at.is_none() {
+                                    form method="post" action="/meta/invites/revoke" {
+                                        (form::csrf_field())
+                                        input type="hidden" name="id" value=(invite.id);
+                                        button .text-red-600 .hover:underline type="submit" { "revoke" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        h3 .text-lg .mt-6 .mb-2 { "New invite" }
+        form method="post" .max-w-md {
+            (form::csrf_field())
+            div .mb-3 {
+                label for="note" .block .mb-1 { "Note" }
+                (form::input::<InviteForm>("note").value(note).placeholder("Who the invite is for"))
+                (field_errors(errors, "note"))
+            }
+            input
+                .text-neutral-50
+                .bg-blue-500
+                .border-neutral-700
+                .border-solid
+                .border-1
+                .px-3
+                .py-1
+                type="submit"
+                value="Create invite";
+        }
+    };
+
+    Ok(shell::document(markup, "invites", session))
+}
+
+#[derive(Deserialize, Validate)]
+struct InviteForm {
+    #[serde(default)]
+    #[validate(length(max = 100))]
+    #[validate(non_control_character)]
+    note: String,
+}
+
+impl FormPage for InviteForm {
+    async fn render(
+        &self,
+        state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        let session = session.ok_or_else(|| anyhow::anyhow!("missing session"))?;
+        invites_page(state, session, Some(self), errors).await
+    }
+}
+
+async fn do_create(
+    state: AppState,
+    session: Session,
+    ValidatedForm(form): ValidatedForm<InviteForm>,
+) -> Result<maud::Markup, AppError> {
+    if !can_invite(&state, &session) {
+        return Err(AppError::Forbidden);
+    }
+
+    let lifetime = Duration::days(state.config.registration.invite_expiry_days.into());
+    let code = model::invite::create(&state.db, session.id, form.note.trim(), lifetime).await?;
+    let link = format!("{}/register?invite={}", state.config.http.public_url, code);
+
+    let markup = maud::html! {
+        (super::meta_nav("invites"))
+
+        h2 .text-xl .mt-4 .mb-4 { "Invite created" }
+        div .mb-4 {
+            div .text-sm .text-gray-600 { "Registration link" }
+            div .font-mono .break-all { (link) }
+        }
+        p .mb-4 { "Copy the link now. It will not be shown again." }
+        a .text-blue-600 .hover:underline href="/meta/invites" { "Done" }
+    };
+
+    Ok(shell::document(markup, "invites", session))
+}
+
+#[derive(Deserialize)]
+struct RevokeForm {
+    id: i32,
+}
+
+async fn do_revoke(
+    state: AppState,
+    session: Session,
+    Form(form): Form<RevokeForm>,
+) -> Result<Redirect, AppError> {
+    model::invite::revoke(&state.db, form.id, Some(session.id)).await?;
+    Ok(Redirect::to("/meta/invites"))
+}
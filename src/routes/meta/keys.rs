@@ -2,12 +2,19 @@ use axum::Router;
 use axum::extract::Form;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
+use conduit_derive::Validate;
 use serde::Deserialize;
+use time::{Date, Month, OffsetDateTime};
 
 use crate::middleware::auth::Session;
-use crate::model;
-use crate::routes::{AppError, shell};
+use crate::middleware::client::ClientInfo;
+use crate::model::audit::{Action, Event};
+use crate::model::user::NewKey;
+use crate::routes::form::{FormPage, Locale, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::validate::{SshPublicKey, ValidationError, ValidationErrors};
+use crate::{mail, model};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -17,6 +24,19 @@ pub fn routes() -> Router<AppState> {
 }
 
 async fn page_keys(state: AppState, session: Session) -> Result<Response, AppError> {
+    let markup = keys_form(&state, session, None, &ValidationErrors::new()).await?;
+    Ok(markup.into_response())
+}
+
+async fn keys_form(
+    state: &AppState,
+    session: Session,
+    form: Option<&AddKeyForm>,
+    errors: &ValidationErrors,
+) -> Result<maud::Markup, AppError> {
+    let name = form.map(|f| f.name.as_str());
+    let pubkey = form.map(|f| f.pubkey.as_str()).unwrap_or_default();
+    let expires = form.map(|f| f.expires.as_str());
     let keys = model::user::get_user_keys(&state.db, session.id).await?;
 
     let markup = maud::html! {
@@ -36,22 +56,39 @@ async fn page_keys(state: AppState, session: Session) -> Result<Response, AppErr
                             }
                             div .font-mono .text-sm {
                                 span .text-gray-600 { (key.key_type) " " }
-                                span .break-all { (truncate_key(&key.encoded)) }
+                                span .break-all { (key.fingerprint) }
                             }
                             div .text-sm .text-gray-600 .mt-1 {
-                                (key.username) "@" (key.hostname)
+                                (key.username) "@" (key.hostname) ", "
+                                @if let Some(last_used_at) = key.last_used_at {
+                                    "last used " (last_used_at.date())
+                                } @else {
+                                    "never used"
+                                }
+                                @if let Some(expires_at) = key.expires_at {
+                                    @if key.is_expired() {
+                                        ", " span .text-red-600 { "expired " (expires_at.date()) }
+                                    } @else {
+                                        ", expires " (expires_at.date())
+                                    }
+                                }
                             }
                         }
-                        form method="post" action="/meta/keys/delete" .ml-2 {
-                            input type="hidden" name="key_type" value=(key.key_type);
-                            input type="hidden" name="encoded" value=(key.encoded);
-                            button
-                                .text-red-600
-                                .hover:underline
-                                .text-sm
-                                type="submit"
-                            {
-                                "delete"
+                        @if key.managed {
+                            span .ml-2 .text-sm .text-gray-600 { "managed by directory" }
+                        } @else {
+                            form method="post" action="/meta/keys/delete" .ml-2 {
+                                (form::csrf_field())
+                                input type="hidden" name="key_type" value=(key.key_type);
+                                input type="hidden" name="encoded" value=(key.encoded);
+                                button
+                                    .text-red-600
+                                    .hover:underline
+                                    .text-sm
+                                    type="submit"
+                                {
+                                    "delete"
+                                }
                             }
                         }
                     }
@@ -61,37 +98,37 @@ async fn page_keys(state: AppState, session: Session) -> Result<Response, AppErr
 
         h3 .text-lg .mt-6 .mb-2 { "Add SSH Key" }
         form method="post" {
+            (form::csrf_field())
             div .mb-2 {
                 label for="name" .block .mb-1 { "Name" }
-                input
-                    .border-solid
-                    .border-1
-                    .border-gray-300
-                    .w-full
-                    .p-2
-                    type="text"
-                    name="name"
-                    placeholder="e.g., Laptop, Work Computer"
-                    required;
+                (form::input::<AddKeyForm>("name")
+                    .value(name)
+                    .placeholder("e.g., Laptop, Work Computer"))
+                (field_errors(errors, "name"))
             }
             div .mb-2 {
                 label for="pubkey" .block .mb-1 { "Public Key" }
-                textarea
-                    .border-solid
-                    .border-1
-                    .border-gray-300
-                    .w-full
-                    .font-mono
-                    .text-sm
-                    .p-2
-                    name="pubkey"
-                    rows="3"
-                    placeholder="ssh-ed25519 AAAAC3... user@hostname"
-                    required {}
+                (form::textarea::<AddKeyForm>("pubkey")
+                    .rows(3)
+                    .value(pubkey)
+                    .placeholder("ssh-ed25519 AAAAC3... user@hostname")
+                    .class("font-mono text-sm"))
+                (field_errors(errors, "pubkey"))
             }
             p .text-sm .text-gray-600 .mb-3 {
                 "Paste your public SSH key. Only ssh-ed25519 keys are supported."
             }
+            div .mb-3 {
+                label for="expires" .block .mb-1 { "Expires (optional)" }
+                (form::input::<AddKeyForm>("expires")
+                    .kind("date")
+                    .value(expires)
+                    .class("max-w-xs"))
+                (field_errors(errors, "expires"))
+                p .text-sm .text-gray-600 .mt-1 {
+                    "The key stops working after this day. You will get an email a week before."
+                }
+            }
             input
                 .text-neutral-50
                 .bg-blue-500
@@ -105,69 +142,109 @@ async fn page_keys(state: AppState, session: Session) -> Result<Response, AppErr
         }
     };
 
-    Ok(shell::document(markup, "keys", session).into_response())
+    Ok(shell::document(markup, "keys", session))
+}
+
+/// Parses the `YYYY-MM-DD` value of a date input into the end of that day.
+fn parse_expiry(value: &str) -> Option<OffsetDateTime> {
+    let mut parts = value.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse::<u8>().ok()?;
+    let day = parts.next()?.parse().ok()?;
+
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    Some(date.next_day()?.midnight().assume_utc())
 }
 
-fn truncate_key(key: &str) -> String {
-    if key.len() > 40 {
-        format!("{}...", &key[..40])
-    } else {
-        key.to_string()
+fn validate_expiry(value: &str) -> Result<(), ValidationError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+
+    match parse_expiry(value) {
+        Some(expires_at) if expires_at > OffsetDateTime::now_utc() => Ok(()),
+        Some(_) => Err(ValidationError::new("expiry_past")),
+        None => Err(ValidationError::new("expiry_format")),
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 struct AddKeyForm {
+    #[validate(length(min = 1, max = 100))]
+    #[validate(non_control_character)]
     name: String,
+    #[validate(ssh_public_key)]
+    #[validate(async_custom(function = "key_available"))]
     pubkey: String,
+    #[serde(default)]
+    #[validate(custom(function = "validate_expiry"))]
+    expires: String,
+}
+
+impl FormPage for AddKeyForm {
+    async fn render(
+        &self,
+        state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        let session = session.ok_or_else(|| anyhow::anyhow!("missing session"))?;
+        keys_form(state, session, Some(self), errors).await
+    }
+}
+
+async fn key_available(pubkey: &str, state: &AppState) -> Result<(), ValidationError> {
+    let Ok(key) = SshPublicKey::parse(pubkey) else {
+        // Malformed keys are reported by the `ssh_public_key` validator.
+        return Ok(());
+    };
+
+    match model::user::key_exists(&state.db, key.key_type, key.encoded).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(ValidationError::new("key_taken")),
+        Err(err) => Err(ValidationError::unavailable(err)),
+    }
 }
 
 async fn do_add_key(
     state: AppState,
     session: Session,
-    Form(form): Form<AddKeyForm>,
-) -> Result<Redirect, AppError> {
-    let pubkey = form.pubkey.trim();
+    client: ClientInfo,
+    locale: Locale,
+    ValidatedForm(form): ValidatedForm<AddKeyForm>,
+) -> Result<Response, AppError> {
+    let key = SshPublicKey::parse(&form.pubkey).map_err(anyhow::Error::from)?;
     let name = form.name.trim();
 
-    // Parse SSH key format: "ssh-ed25519 AAAAC3... user@hostname"
-    let parts: Vec<&str> = pubkey.split_whitespace().collect();
+    let (username, hostname) = model::user::key_comment_parts(key.comment);
 
-    if parts.len() < 2 {
-        return Err(anyhow::anyhow!(
-            "Invalid SSH key format. Expected: ssh-ed25519 <key> [comment]"
-        )
-        .into());
+    let new_key = NewKey {
+        key_type: key.key_type,
+        encoded: key.encoded,
+        username: &username,
+        hostname: &hostname,
+        name,
+        expires_at: parse_expiry(&form.expires),
+    };
+    // Someone may have added the same key since the form was validated.
+    if !model::user::add_user_key(&state.db, session.id, &new_key).await? {
+        let mut errors = ValidationErrors::new();
+        errors.add("pubkey", ValidationError::new("key_taken"));
+        return Ok(form::rerender(&form, &state, Some(session), locale, errors).await);
     }
 
-    let key_type = parts[0];
-    let encoded = parts[1];
-
-    // Validate key type
-    if key_type != "ssh-ed25519" {
-        return Err(anyhow::anyhow!("Only ssh-ed25519 keys are supported").into());
-    }
+    let event = Event::new(Action::KeyAdded, session.id, client.ip).detail(name);
+    model::audit::record(&state.db, event).await?;
 
-    // Parse comment (username@hostname) or use defaults
-    let (username, hostname) = if parts.len() >= 3 {
-        let comment = parts[2];
-        if let Some(at_pos) = comment.find('@') {
-            let user = &comment[..at_pos];
-            let host = &comment[at_pos + 1..];
-            (user.to_string(), host.to_string())
-        } else {
-            (comment.to_string(), "unknown".to_string())
-        }
-    } else {
-        ("unknown".to_string(), "unknown".to_string())
-    };
-
-    model::user::add_user_key(
-        &state.db, session.id, key_type, encoded, &username, &hostname, name,
+    mail::notify(
+        &state,
+        session.id,
+        "SSH key added",
+        &format!("The SSH key \"{}\" was added to your account.", name),
     )
     .await?;
 
-    Ok(Redirect::to("/meta/keys"))
+    Ok(Redirect::to("/meta/keys").into_response())
 }
 
 #[derive(Deserialize)]
@@ -178,9 +255,19 @@ struct DeleteKeyForm {
 
 async fn do_delete_key(
     state: AppState,
-    _session: Session,
+    session: Session,
+    client: ClientInfo,
     Form(form): Form<DeleteKeyForm>,
 ) -> Result<Redirect, AppError> {
-    model::user::delete_user_key(&state.db, &form.key_type, &form.encoded).await?;
+    let removed =
+        model::user::delete_user_key(&state.db, session.id, &form.key_type, &form.encoded).await?;
+    if !removed {
+        return Ok(Redirect::to("/meta/keys"));
+    }
+
+    let key = format!("{} {}", form.key_type, form.encoded);
+    let event = Event::new(Action::KeyRemoved, session.id, client.ip).detail(&key);
+    model::audit::record(&state.db, event).await?;
+
     Ok(Redirect::to("/meta/keys"))
 }
@@ -1,4 +1,6 @@
 mod account;
+mod applications;
+mod invites;
 mod keys;
 mod profile;
 mod security;
@@ -15,6 +17,8 @@ pub fn routes() -> Router<AppState> {
         .merge(keys::routes())
         .merge(account::routes())
         .merge(security::routes())
+        .merge(applications::routes())
+        .merge(invites::routes())
         .route("/meta", get(meta_redirect))
 }
 
@@ -24,6 +28,8 @@ fn meta_nav(current: &str) -> maud::Markup {
         ("account", "/meta/account"),
         ("keys", "/meta/keys"),
         ("security", "/meta/security"),
+        ("applications", "/meta/applications"),
+        ("invites", "/meta/invites"),
     ];
 
     maud::html! {
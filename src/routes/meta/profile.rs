@@ -1,96 +1,87 @@
 use axum::Router;
-use axum::extract::Form;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
+use conduit_derive::Validate;
 use serde::Deserialize;
 
 use crate::middleware::auth::Session;
-use crate::model;
-use crate::routes::{AppError, shell};
+use crate::routes::form::{FormPage, Locale, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+use crate::{account, mail, model};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/meta/profile", get(page_profile))
         .route("/meta/profile", post(do_update_profile))
+        .route("/meta/profile/verify", post(do_resend_verification))
+        .route("/meta/profile/username", post(do_change_username))
 }
 
 async fn page_profile(state: AppState, session: Session) -> Result<Response, AppError> {
+    let markup = profile_form(&state, session, None, None, &ValidationErrors::new()).await?;
+    Ok(markup.into_response())
+}
+
+async fn profile_form(
+    state: &AppState,
+    session: Session,
+    form: Option<&UpdateProfileForm>,
+    username_form: Option<&ChangeUsernameForm>,
+    errors: &ValidationErrors,
+) -> Result<maud::Markup, AppError> {
     let profile = model::user::get_profile(&state.db, session.id)
         .await?
         .ok_or_else(|| anyhow::anyhow!("User not found"))?;
 
+    let display_name = form.map_or(profile.display_name.as_str(), |f| &f.display_name);
+    let email = form.map_or(profile.email.as_str(), |f| &f.email);
+    let biography = form.map_or(profile.biography.as_str(), |f| &f.biography);
+    let username = username_form.map_or(profile.username.as_str(), |f| &f.username);
+
     let markup = maud::html! {
         (super::meta_nav("profile"))
 
         h2 .text-xl .mt-4 .mb-4 { "Edit your profile" }
 
         form method="post" {
-            div .mb-3 {
-                label for="username" .block .mb-1 { "Username" }
-                input
-                    .border-solid
-                    .border-1
-                    .border-gray-300
-                    .bg-gray-100
-                    .w-full
-                    .max-w-md
-                    .p-2
-                    .text-gray-600
-                    type="text"
-                    name="username"
-                    value=(profile.username)
-                    disabled;
-                p .text-sm .text-gray-500 .mt-1 {
-                    "Username cannot be changed."
-                }
-            }
-
+            (form::csrf_field())
             div .mb-3 {
                 label for="display_name" .block .mb-1 { "Display Name" }
-                input
-                    .border-solid
-                    .border-1
-                    .border-gray-300
-                    .w-full
-                    .max-w-md
-                    .p-2
-                    type="text"
-                    name="display_name"
-                    value=(profile.display_name)
-                    required;
+                (form::input::<UpdateProfileForm>("display_name")
+                    .value(Some(display_name))
+                    .class("max-w-md"))
+                (field_errors(errors, "display_name"))
             }
 
             div .mb-3 {
                 label for="email" .block .mb-1 { "Email" }
-                input
-                    .border-solid
-                    .border-1
-                    .border-gray-300
-                    .w-full
-                    .max-w-md
-                    .p-2
-                    type="email"
-                    name="email"
-                    value=(profile.email)
-                    required;
+                (form::input::<UpdateProfileForm>("email")
+                    .value(Some(email))
+                    .class("max-w-md"))
+                (field_errors(errors, "email"))
+                @if profile.email_verified {
+                    p .text-sm .text-green-700 .mt-1 { "Verified." }
+                } @else if mail::enabled(state) {
+                    p .text-sm .text-gray-500 .mt-1 {
+                        "Not verified yet. "
+                        button .text-blue-600 .hover:underline type="submit" form="resend-verification" {
+                            "Resend verification email"
+                        }
+                    }
+                } @else {
+                    p .text-sm .text-gray-500 .mt-1 { "Not verified." }
+                }
             }
 
             div .mb-3 {
                 label for="biography" .block .mb-1 { "Biography" }
-                textarea
-                    .border-solid
-                    .border-1
-                    .border-gray-300
-                    .w-full
-                    .max-w-md
-                    .p-2
-                    name="biography"
-                    rows="4"
-                    placeholder="Tell us about yourself..."
-                {
-                    (profile.biography)
-                }
+                (form::textarea::<UpdateProfileForm>("biography")
+                    .value(biography)
+                    .placeholder("Tell us about yourself...")
+                    .class("max-w-md"))
+                (field_errors(errors, "biography"))
             }
 
             div .mt-6 {
@@ -108,31 +99,146 @@ async fn page_profile(state: AppState, session: Session) -> Result<Response, App
                     value="Save Changes";
             }
         }
+
+        form #resend-verification method="post" action="/meta/profile/verify" {
+            (form::csrf_field())
+        }
+
+        h2 .text-xl .mt-8 .mb-2 { "Change username" }
+        p .text-gray-600 .mb-4 .max-w-md {
+            "Your repositories move to the new name. Links and SSH remotes using the old name "
+            "keep working until someone else takes it."
+        }
+
+        form method="post" action="/meta/profile/username" {
+            (form::csrf_field())
+            div .mb-3 {
+                label for="username" .block .mb-1 { "Username" }
+                (form::input::<ChangeUsernameForm>("username")
+                    .value(Some(username))
+                    .autocomplete("username")
+                    .class("max-w-md"))
+                (field_errors(errors, "username"))
+            }
+
+            input
+                .text-neutral-50
+                .bg-blue-500
+                .hover:bg-blue-600
+                .border-neutral-700
+                .border-solid
+                .border-1
+                .px-4
+                .py-2
+                .cursor-pointer
+                type="submit"
+                value="Change Username";
+        }
     };
 
-    Ok(shell::document(markup, "profile", session).into_response())
+    Ok(shell::document(markup, "profile", session))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 struct UpdateProfileForm {
+    #[validate(length(min = 1, max = 100))]
+    #[validate(non_control_character)]
     display_name: String,
+    #[validate(email)]
     email: String,
+    #[validate(length(max = 2000))]
     biography: String,
 }
 
+impl FormPage for UpdateProfileForm {
+    async fn render(
+        &self,
+        state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        let session = session.ok_or_else(|| anyhow::anyhow!("missing session"))?;
+        profile_form(state, session, Some(self), None, errors).await
+    }
+}
+
 async fn do_update_profile(
     state: AppState,
     session: Session,
-    Form(form): Form<UpdateProfileForm>,
-) -> Result<Redirect, AppError> {
-    model::user::update_profile(
+    locale: Locale,
+    ValidatedForm(form): ValidatedForm<UpdateProfileForm>,
+) -> Result<Response, AppError> {
+    // Uniqueness depends on who is asking, so it is checked here rather than
+    // by an `async_custom` validator.
+    let owner = model::user::get_id_by_email(&state.db, form.email.trim()).await?;
+    if owner.is_some_and(|owner| owner != session.id) {
+        let mut errors = ValidationErrors::new();
+        errors.add("email", ValidationError::new("email_taken"));
+        return Ok(form::rerender(&form, &state, Some(session), locale, errors).await);
+    }
+
+    let email = form.email.trim();
+    let email_changed = model::user::update_profile(
         &state.db,
         session.id,
-        form.email.trim(),
+        email,
         form.display_name.trim(),
         form.biography.trim(),
     )
     .await?;
 
+    if email_changed {
+        mail::send_verification(&state, session.id, &session.username, email).await?;
+    }
+
+    Ok(Redirect::to("/meta/profile").into_response())
+}
+
+#[derive(Deserialize, Validate)]
+struct ChangeUsernameForm {
+    #[validate(username)]
+    username: String,
+}
+
+impl FormPage for ChangeUsernameForm {
+    async fn render(
+        &self,
+        state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        let session = session.ok_or_else(|| anyhow::anyhow!("missing session"))?;
+        profile_form(state, session, None, Some(self), errors).await
+    }
+}
+
+async fn do_change_username(
+    state: AppState,
+    session: Session,
+    locale: Locale,
+    ValidatedForm(form): ValidatedForm<ChangeUsernameForm>,
+) -> Result<Response, AppError> {
+    if form.username == session.username {
+        return Ok(Redirect::to("/meta/profile").into_response());
+    }
+
+    if !account::rename(&state, session.id, &session.username, &form.username).await? {
+        let mut errors = ValidationErrors::new();
+        errors.add("username", ValidationError::new("username_taken"));
+        return Ok(form::rerender(&form, &state, Some(session), locale, errors).await);
+    }
+
+    Ok(Redirect::to("/meta/profile").into_response())
+}
+
+async fn do_resend_verification(state: AppState, session: Session) -> Result<Redirect, AppError> {
+    let profile = model::user::get_profile(&state.db, session.id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+
+    if !profile.email_verified {
+        mail::send_verification(&state, session.id, &profile.username, &profile.email).await?;
+    }
+
     Ok(Redirect::to("/meta/profile"))
 }
@@ -1,18 +1,447 @@
 use axum::Router;
-use axum::routing::get;
+use axum::extract::Form;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use conduit_derive::Validate;
+use maud::PreEscaped;
+use qrcode::QrCode;
+use qrcode::render::svg;
+use serde::Deserialize;
+use time::OffsetDateTime;
 
 use crate::middleware::auth::Session;
-use crate::routes::shell;
+use crate::middleware::client::ClientInfo;
+use crate::model::audit::{Action, Event};
+use crate::routes::form::{FormPage, Locale, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+use crate::{mail, model};
+
+/// How many audit log entries are shown on the security page.
+const ACTIVITY_LIMIT: i64 = 50;
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/meta/security", get(page_security))
+    Router::new()
+        .route("/meta/security", get(page_security))
+        .route("/meta/security/totp", post(do_begin_totp))
+        .route("/meta/security/totp/confirm", post(do_confirm_totp))
+        .route("/meta/security/totp/manage", post(do_manage_totp))
+        .route("/meta/security/sessions/revoke", post(do_revoke_session))
+        .route(
+            "/meta/security/sessions/revoke-all",
+            post(do_revoke_all_sessions),
+        )
+        .route("/meta/security/identities/unlink", post(do_unlink_identity))
+}
+
+async fn page_security(state: AppState, session: Session) -> Result<Response, AppError> {
+    let markup = security_page(&state, session, &ValidationErrors::new()).await?;
+    Ok(markup.into_response())
+}
+
+async fn security_page(
+    state: &AppState,
+    session: Session,
+    errors: &ValidationErrors,
+) -> Result<maud::Markup, AppError> {
+    let totp = model::two_factor::get_totp(&state.db, session.id).await?;
+    let required = state.config.auth.require_two_factor;
+
+    let two_factor = match &totp {
+        Some(totp) if totp.confirmed => {
+            let remaining = model::two_factor::count_recovery_codes(&state.db, session.id).await?;
+            maud::html! {
+                p .mb-3 {
+                    "Two-factor authentication is "
+                    span .font-semibold .text-green-700 { "on" }
+                    ". You have " (remaining) " unused recovery codes."
+                }
+
+                form method="post" action="/meta/security/totp/manage" .max-w-md {
+                    (form::csrf_field())
+                    div .mb-3 {
+                        label for="code" .block .mb-1 { "Authentication code" }
+                        (form::input::<CodeForm>("code").autocomplete("one-time-code"))
+                        (field_errors(errors, "code"))
+                    }
+                    div .flex .gap-2 {
+                        button .border-solid .border-1 .border-gray-300 .px-3 .py-1
+                            type="submit" name="action" value="recovery_codes"
+                        {
+                            "Regenerate recovery codes"
+                        }
+                        @if !required {
+                            button .text-red-600 .border-solid .border-1 .border-gray-300 .px-3 .py-1
+                                type="submit" name="action" value="disable"
+                            {
+                                "Disable"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Some(totp) => {
+            let url = totp.totp(&session.username)?.get_url();
+            let qr = QrCode::new(url.as_bytes())
+                .map_err(anyhow::Error::from)?
+                .render::<svg::Color<'_>>()
+                .min_dimensions(192, 192)
+                .build();
+
+            maud::html! {
+                p .mb-3 {
+                    "Scan this QR code with your authenticator app, then enter the code it shows to finish setting up."
+                }
+                div .mb-3 { (PreEscaped(qr)) }
+                p .text-sm .text-gray-600 .mb-3 {
+                    "Or enter the key manually: "
+                    code .font-mono .break-all { (totp.secret) }
+                }
+
+                form method="post" action="/meta/security/totp/confirm" .max-w-md {
+                    (form::csrf_field())
+                    div .mb-3 {
+                        label for="code" .block .mb-1 { "Authentication code" }
+                        (form::input::<CodeForm>("code").autocomplete("one-time-code"))
+                        (field_errors(errors, "code"))
+                    }
+                    (submit_button("Enable two-factor authentication"))
+                }
+            }
+        }
+        None => maud::html! {
+            @if required {
+                p .mb-3 .text-red-700 {
+                    "This site requires two-factor authentication. Set it up to continue."
+                }
+            }
+            p .mb-3 { "Two-factor authentication is off." }
+            form method="post" action="/meta/security/totp" {
+                (form::csrf_field())
+                (submit_button("Set up two-factor authentication"))
+            }
+        },
+    };
+
+    let identities = model::identity::list_for_user(&state.db, session.id).await?;
+    let sessions = model::session::list_for_user(&state.db, session.id).await?;
+    let activity = model::audit::list_for_user(&state.db, session.id, ACTIVITY_LIMIT).await?;
+
+    let markup = maud::html! {
+        (super::meta_nav("security"))
+
+        h2 .text-xl .mt-4 .mb-4 { "Two-factor authentication" }
+        (two_factor)
+
+        @if !state.config.oidc.is_empty() {
+            h2 .text-xl .mt-8 .mb-4 { "Linked accounts" }
+            (field_errors(errors, "identities"))
+            div .mb-4 {
+                @for provider in &state.config.oidc {
+                    @let linked = identities.iter().find(|identity| identity.provider == provider.id);
+                    div .border-solid .border-1 .border-gray-300 .p-2 .mb-2 .flex .justify-between .items-start {
+                        div .flex-1 {
+                            div .font-semibold .mb-1 { (provider.name) }
+                            @if let Some(identity) = linked {
+                                div .text-sm .text-gray-600 {
+                                    @if let Some(email) = &identity.email {
+                                        (email) ", "
+                                    }
+                                    "linked " (format_time(identity.created_at))
+                                    @if let Some(last_login) = identity.last_login {
+                                        ", last used " (format_time(last_login))
+                                    }
+                                }
+                            } @else {
+                                div .text-sm .text-gray-600 { "Not linked" }
+                            }
+                        }
+                        @if linked.is_some() {
+                            form method="post" action="/meta/security/identities/unlink" .ml-2 {
+                                (form::csrf_field())
+                                input type="hidden" name="provider" value=(provider.id);
+                                button .text-red-600 .hover:underline .text-sm type="submit" { "unlink" }
+                            }
+                        } @else {
+                            form method="post" action={ "/login/oidc/" (provider.id) "/link" } .ml-2 {
+                                (form::csrf_field())
+                                button .text-blue-600 .hover:underline .text-sm type="submit" { "link" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        h2 .text-xl .mt-8 .mb-4 { "Sessions" }
+        div .mb-4 {
+            @for item in &sessions {
+                div .border-solid .border-1 .border-gray-300 .p-2 .mb-2 .flex .justify-between .items-start {
+                    div .flex-1 .overflow-hidden {
+                        div .font-semibold .mb-1 {
+                            (item.ip.as_deref().unwrap_or("unknown address"))
+                            @if item.id == session.session_id {
+                                span .ml-2 .text-xs .bg-green-100 .text-green-800 .px-2 .py-1 .rounded { "this session" }
+                            }
+                        }
+                        div .text-sm .text-gray-600 .truncate {
+                            (item.user_agent.as_deref().unwrap_or("unknown browser"))
+                        }
+                        div .text-sm .text-gray-600 .mt-1 {
+                            "Signed in " (format_time(item.created_at)) ", last seen " (format_time(item.last_seen))
+                        }
+                    }
+                    form method="post" action="/meta/security/sessions/revoke" .ml-2 {
+                        (form::csrf_field())
+                        input type="hidden" name="id" value=(item.id);
+                        button .text-red-600 .hover:underline .text-sm type="submit" { "revoke" }
+                    }
+                }
+            }
+        }
+        form method="post" action="/meta/security/sessions/revoke-all" {
+            (form::csrf_field())
+            (submit_button("Log out everywhere"))
+        }
+
+        h2 .text-xl .mt-8 .mb-2 { "Recent activity" }
+        p .text-gray-600 .mb-4 {
+            "Security-relevant events on your account, including those done by administrators."
+        }
+        @if activity.is_empty() {
+            p .text-gray-600 .mb-4 { "Nothing yet." }
+        } @else {
+            table .w-full .text-sm .mb-4 {
+                thead {
+                    tr .text-left .border-b .border-gray-300 {
+                        th .py-1 { "Time" }
+                        th .py-1 { "Event" }
+                        th .py-1 { "By" }
+                        th .py-1 { "Address" }
+                    }
+                }
+                tbody {
+                    @for entry in &activity {
+                        tr .border-b .border-gray-200 {
+                            td .py-1 .whitespace-nowrap { (format_time(entry.created_at)) }
+                            td .py-1 {
+                                (entry.describe())
+                                @if !entry.detail.is_empty() {
+                                    div .text-gray-500 .truncate .max-w-md { (entry.detail) }
+                                }
+                            }
+                            td .py-1 { (entry.actor.as_deref().unwrap_or("-")) }
+                            td .py-1 .font-mono { (entry.ip.as_deref().unwrap_or("-")) }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "security", session))
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+    format!(
+        "{} {:02}:{:02} UTC",
+        time.date(),
+        time.hour(),
+        time.minute()
+    )
 }
 
-async fn page_security(session: Session) -> maud::Markup {
+fn submit_button(label: &str) -> maud::Markup {
+    maud::html! {
+        input
+            .text-neutral-50
+            .bg-blue-500
+            .hover:bg-blue-600
+            .border-neutral-700
+            .border-solid
+            .border-1
+            .px-4
+            .py-2
+            .cursor-pointer
+            type="submit"
+            value=(label);
+    }
+}
+
+fn recovery_codes_page(session: Session, codes: &[String]) -> maud::Markup {
     let markup = maud::html! {
         (super::meta_nav("security"))
+
+        h2 .text-xl .mt-4 .mb-4 { "Recovery codes" }
+        p .mb-3 {
+            "Save these codes somewhere safe. Each one can be used once to log in if you "
+            "lose access to your authenticator app. They will not be shown again."
+        }
+        ul .font-mono .mb-4 .grid .grid-cols-2 .gap-1 .max-w-xs {
+            @for code in codes {
+                li { (code) }
+            }
+        }
+        a .text-blue-600 .hover:underline href="/meta/security" { "Done" }
     };
 
-    shell::document(markup, "security", session)
+    shell::document(markup, "recovery codes", session)
+}
+
+#[derive(Deserialize, Validate)]
+struct CodeForm {
+    #[validate(length(min = 1, code = "required"))]
+    code: String,
+    #[serde(default)]
+    action: String,
+}
+
+impl FormPage for CodeForm {
+    async fn render(
+        &self,
+        state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        let session = session.ok_or_else(|| anyhow::anyhow!("missing session"))?;
+        security_page(state, session, errors).await
+    }
+}
+
+fn invalid_code() -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add("code", ValidationError::new("invalid_code"));
+    errors
+}
+
+async fn do_begin_totp(state: AppState, session: Session) -> Result<Redirect, AppError> {
+    model::two_factor::begin_enrolment(&state.db, session.id).await?;
+    Ok(Redirect::to("/meta/security"))
+}
+
+async fn do_confirm_totp(
+    state: AppState,
+    session: Session,
+    locale: Locale,
+    ValidatedForm(form): ValidatedForm<CodeForm>,
+) -> Result<Response, AppError> {
+    let code = form.code.trim();
+    let Some(codes) = model::two_factor::confirm_enrolment(&state.db, session.id, code).await?
+    else {
+        return Ok(form::rerender(&form, &state, Some(session), locale, invalid_code()).await);
+    };
+
+    mail::notify(
+        &state,
+        session.id,
+        "Two-factor authentication enabled",
+        "Two-factor authentication was turned on for your account.",
+    )
+    .await?;
+
+    Ok(recovery_codes_page(session, &codes).into_response())
+}
+
+async fn do_manage_totp(
+    state: AppState,
+    session: Session,
+    locale: Locale,
+    ValidatedForm(form): ValidatedForm<CodeForm>,
+) -> Result<Response, AppError> {
+    if !model::two_factor::verify(&state.db, session.id, &form.code).await? {
+        return Ok(form::rerender(&form, &state, Some(session), locale, invalid_code()).await);
+    }
+
+    match form.action.as_str() {
+        "recovery_codes" => {
+            let codes = model::two_factor::replace_recovery_codes(&state.db, session.id).await?;
+            mail::notify(
+                &state,
+                session.id,
+                "New recovery codes",
+                "New two-factor recovery codes were generated for your account. The old codes no longer work.",
+            )
+            .await?;
+
+            Ok(recovery_codes_page(session, &codes).into_response())
+        }
+        "disable" if !state.config.auth.require_two_factor => {
+            model::two_factor::disable(&state.db, session.id).await?;
+            mail::notify(
+                &state,
+                session.id,
+                "Two-factor authentication disabled",
+                "Two-factor authentication was turned off for your account.",
+            )
+            .await?;
+
+            Ok(Redirect::to("/meta/security").into_response())
+        }
+        _ => Err(AppError::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+struct RevokeForm {
+    id: i64,
+}
+
+async fn do_revoke_session(
+    state: AppState,
+    session: Session,
+    client: ClientInfo,
+    Form(form): Form<RevokeForm>,
+) -> Result<Redirect, AppError> {
+    model::session::revoke(&state.db, session.id, form.id).await?;
+
+    let detail = format!("session {}", form.id);
+    let event = Event::new(Action::SessionRevoked, session.id, client.ip).detail(&detail);
+    model::audit::record(&state.db, event).await?;
+
+    if form.id == session.session_id {
+        return Ok(Redirect::to("/"));
+    }
+
+    Ok(Redirect::to("/meta/security"))
+}
+
+async fn do_revoke_all_sessions(
+    state: AppState,
+    session: Session,
+    client: ClientInfo,
+) -> Result<Redirect, AppError> {
+    model::session::delete_for_user(&state.db, session.id).await?;
+
+    let event = Event::new(Action::SessionRevoked, session.id, client.ip).detail("all sessions");
+    model::audit::record(&state.db, event).await?;
+    Ok(Redirect::to("/"))
+}
+
+#[derive(Deserialize)]
+struct UnlinkForm {
+    provider: String,
+}
+
+async fn do_unlink_identity(
+    state: AppState,
+    session: Session,
+    Locale(catalog): Locale,
+    Form(form): Form<UnlinkForm>,
+) -> Result<Response, AppError> {
+    let identities = model::identity::list_for_user(&state.db, session.id).await?;
+    let has_password = model::user::has_password(&state.db, session.id).await?;
+
+    // Keep at least one way to log in.
+    if !has_password && identities.len() <= 1 {
+        let mut errors = ValidationErrors::new();
+        errors.add("identities", ValidationError::new("last_login_method"));
+        let markup = security_page(&state, session, &errors.localize(catalog)).await?;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, markup).into_response());
+    }
+
+    model::identity::unlink(&state.db, session.id, &form.provider).await?;
+    Ok(Redirect::to("/meta/security").into_response())
 }
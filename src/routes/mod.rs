@@ -1,11 +1,15 @@
+mod admin;
+mod api;
 mod assets;
 #[cfg(debug_assertions)]
 mod autoreload;
 mod error;
+mod form;
 mod hub;
 mod lfs;
 mod login;
 mod meta;
+mod oauth;
 mod paste;
 mod shell;
 
@@ -17,6 +21,9 @@ pub use error::AppError;
 use crate::middleware::auth::Session;
 use crate::state::AppState;
 
+/// Later pages are clamped to this one, so that offsets cannot overflow.
+const MAX_PAGE: i64 = 100_000;
+
 pub fn routes() -> Router<AppState> {
     let autoreload = cfg_select! {
         debug_assertions => { autoreload::routes() }
@@ -24,17 +31,27 @@ pub fn routes() -> Router<AppState> {
     };
 
     Router::new()
+        .merge(admin::routes())
+        .merge(api::routes())
         .merge(assets::routes())
         .merge(autoreload)
         .merge(login::routes())
         .merge(hub::routes())
         .merge(lfs::routes())
         .merge(meta::routes())
+        .merge(oauth::routes())
         .merge(paste::routes())
         .route("/", get(page))
         .fallback(fallback)
 }
 
+/// The clamped 1-based page number from a query and the offset of its first
+/// row.
+pub fn paginate(page: Option<i64>, page_size: i64) -> (i64, i64) {
+    let page = page.unwrap_or(1).clamp(1, MAX_PAGE);
+    (page, (page - 1) * page_size)
+}
+
 async fn page(session: Option<Session>) -> maud::Markup {
     let markup = maud::html! {
         h1 { "Hello, World!" }
@@ -0,0 +1,447 @@
+use std::net::IpAddr;
+
+use axum::Router;
+use axum::extract::{Form, Json, Query};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use base64::engine::Engine;
+use base64::engine::general_purpose::{
+    STANDARD as BASE64_STANDARD, URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::middleware::auth::Session;
+use crate::middleware::client::ClientInfo;
+use crate::model;
+use crate::model::audit::{Action, Event};
+use crate::model::oauth::{App, Grant, Scope, TokenPair};
+use crate::routes::{AppError, form, shell};
+use crate::state::AppState;
+use crate::utils::{self, re};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/oauth/authorize", get(page_authorize).post(do_authorize))
+        .route("/oauth/token", post(do_token))
+}
+
+#[derive(Deserialize)]
+struct AuthorizeParams {
+    response_type: Option<String>,
+    client_id: Option<String>,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+/// An authorization request that has been checked against the registered
+/// application.
+struct AuthorizeRequest {
+    app: App,
+    redirect_uri: String,
+    scopes: Vec<Scope>,
+    state: Option<String>,
+    code_challenge: String,
+}
+
+impl AuthorizeRequest {
+    /// Send the user back to the application with the outcome.
+    fn respond(&self, params: &[(&str, &str)]) -> Response {
+        let mut url = Url::parse(&self.redirect_uri).expect("redirect URIs are validated");
+        {
+            let mut query = url.query_pairs_mut();
+            query.extend_pairs(params);
+            if let Some(state) = &self.state {
+                query.append_pair("state", state);
+            }
+        }
+
+        Redirect::to(url.as_str()).into_response()
+    }
+
+    fn error(&self, error: &str, description: &str) -> Response {
+        self.respond(&[("error", error), ("error_description", description)])
+    }
+
+    async fn grant(&self, state: &AppState, session: &Session) -> Result<Response, AppError> {
+        let grant = Grant {
+            app_id: self.app.id,
+            user_id: session.id,
+            redirect_uri: self.redirect_uri.clone(),
+            scopes: self.scopes.clone(),
+            code_challenge: self.code_challenge.clone(),
+        };
+
+        let code = model::oauth::create_code(&state.db, &grant).await?;
+        Ok(self.respond(&[("code", &code)]))
+    }
+}
+
+/// Check an authorization request. Until the client and redirect URI are
+/// known to be good, errors are shown to the user instead of being sent
+/// back, so that conduit cannot be used as an open redirect.
+async fn check_request(
+    state: &AppState,
+    session: &Session,
+    params: AuthorizeParams,
+) -> Result<Result<AuthorizeRequest, Response>, AppError> {
+    let app = match &params.client_id {
+        Some(client_id) => model::oauth::get_app(&state.db, client_id).await?,
+        None => None,
+    };
+    let Some(app) = app else {
+        return Ok(Err(failure(session, "The application is not registered.")));
+    };
+
+    let redirect_uri = match params.redirect_uri {
+        Some(uri) => app.redirect_uris.contains(&uri).then_some(uri),
+        None if app.redirect_uris.len() == 1 => app.redirect_uris.first().cloned(),
+        None => None,
+    };
+    let Some(redirect_uri) = redirect_uri else {
+        return Ok(Err(failure(
+            session,
+            "The application sent you here with a redirect URI it has not registered.",
+        )));
+    };
+
+    let mut request = AuthorizeRequest {
+        app,
+        redirect_uri,
+        scopes: Vec::new(),
+        state: params.state,
+        code_challenge: String::new(),
+    };
+
+    if params.response_type.as_deref() != Some("code") {
+        let error = request.error(
+            "unsupported_response_type",
+            "only the authorization code flow is supported",
+        );
+        return Ok(Err(error));
+    }
+
+    match (
+        params.code_challenge,
+        params.code_challenge_method.as_deref(),
+    ) {
+        (Some(challenge), Some("S256")) if re!(r"^[A-Za-z0-9_-]{43}$").is_match(&challenge) => {
+            request.code_challenge = challenge;
+        }
+        _ => {
+            let error = request.error("invalid_request", "PKCE with S256 is required");
+            return Ok(Err(error));
+        }
+    }
+
+    let scope = params.scope.unwrap_or_default();
+    request.scopes = match model::oauth::parse_scopes(&scope) {
+        Some(scopes) if scopes.is_empty() => vec![Scope::Profile],
+        Some(scopes) => scopes,
+        None => {
+            let error = request.error("invalid_scope", "unknown scope requested");
+            return Ok(Err(error));
+        }
+    };
+
+    Ok(Ok(request))
+}
+
+async fn page_authorize(
+    state: AppState,
+    session: Session,
+    Query(params): Query<AuthorizeParams>,
+) -> Result<Response, AppError> {
+    let request = match check_request(&state, &session, params).await? {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+
+    // Applications that were already given these scopes are sent straight
+    // back, so that logging in again does not ask for consent every time.
+    let granted = model::oauth::granted_scopes(&state.db, session.id, request.app.id).await?;
+    if request.scopes.iter().all(|scope| granted.contains(scope)) {
+        return request.grant(&state, &session).await;
+    }
+
+    Ok(consent_page(session, &request).into_response())
+}
+
+fn consent_page(session: Session, request: &AuthorizeRequest) -> maud::Markup {
+    let scope = model::oauth::format_scopes(&request.scopes);
+    let host = Url::parse(&request.redirect_uri)
+        .ok()
+        .and_then(|url| url.host_str().map(ToOwned::to_owned))
+        .unwrap_or_default();
+
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Authorize " (request.app.name) }
+            p .mb-3 {
+                (request.app.name) " wants to access your account "
+                span .font-semibold { (session.username) }
+                ". It will be able to:"
+            }
+            ul .list-disc .ml-6 .mb-4 {
+                @for scope in &request.scopes {
+                    li { (scope.description()) }
+                }
+            }
+            p .text-sm .text-gray-600 .mb-4 {
+                "You will be sent back to " span .font-mono { (host) } ". "
+                "You can revoke access at any time from your "
+                a .text-blue-600 .hover:underline href="/meta/applications" { "applications" }
+                " settings."
+            }
+            form method="post" action="/oauth/authorize" .flex .gap-2 {
+                (form::csrf_field())
+                input type="hidden" name="response_type" value="code";
+                input type="hidden" name="client_id" value=(request.app.client_id);
+                input type="hidden" name="redirect_uri" value=(request.redirect_uri);
+                input type="hidden" name="scope" value=(scope);
+                @if let Some(state) = &request.state {
+                    input type="hidden" name="state" value=(state);
+                }
+                input type="hidden" name="code_challenge" value=(request.code_challenge);
+                input type="hidden" name="code_challenge_method" value="S256";
+                button
+                    .text-neutral-50
+                    .bg-blue-500
+                    .hover:bg-blue-600
+                    .border-neutral-700
+                    .border-solid
+                    .border-1
+                    .px-4
+                    .py-2
+                    .cursor-pointer
+                    type="submit"
+                    name="decision"
+                    value="approve"
+                {
+                    "Authorize"
+                }
+                button
+                    .px-4
+                    .py-2
+                    .border-solid
+                    .border-1
+                    .border-gray-300
+                    .hover:bg-gray-100
+                    .cursor-pointer
+                    type="submit"
+                    name="decision"
+                    value="deny"
+                {
+                    "Cancel"
+                }
+            }
+        }
+    };
+
+    shell::document(markup, "authorize", session)
+}
+
+#[derive(Deserialize)]
+struct ConsentForm {
+    #[serde(flatten)]
+    params: AuthorizeParams,
+    decision: String,
+}
+
+async fn do_authorize(
+    state: AppState,
+    session: Session,
+    Form(form): Form<ConsentForm>,
+) -> Result<Response, AppError> {
+    let request = match check_request(&state, &session, form.params).await? {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+
+    if form.decision != "approve" {
+        return Ok(request.error("access_denied", "the user denied the request"));
+    }
+
+    model::oauth::authorize(&state.db, session.id, request.app.id, &request.scopes).await?;
+    request.grant(&state, &session).await
+}
+
+fn failure(session: &Session, message: &str) -> Response {
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Authorization failed" }
+            p .mb-4 .text-red-600 { (message) }
+            p .text-gray-600 {
+                a .text-blue-600 .hover:underline href="/" { "Back to conduit" }
+            }
+        }
+    };
+
+    (
+        StatusCode::BAD_REQUEST,
+        shell::document(markup, "authorize", session.clone()),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    grant_type: Option<String>,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+/// The token endpoint is called by applications rather than browsers, so
+/// it answers in JSON and is not covered by CSRF protection as long as no
+/// cookies are sent.
+async fn do_token(
+    state: AppState,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Form(form): Form<TokenForm>,
+) -> Result<Response, AppError> {
+    let Some(app) = authenticate_client(&state, &headers, &form).await? else {
+        return Ok(token_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "client authentication failed",
+        ));
+    };
+
+    let pair = match form.grant_type.as_deref() {
+        Some("authorization_code") => exchange_code(&state, &app, &form, client.ip).await?,
+        Some("refresh_token") => match &form.refresh_token {
+            Some(token) => model::oauth::refresh(&state.db, token, app.id).await?,
+            None => None,
+        },
+        _ => {
+            return Ok(token_error(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                "only authorization_code and refresh_token are supported",
+            ));
+        }
+    };
+
+    let Some(pair) = pair else {
+        return Ok(token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "the grant is invalid, expired or was issued to another client",
+        ));
+    };
+
+    let body = serde_json::json!({
+        "access_token": pair.access_token,
+        "token_type": "Bearer",
+        "expires_in": pair.expires_in,
+        "refresh_token": pair.refresh_token,
+        "scope": model::oauth::format_scopes(&pair.scopes),
+    });
+
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(body)).into_response())
+}
+
+/// Identify the client through HTTP basic authentication or the form.
+/// Confidential clients must present their secret.
+async fn authenticate_client(
+    state: &AppState,
+    headers: &HeaderMap,
+    form: &TokenForm,
+) -> Result<Option<App>, AppError> {
+    let basic = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| BASE64_STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| {
+            let (id, secret) = decoded.split_once(':')?;
+            Some((id.to_owned(), secret.to_owned()))
+        });
+
+    let (client_id, secret) = match basic {
+        Some((id, secret)) => (Some(id), Some(secret)),
+        None => (form.client_id.clone(), form.client_secret.clone()),
+    };
+
+    let Some(client_id) = client_id else {
+        return Ok(None);
+    };
+    let Some(app) = model::oauth::get_app(&state.db, &client_id).await? else {
+        return Ok(None);
+    };
+
+    if app.is_confidential() && !secret.is_some_and(|secret| app.check_secret(&secret)) {
+        return Ok(None);
+    }
+
+    Ok(Some(app))
+}
+
+/// Tokens are only recorded in the audit log when first issued, not each
+/// time they are refreshed.
+async fn exchange_code(
+    state: &AppState,
+    app: &App,
+    form: &TokenForm,
+    ip: IpAddr,
+) -> Result<Option<TokenPair>, AppError> {
+    let (Some(code), Some(verifier)) = (&form.code, &form.code_verifier) else {
+        return Ok(None);
+    };
+
+    let Some(grant) = model::oauth::take_code(&state.db, code, app.id).await? else {
+        return Ok(None);
+    };
+
+    if form
+        .redirect_uri
+        .as_ref()
+        .is_some_and(|uri| *uri != grant.redirect_uri)
+    {
+        return Ok(None);
+    }
+
+    if !re!(r"^[A-Za-z0-9._~-]{43,128}$").is_match(verifier) {
+        return Ok(None);
+    }
+
+    let challenge = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    if !utils::constant_time_eq(&challenge, &grant.code_challenge) {
+        return Ok(None);
+    }
+
+    let pair = model::oauth::issue_tokens(&state.db, app.id, grant.user_id, &grant.scopes).await?;
+
+    let event = Event::new(Action::TokenCreated, grant.user_id, ip).detail(&app.name);
+    model::audit::record(&state.db, event).await?;
+
+    Ok(Some(pair))
+}
+
+fn token_error(status: StatusCode, error: &str, description: &str) -> Response {
+    let body = serde_json::json!({
+        "error": error,
+        "error_description": description,
+    });
+
+    let mut response = (status, [(header::CACHE_CONTROL, "no-store")], Json(body)).into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            header::HeaderValue::from_static("Basic realm=\"conduit\""),
+        );
+    }
+
+    response
+}
@@ -0,0 +1,120 @@
+use axum::Router;
+use axum::extract::Query;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde::Deserialize;
+
+use crate::middleware::auth::Session;
+use crate::model;
+use crate::routes::{self, AppError, shell};
+use crate::state::AppState;
+
+const PAGE_SIZE: i64 = 25;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/paste/explore", get(page_explore))
+}
+
+#[derive(Deserialize)]
+struct ExploreQuery {
+    q: Option<String>,
+    page: Option<i64>,
+}
+
+async fn page_explore(
+    state: AppState,
+    session: Option<Session>,
+    Query(query): Query<ExploreQuery>,
+) -> Result<Response, AppError> {
+    let search = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let (page, offset) = routes::paginate(query.page, PAGE_SIZE);
+
+    // Fetch one extra row to find out whether there is a next page.
+    let mut pastes = match search {
+        Some(search) => {
+            let viewer = session.as_ref().map(|s| s.id);
+            model::paste::search(&state.db, viewer, search, PAGE_SIZE + 1, offset).await?
+        }
+        None => model::paste::list_public(&state.db, PAGE_SIZE + 1, offset).await?,
+    };
+
+    let has_next = pastes.len() as i64 > PAGE_SIZE;
+    pastes.truncate(PAGE_SIZE as usize);
+
+    let markup = maud::html! {
+        h2 .text-xl .mb-4 { "Explore Pastes" }
+
+        form method="get" action="/paste/explore" .mb-4 .flex .gap-2 {
+            input
+                .border-solid
+                .border-1
+                .border-gray-300
+                .grow
+                .p-2
+                type="search"
+                name="q"
+                placeholder="Search filenames and contents"
+                value=[search];
+            input
+                .text-neutral-50
+                .bg-blue-500
+                .hover:bg-blue-600
+                .border-neutral-700
+                .border-solid
+                .border-1
+                .px-4
+                .py-2
+                .cursor-pointer
+                type="submit"
+                value="Search";
+        }
+
+        @if pastes.is_empty() {
+            p .text-gray-600 .mb-4 {
+                @if search.is_some() { "No pastes match your search." } @else { "No public pastes yet." }
+            }
+        } @else {
+            div .mb-4 {
+                @for paste in &pastes {
+                    div .border-solid .border-1 .border-gray-300 .p-3 .mb-2 .flex .justify-between .items-center {
+                        div .flex-1 {
+                            a .font-mono .text-blue-600 .hover:underline href=(format!("/~{}/paste/{}", paste.username, paste.id)) {
+                                (paste.filename)
+                            }
+                            @if paste.file_count > 1 {
+                                span .text-gray-500 .text-sm .ml-2 { "+" (paste.file_count - 1) " more" }
+                            }
+                            span .text-gray-500 .text-sm .ml-3 {
+                                "by " a .hover:underline href=(format!("/~{}", paste.username)) { (paste.username) }
+                            }
+                            @if paste.visibility != "public" {
+                                span .ml-3 .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded { (paste.visibility) }
+                            }
+                        }
+                        span .text-gray-500 .text-sm { (paste.created_at.date()) }
+                    }
+                }
+            }
+        }
+
+        div .flex .gap-4 {
+            @if page > 1 {
+                a .text-blue-600 .hover:underline href=(page_href(search, page - 1)) { "← Newer" }
+            }
+            @if has_next {
+                a .text-blue-600 .hover:underline href=(page_href(search, page + 1)) { "Older →" }
+            }
+        }
+    };
+
+    Ok(shell::document(markup, "explore pastes", session).into_response())
+}
+
+fn page_href(search: Option<&str>, page: i64) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(search) = search {
+        query.append_pair("q", search);
+    }
+    query.append_pair("page", &page.to_string());
+    format!("/paste/explore?{}", query.finish())
+}
@@ -6,7 +6,7 @@ use serde::Deserialize;
 
 use crate::middleware::auth::Session;
 use crate::model;
-use crate::routes::{AppError, shell};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
 
 pub fn routes() -> Router<AppState> {
@@ -42,9 +42,13 @@ async fn page_manage(state: AppState, session: Session) -> Result<Response, AppE
                                 } @else {
                                     span .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded { "private" }
                                 }
+                                @if paste.protected {
+                                    span .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded .ml-1 { "password" }
+                                }
                             }
                         }
                         form method="post" action="/paste/manage/delete" .ml-2 {
+                            (form::csrf_field())
                             input type="hidden" name="paste_id" value=(paste.id);
                             button
                                 .text-red-600
@@ -1,46 +1,66 @@
+mod explore;
 mod manage;
 mod view;
 
 use axum::Router;
-use axum::extract::Form;
-use axum::response::Redirect;
+use axum::extract::DefaultBodyLimit;
+use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
+use conduit_derive::Validate;
 use serde::Deserialize;
 
 use crate::middleware::auth::Session;
 use crate::model;
-use crate::routes::{AppError, shell};
+use crate::routes::form::{FormPage, Locale, ValidatedForm, field_errors};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::validate::{ValidationError, ValidationErrors};
+
+// Url-encoding can triple the size of the content, so the body limit leaves
+// headroom above `paste.max_size`, which is enforced by `do_paste` itself.
+const FORM_BODY_LIMIT: usize = 16 * 1024 * 1024;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .merge(view::routes())
         .merge(manage::routes())
+        .merge(explore::routes())
         .route("/paste", get(page_paste))
-        .route("/paste", post(do_paste))
+        .route(
+            "/paste",
+            post(do_paste).layer(DefaultBodyLimit::max(FORM_BODY_LIMIT)),
+        )
 }
 
 async fn page_paste(session: Session) -> maud::Markup {
+    paste_form(Some(session), None, &ValidationErrors::new())
+}
+
+fn paste_form(
+    session: Option<Session>,
+    form: Option<&PasteForm>,
+    errors: &ValidationErrors,
+) -> maud::Markup {
+    let filename = form.and_then(|f| f.filename.as_deref());
+    let content = form.map(|f| f.content.as_str()).unwrap_or_default();
+    let visibility = form.map(|f| f.visibility.as_str()).unwrap_or("unlisted");
+
     let markup = maud::html! {
-        div .mb-4 {
+        div .mb-4 .flex .gap-4 {
             a .text-blue-600 .hover:underline href="/paste/manage" { "Manage your pastes" }
+            a .text-blue-600 .hover:underline href="/paste/explore" { "Explore public pastes" }
         }
 
         h2 .text-xl .mb-4 { "New Paste" }
 
         form method="post" {
+            (form::csrf_field())
             div .mb-3 {
                 label for="filename" .block .mb-1 { "Filename" }
-                input
-                    .border-solid
-                    .border-1
-                    .border-gray-300
-                    .w-full
-                    .p-2
-                    type="text"
-                    name="filename"
-                    placeholder="example.txt"
-                    required;
+                (form::input::<PasteForm>("filename")
+                    .value(filename)
+                    .placeholder("example.txt"))
+                (field_errors(errors, "filename"))
             }
 
             div .mb-3 {
@@ -53,16 +73,26 @@ async fn page_paste(session: Session) -> maud::Markup {
                     .p-2
                     name="visibility"
                 {
-                    option value="public" { "Public - visible to everyone" }
-                    option value="unlisted" selected { "Unlisted - only via link" }
-                    option value="private" { "Private - only you" }
+                    option value="public" selected[visibility == "public"] { "Public - visible to everyone" }
+                    option value="unlisted" selected[visibility == "unlisted"] { "Unlisted - only via link" }
+                    option value="private" selected[visibility == "private"] { "Private - only you" }
                 }
             }
 
+            div .mb-3 {
+                label for="password" .block .mb-1 { "Password (optional)" }
+                (form::input::<PasteForm>("password")
+                    .kind("password")
+                    .autocomplete("new-password")
+                    .placeholder("Leave empty for no password"))
+                (field_errors(errors, "password"))
+            }
+
             div .mb-3 {
                 label .block .mb-1 { "Content" }
-                input #content_input type="hidden" name="content";
-                div #editor .relative .w-full style="height: 400px;" .border-solid .border-1 .border-gray-300 { }
+                input #content_input type="hidden" name="content" value=(content);
+                div #editor .relative .w-full style="height: 400px;" .border-solid .border-1 .border-gray-300 { (content) }
+                (field_errors(errors, "content"))
             }
 
             input
@@ -107,29 +137,84 @@ fn ace_enable(editor_id: &str, input_id: &str) -> maud::Markup {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 struct PasteForm {
-    filename: String,
+    #[serde(default, deserialize_with = "form::empty_as_none")]
+    #[validate(filename)]
+    filename: Option<String>,
+    #[validate(length(min = 1, code = "required"))]
+    #[validate(custom(function = "validate_text"))]
     content: String,
     visibility: String,
+    #[validate(length(max = 1024))]
+    password: String,
+}
+
+impl FormPage for PasteForm {
+    async fn render(
+        &self,
+        _state: &AppState,
+        session: Option<Session>,
+        errors: &ValidationErrors,
+    ) -> Result<maud::Markup, AppError> {
+        Ok(paste_form(session, Some(self), errors))
+    }
+}
+
+/// Rejects content that looks binary: NUL bytes or control characters other
+/// than the usual whitespace.
+fn validate_text(content: &str) -> Result<(), ValidationError> {
+    let binary = content
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'));
+
+    if binary {
+        Err(ValidationError::new("binary"))
+    } else {
+        Ok(())
+    }
 }
 
 async fn do_paste(
     state: AppState,
     session: Session,
-    Form(paste): Form<PasteForm>,
-) -> Result<Redirect, AppError> {
+    locale: Locale,
+    ValidatedForm(paste): ValidatedForm<PasteForm>,
+) -> Result<Response, AppError> {
+    let mut errors = ValidationErrors::new();
+    let limits = &state.config.paste;
+    let size = paste.content.len();
+    if size > limits.max_size {
+        errors.add(
+            "content",
+            ValidationError::new("max_size")
+                .add_param("size", size)
+                .add_param("max", limits.max_size),
+        );
+    } else {
+        let used = model::paste::storage_used(&state.db, session.id).await? as u64;
+        if used + size as u64 > limits.user_quota {
+            errors.add(
+                "content",
+                ValidationError::new("quota")
+                    .add_param("quota", limits.user_quota)
+                    .add_param("used", used),
+            );
+        }
+    }
+
+    if !errors.is_empty() {
+        return Ok(form::rerender(&paste, &state, Some(session), locale, errors).await);
+    }
+
     let PasteForm {
         filename,
         content,
         visibility,
+        password,
     } = paste;
 
-    let filename = if filename.trim().is_empty() {
-        "untitled.txt".to_owned()
-    } else {
-        filename.trim().to_owned()
-    };
+    let filename = filename.unwrap_or_else(|| "untitled.txt".to_owned());
 
     let visibility = match visibility.as_str() {
         "public" => model::paste::Visibility::Public,
@@ -137,9 +222,12 @@ async fn do_paste(
         _ => model::paste::Visibility::Unlisted,
     };
 
-    let id =
-        model::paste::create_paste(&state.db, session.id, visibility, filename, content).await?;
+    let password = Some(password).filter(|p| !p.is_empty());
+    let id = model::paste::create_paste(
+        &state.db, session.id, visibility, filename, content, password,
+    )
+    .await?;
 
     let url = format!("/~{}/paste/{}", session.username, id);
-    Ok(Redirect::to(&url))
+    Ok(Redirect::to(&url).into_response())
 }
@@ -1,62 +1,580 @@
+use std::io::{Cursor, Write};
+
+use anyhow::Result;
 use axum::Router;
-use axum::extract::Path;
-use axum::response::{IntoResponse, Response};
-use axum::routing::get;
+use axum::extract::{Form, Path, Query};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Deserialize;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
 
 use crate::middleware::auth::Session;
-use crate::model;
-use crate::routes::{AppError, shell};
+use crate::middleware::client::ClientInfo;
+use crate::model::paste::{File, Paste, Visibility};
+use crate::ratelimit::{Scope, Subject};
+use crate::routes::{AppError, form, shell};
 use crate::state::AppState;
+use crate::{model, utils};
+
+/// Characters left as-is when a filename is placed in a URL or header.
+const FILENAME_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Lets clients of the raw and archive endpoints unlock a paste without a cookie.
+const PASSWORD_HEADER: &str = "x-paste-password";
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/~{username}/paste/{id}", get(page_view_paste))
+    Router::new()
+        .route("/~{username}/paste/{id}", get(page_view_paste))
+        .route("/~{username}/paste/{id}/unlock", post(do_unlock))
+        .route("/~{username}/paste/{id}/fork", post(do_fork))
+        .route("/~{username}/paste/{id}/raw/{*filename}", get(raw_file))
+        .route("/~{username}/paste/{id}/archive.zip", get(archive))
+        .route("/~{username}/paste/{id}/embed", get(page_embed))
+        .route("/~{username}/paste/{id}/embed.js", get(embed_script))
 }
 
-async fn page_view_paste(
-    state: AppState,
-    Path((_username, id)): Path<(String, String)>,
-    session: Option<Session>,
-) -> Result<Response, AppError> {
-    let paste = model::paste::get_paste(&state.db, &id)
+/// Loads a paste, hiding private pastes from everyone but their owner.
+async fn viewable_paste(
+    state: &AppState,
+    id: &str,
+    session: Option<&Session>,
+) -> Result<Paste, AppError> {
+    let paste = model::paste::get_paste(&state.db, id)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("Paste not found"))?;
+        .ok_or(AppError::NotFound)?;
 
-    if paste.visibility == "private" {
-        let is_owner = session.as_ref().is_some_and(|s| s.id == paste.user_id);
+    if paste.visibility == Visibility::Private {
+        let is_owner = session.is_some_and(|s| s.id == paste.user_id);
         if !is_owner {
-            return Err(anyhow::anyhow!("Paste not found").into());
+            return Err(AppError::NotFound);
+        }
+    }
+
+    Ok(paste)
+}
+
+/// Whether the viewer may see the contents of the paste. Pastes without a
+/// password are always unlocked; otherwise the viewer has to own the paste
+/// or hold its unlock cookie.
+fn is_unlocked(
+    state: &AppState,
+    paste: &Paste,
+    session: Option<&Session>,
+    jar: &CookieJar,
+) -> bool {
+    let Some(password_hash) = &paste.password_hash else {
+        return true;
+    };
+
+    if session.is_some_and(|s| s.id == paste.user_id) {
+        return true;
+    }
+
+    let token = model::paste::unlock_token(&state.config.http.secret, password_hash);
+    jar.get(&unlock_cookie_name(&paste.id))
+        .is_some_and(|cookie| utils::constant_time_eq(cookie.value(), &token))
+}
+
+/// Like [`is_unlocked`], but also accepts the password in a header, for
+/// clients of the raw and archive endpoints.
+async fn is_unlocked_or_header(
+    state: &AppState,
+    paste: &Paste,
+    session: Option<&Session>,
+    jar: &CookieJar,
+    client: &ClientInfo,
+    headers: &HeaderMap,
+) -> Result<bool, AppError> {
+    if is_unlocked(state, paste, session, jar) {
+        return Ok(true);
+    }
+
+    match (&paste.password_hash, header_password(headers)) {
+        (Some(password_hash), Some(password)) => {
+            check_password(state, client, password_hash, password).await
         }
+        _ => Ok(false),
+    }
+}
+
+/// Check a guess at a paste's password. Wrong guesses count against the
+/// client's address, which is locked out like failed logins are.
+async fn check_password(
+    state: &AppState,
+    client: &ClientInfo,
+    password_hash: &str,
+    password: &str,
+) -> Result<bool, AppError> {
+    let subjects = [Subject::Ip(client.ip)];
+    if let Some(retry_after) = state.limiter.check(Scope::PastePassword, &subjects) {
+        return Err(AppError::TooManyRequests(retry_after));
+    }
+
+    let valid = model::paste::verify_password(password_hash, password).await?;
+    if valid {
+        state.limiter.reset(Scope::PastePassword, &subjects[0]);
+    } else {
+        state.limiter.record(Scope::PastePassword, &subjects);
+    }
+
+    Ok(valid)
+}
+
+fn unlock_cookie_name(id: &str) -> String {
+    format!("conduit_paste_{}", id)
+}
+
+fn page_locked(base: &str, incorrect: bool, session: Option<Session>) -> Response {
+    let markup = maud::html! {
+        div .max-w-md {
+            h2 .text-xl .mb-4 { "Password required" }
+            p .text-gray-600 .mb-4 { "This paste is protected. Enter its password to view it." }
+
+            @if incorrect {
+                p .text-red-600 .mb-3 { "Incorrect password." }
+            }
+
+            form method="post" action={ (base) "/unlock" } {
+                (form::csrf_field())
+                div .mb-3 {
+                    label for="password" .block .mb-1 { "Password" }
+                    input
+                        .border-solid
+                        .border-1
+                        .border-gray-300
+                        .w-full
+                        .p-2
+                        type="password"
+                        name="password"
+                        autofocus
+                        required;
+                }
+
+                input
+                    .text-neutral-50
+                    .bg-blue-500
+                    .hover:bg-blue-600
+                    .border-neutral-700
+                    .border-solid
+                    .border-1
+                    .px-4
+                    .py-2
+                    .cursor-pointer
+                    type="submit"
+                    value="Unlock";
+            }
+        }
+    };
+
+    shell::document(markup, "locked paste", session).into_response()
+}
+
+#[derive(Deserialize)]
+struct UnlockForm {
+    password: String,
+}
+
+async fn do_unlock(
+    state: AppState,
+    Path((username, id)): Path<(String, String)>,
+    session: Option<Session>,
+    client: ClientInfo,
+    mut jar: CookieJar,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response, AppError> {
+    let paste = viewable_paste(&state, &id, session.as_ref()).await?;
+    let base = paste_url(&username, &paste.id);
+
+    let Some(password_hash) = &paste.password_hash else {
+        return Ok(Redirect::to(&base).into_response());
+    };
+
+    if !check_password(&state, &client, password_hash, &form.password).await? {
+        return Ok(page_locked(&base, true, session));
+    }
+
+    let cookie = Cookie::build((
+        unlock_cookie_name(&paste.id),
+        model::paste::unlock_token(&state.config.http.secret, password_hash),
+    ))
+    .path(base.clone())
+    .http_only(true)
+    .secure(cfg!(not(debug_assertions)))
+    .same_site(SameSite::Lax);
+
+    jar = jar.add(cookie);
+    Ok((jar, Redirect::to(&base)).into_response())
+}
+
+async fn do_fork(
+    state: AppState,
+    Path((_username, id)): Path<(String, String)>,
+    session: Session,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let paste = viewable_paste(&state, &id, Some(&session)).await?;
+    if !is_unlocked(&state, &paste, Some(&session), &jar) {
+        return Ok(password_required());
+    }
+
+    let size = model::paste::paste_size(&state.db, &paste.id).await?;
+    let used = model::paste::storage_used(&state.db, session.id).await?;
+    if (used + size) as u64 > state.config.paste.user_quota {
+        return Ok((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "paste storage quota exceeded",
+        )
+            .into_response());
+    }
+
+    let fork_id = model::paste::fork_paste(&state.db, session.id, &paste.id).await?;
+    Ok(Redirect::to(&paste_url(&session.username, &fork_id)).into_response())
+}
+
+fn password_required() -> Response {
+    (StatusCode::UNAUTHORIZED, "paste password required").into_response()
+}
+
+async fn page_view_paste(
+    state: AppState,
+    Path((username, id)): Path<(String, String)>,
+    session: Option<Session>,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let paste = viewable_paste(&state, &id, session.as_ref()).await?;
+    if !is_unlocked(&state, &paste, session.as_ref(), &jar) {
+        return Ok(page_locked(
+            &paste_url(&username, &paste.id),
+            false,
+            session,
+        ));
     }
 
-    // Infer language mode from filename extension
-    let mode = infer_ace_mode(&paste.filename);
+    let files = model::paste::get_paste_files(&state.db, &paste.id).await?;
+    let parent = match &paste.forked_from {
+        Some(parent_id) => model::paste::get_fork_info(&state.db, parent_id).await?,
+        None => None,
+    };
+    let viewer = session.as_ref().map(|s| s.id);
+    let fork_count = model::paste::count_forks(&state.db, &paste.id, viewer).await?;
+    let forks = model::paste::get_forks(&state.db, &paste.id, viewer).await?;
+    let base = paste_url(&username, &paste.id);
+    let embed_url = format!("{}{}/embed.js", state.config.http.public_url, base);
 
     let markup = maud::html! {
-        div .mb-4 {
-            h2 .text-xl .font-mono { (paste.filename) }
-            div .text-sm .text-gray-600 .mt-1 {
-                span .mr-3 { "ID: " (paste.id) }
-                @if paste.visibility == "public" {
-                    span .text-xs .bg-green-100 .text-green-800 .px-2 .py-1 .rounded { "public" }
-                } @else if paste.visibility == "unlisted" {
-                    span .text-xs .bg-yellow-100 .text-yellow-800 .px-2 .py-1 .rounded { "unlisted" }
-                } @else {
-                    span .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded { "private" }
+        div .mb-4 .flex .justify-between .items-start {
+            div {
+                h2 .text-xl .font-mono { (title_filename(&files)) }
+                @if let Some(parent) = &parent {
+                    div .text-sm .text-gray-600 .mt-1 {
+                        "forked from "
+                        a .text-blue-600 .hover:underline href=(paste_url(&parent.username, &parent.id)) {
+                            "~" (parent.username) "/" (parent.id)
+                        }
+                    }
+                }
+                div .text-sm .text-gray-600 .mt-1 {
+                    span .mr-3 { "ID: " (paste.id) }
+                    @match paste.visibility {
+                        Visibility::Public => {
+                            span .text-xs .bg-green-100 .text-green-800 .px-2 .py-1 .rounded { "public" }
+                        }
+                        Visibility::Unlisted => {
+                            span .text-xs .bg-yellow-100 .text-yellow-800 .px-2 .py-1 .rounded { "unlisted" }
+                        }
+                        Visibility::Private => {
+                            span .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded { "private" }
+                        }
+                    }
+                    @if paste.password_hash.is_some() {
+                        span .text-xs .bg-gray-100 .text-gray-800 .px-2 .py-1 .rounded .ml-1 { "password" }
+                    }
+                    a .ml-3 .text-blue-600 .hover:underline href={ (base) "/archive.zip" } { "download all" }
                 }
             }
+
+            @if session.is_some() {
+                form method="post" action={ (base) "/fork" } {
+                    (form::csrf_field())
+                    button
+                        .border-solid
+                        .border-1
+                        .border-gray-300
+                        .hover:bg-gray-100
+                        .px-3
+                        .py-1
+                        .text-sm
+                        .cursor-pointer
+                        type="submit"
+                    {
+                        "Fork (" (fork_count) ")"
+                    }
+                }
+            } @else {
+                span .text-sm .text-gray-600 { (fork_count) " forks" }
+            }
         }
 
-        div #editor .relative .w-full style="height: 600px;" .border-solid .border-1 .border-gray-300 {
-            (paste.content)
+        @for (index, file) in files.iter().enumerate() {
+            div .mb-6 {
+                div .flex .justify-between .items-center .mb-1 {
+                    span .font-mono { (file.filename) }
+                    span .text-sm {
+                        a .text-blue-600 .hover:underline href=(raw_url(&base, &file.filename)) { "raw" }
+                        " - "
+                        a .text-blue-600 .hover:underline href={ (raw_url(&base, &file.filename)) "?download" } { "download" }
+                    }
+                }
+
+                div id=(format!("editor-{index}")) .relative .w-full style="height: 600px;" .border-solid .border-1 .border-gray-300 {
+                    (file.content)
+                }
+
+                (ace_readonly(&format!("editor-{index}"), infer_ace_mode(&file.filename)))
+            }
         }
 
-        (ace_readonly("editor", mode))
+        @if !forks.is_empty() {
+            div .mb-4 {
+                h3 .text-lg .mb-2 { "Forks" }
+                ul {
+                    @for fork in &forks {
+                        li .text-sm .mb-1 {
+                            a .text-blue-600 .hover:underline href=(paste_url(&fork.username, &fork.id)) {
+                                "~" (fork.username) "/" (fork.id)
+                            }
+                            span .text-gray-500 .ml-2 { (fork.created_at.date()) }
+                        }
+                    }
+                }
+            }
+        }
+
+        @if paste.visibility != Visibility::Private {
+            div .mb-4 {
+                label for="embed" .block .mb-1 .text-sm .text-gray-600 { "Embed this paste" }
+                input #embed
+                    .border-solid
+                    .border-1
+                    .border-gray-300
+                    .w-full
+                    .p-2
+                    .font-mono
+                    .text-sm
+                    type="text"
+                    readonly
+                    value=(format!(r#"<script src="{embed_url}"></script>"#));
+            }
+        }
     };
 
-    let title = format!("{} - paste", paste.filename);
+    let title = format!("{} - paste", title_filename(&files));
     Ok(shell::document_with(markup, &title, session, ace_script()).into_response())
 }
 
+#[derive(Deserialize)]
+struct RawQuery {
+    download: Option<String>,
+}
+
+async fn raw_file(
+    state: AppState,
+    Path((_username, id, filename)): Path<(String, String, String)>,
+    Query(query): Query<RawQuery>,
+    session: Option<Session>,
+    client: ClientInfo,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let paste = viewable_paste(&state, &id, session.as_ref()).await?;
+    if !is_unlocked_or_header(&state, &paste, session.as_ref(), &jar, &client, &headers).await? {
+        return Ok(password_required());
+    }
+
+    let file = model::paste::get_paste_file(&state.db, &paste.id, &filename)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let mut response = file.content.into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    if query.download.is_some() {
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            content_disposition(&file.filename),
+        );
+    }
+
+    Ok(response)
+}
+
+async fn archive(
+    state: AppState,
+    Path((_username, id)): Path<(String, String)>,
+    session: Option<Session>,
+    client: ClientInfo,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let paste = viewable_paste(&state, &id, session.as_ref()).await?;
+    if !is_unlocked_or_header(&state, &paste, session.as_ref(), &jar, &client, &headers).await? {
+        return Ok(password_required());
+    }
+
+    let files = model::paste::get_paste_files(&state.db, &paste.id).await?;
+    let data = build_archive(&files)?;
+
+    let mut response = data.into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/zip"),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition(&format!("{}.zip", paste.id)),
+    );
+
+    Ok(response)
+}
+
+fn header_password(headers: &HeaderMap) -> Option<&str> {
+    headers.get(PASSWORD_HEADER).and_then(|v| v.to_str().ok())
+}
+
+fn build_archive(files: &[File]) -> Result<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for file in files {
+        writer.start_file(&file.filename, options)?;
+        writer.write_all(file.content.as_bytes())?;
+    }
+
+    Ok(writer.finish()?.into_inner())
+}
+
+async fn page_embed(
+    state: AppState,
+    Path((username, id)): Path<(String, String)>,
+    session: Option<Session>,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let paste = viewable_paste(&state, &id, session.as_ref()).await?;
+    let base = paste_url(&username, &paste.id);
+
+    if !is_unlocked(&state, &paste, session.as_ref(), &jar) {
+        let markup = maud::html! {
+            p .text-sm .text-gray-600 .p-2 {
+                "This paste is password protected. "
+                a .text-blue-600 .hover:underline href=(base) target="_blank" { "View it on conduit" }
+                "."
+            }
+        };
+
+        return Ok(shell::embed(markup, "locked paste", maud::html! {}).into_response());
+    }
+
+    let files = model::paste::get_paste_files(&state.db, &paste.id).await?;
+
+    let markup = maud::html! {
+        @for (index, file) in files.iter().enumerate() {
+            div .mb-2 {
+                div .flex .justify-between .items-center .text-sm .px-1 {
+                    span .font-mono { (file.filename) }
+                    a .text-blue-600 .hover:underline href=(raw_url(&base, &file.filename)) target="_blank" { "raw" }
+                }
+
+                div id=(format!("editor-{index}")) .relative .w-full style="height: 300px;" .border-solid .border-1 .border-gray-300 {
+                    (file.content)
+                }
+
+                (ace_readonly(&format!("editor-{index}"), infer_ace_mode(&file.filename)))
+            }
+        }
+
+        div .text-xs .text-gray-500 .px-1 {
+            a .hover:underline href=(base) target="_blank" { "view on conduit" }
+        }
+    };
+
+    let title = format!("{} - paste", title_filename(&files));
+    Ok(shell::embed(markup, &title, ace_script()).into_response())
+}
+
+async fn embed_script(
+    state: AppState,
+    Path((username, id)): Path<(String, String)>,
+    session: Option<Session>,
+) -> Result<Response, AppError> {
+    let paste = viewable_paste(&state, &id, session.as_ref()).await?;
+    let src = format!(
+        "{}{}/embed",
+        state.config.http.public_url,
+        paste_url(&username, &paste.id)
+    );
+
+    let js = format!(
+        r#"
+            (() => {{
+                let iframe = document.createElement("iframe");
+                iframe.src = {src};
+                iframe.style = "width: 100%; height: 360px; border: 0;";
+                document.currentScript.replaceWith(iframe);
+            }})();
+        "#,
+        src = serde_json::to_string(&src).map_err(anyhow::Error::from)?,
+    );
+
+    let mut response = js.into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/javascript; charset=utf-8"),
+    );
+
+    Ok(response)
+}
+
+fn paste_url(username: &str, id: &str) -> String {
+    format!("/~{}/paste/{}", username, id)
+}
+
+fn raw_url(base: &str, filename: &str) -> String {
+    format!(
+        "{}/raw/{}",
+        base,
+        utf8_percent_encode(filename, FILENAME_ENCODE_SET)
+    )
+}
+
+fn title_filename(files: &[File]) -> &str {
+    files.first().map_or("untitled", |f| f.filename.as_str())
+}
+
+/// Builds an attachment header, using the RFC 6266 extended form so that
+/// non-ASCII filenames survive.
+fn content_disposition(filename: &str) -> HeaderValue {
+    let encoded = utf8_percent_encode(filename, FILENAME_ENCODE_SET);
+    let value = format!("attachment; filename*=UTF-8''{}", encoded);
+    HeaderValue::from_str(&value).unwrap()
+}
+
 fn ace_script() -> maud::Markup {
     maud::html! {
         script defer src="/assets/lib/ace-1.43.4/ace.js" {}
@@ -1,4 +1,5 @@
 use crate::middleware::auth::Session;
+use crate::middleware::csrf;
 use crate::routes::assets;
 
 pub fn document<S: Into<Option<Session>>>(
@@ -25,19 +26,28 @@ fn document_impl(
     session: Option<Session>,
     extra: maud::Markup,
 ) -> maud::Markup {
+    let csrf_token = csrf::token();
+    // htmx sends these headers with every request made from the page.
+    let hx_headers = csrf_token
+        .as_ref()
+        .map(|token| serde_json::json!({ "X-CSRF-Token": token }).to_string());
+
     maud::html! {
         (maud::DOCTYPE)
         html lang="en" {
             head {
                 meta charset="UTF-8";
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
+                @if let Some(token) = &csrf_token {
+                    meta name="csrf-token" content=(token);
+                }
                 link rel="stylesheet" href={ "/assets/" (assets::CSS_ASSET_NAME) };
                 (scripts())
                 (extra)
                 title { (title) " - conduit" }
             }
 
-            body {
+            body hx-headers=[hx_headers] {
                 div .container .m-auto .2xl:px-50 .xl:px-20 .lg:px-12 .md:px-4 .sm:px-2 {
                     (header(&session))
                     main { (markup) }
@@ -47,6 +57,26 @@ fn document_impl(
     }
 }
 
+/// A bare document without navigation, meant to be framed by other sites.
+pub fn embed(markup: maud::Markup, title: &str, extra: maud::Markup) -> maud::Markup {
+    maud::html! {
+        (maud::DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                link rel="stylesheet" href={ "/assets/" (assets::CSS_ASSET_NAME) };
+                (extra)
+                title { (title) " - conduit" }
+            }
+
+            body {
+                main { (markup) }
+            }
+        }
+    }
+}
+
 fn scripts() -> maud::Markup {
     maud::html! {
         script src="/assets/lib/htmx-2.0.8.js" {}
@@ -66,6 +96,9 @@ fn header(session: &Option<Session>) -> maud::Markup {
                 ul .flex .grow .ms-12 .gap-8 {
                     li { a .text-gray-500 .hover:text-gray-700 href="/paste" { "paste" } }
                     li { a .text-gray-500 .hover:text-gray-700 href="/meta" { "meta" } }
+                    @if session.is_admin {
+                        li { a .text-gray-500 .hover:text-gray-700 href="/admin" { "admin" } }
+                    }
                 }
 
                 div {
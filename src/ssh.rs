@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::env;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::pin::pin;
 use std::process::Stdio;
@@ -13,9 +14,10 @@ use tracing::debug;
 
 use crate::config::Config;
 use crate::libssh::{ChannelEvent, ChannelStateExt, Session};
-use crate::model;
+use crate::ratelimit::{Scope, Subject};
 use crate::state::AppState;
 use crate::utils::{RingBuf, re};
+use crate::{model, validate};
 
 const LFS_TOKEN_TTL_SECS: u64 = 60 * 60 * 24;
 
@@ -56,8 +58,20 @@ impl ImmediateResponse {
     }
 }
 
-pub async fn handle_session(state: &AppState, mut session: Session) -> anyhow::Result<()> {
+pub async fn handle_session(
+    state: &AppState,
+    mut session: Session,
+    ip: IpAddr,
+) -> anyhow::Result<()> {
     session.configure();
+    if state
+        .limiter
+        .check(Scope::Ssh, &[Subject::Ip(ip)])
+        .is_some()
+    {
+        session.deny_auth();
+    }
+
     let keys = model::user::get_all_ssh_keys(&state.db).await?;
     session.allowed_keys(keys);
     session.handle_key_exchange().await.unwrap();
@@ -68,7 +82,7 @@ pub async fn handle_session(state: &AppState, mut session: Session) -> anyhow::R
     });
 
     // Wait for the exec request to determine what kind of session this is
-    let Some(command) = wait_for_exec_request(&mut session, &mut cancel).await else {
+    let Some(command) = wait_for_exec_request(state, &mut session, ip, &mut cancel).await else {
         return Ok(());
     };
 
@@ -92,14 +106,24 @@ pub async fn handle_session(state: &AppState, mut session: Session) -> anyhow::R
 
 /// Wait for an exec request from the client
 async fn wait_for_exec_request(
+    state: &AppState,
     session: &mut Session,
+    ip: IpAddr,
     cancel: &mut std::pin::Pin<&mut impl Future<Output = ()>>,
 ) -> Option<String> {
+    let mut authenticated = false;
+
     loop {
         select! {
             _ = &mut *cancel => return None,
             res = session.wait() => {
                 res.unwrap();
+                if account_auth_attempts(state, session, ip, &mut authenticated)
+                    && let Some(key) = session.authenticated_key()
+                    && let Err(err) = model::user::touch_key(&state.db, key).await
+                {
+                    tracing::error!("database error recording SSH key use: {}", err);
+                }
                 if let Some(mut channel_state) = session.channel_state() {
                     while let Some(event) = channel_state.events().pop_front() {
                         if let ChannelEvent::ExeqRequest { command } = event {
@@ -115,6 +139,35 @@ async fn wait_for_exec_request(
     }
 }
 
+/// Feed rejected public keys into the rate limiter, and stop accepting
+/// attempts once the address is locked out. SSH users all log in as `git`,
+/// so only the address is tracked. Returns `true` once, when the session
+/// has just authenticated.
+fn account_auth_attempts(
+    state: &AppState,
+    session: &mut Session,
+    ip: IpAddr,
+    authenticated: &mut bool,
+) -> bool {
+    let subjects = [Subject::Ip(ip)];
+
+    for _ in 0..session.take_auth_failures() {
+        state.limiter.record(Scope::Ssh, &subjects);
+    }
+
+    if state.limiter.check(Scope::Ssh, &subjects).is_some() {
+        session.deny_auth();
+    }
+
+    if !*authenticated && session.authenticated_user().is_some() {
+        state.limiter.reset(Scope::Ssh, &subjects[0]);
+        *authenticated = true;
+        return true;
+    }
+
+    false
+}
+
 /// Handle LFS authentication - sends response and closes immediately
 async fn handle_lfs_auth_session(
     state: &AppState,
@@ -200,13 +253,14 @@ async fn handle_git_session(
     repo: &str,
 ) -> anyhow::Result<()> {
     let bin_path = search_path(Path::new(bin)).unwrap();
+    let user = current_username(state, user).await?;
     debug!("Git command: {} for {}/{}", bin, user, repo);
 
     let mut cmd = Command::new(bin_path);
     cmd.stdin(Stdio::piped());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
-    cmd.arg(repo_path(&state.config, user, repo));
+    cmd.arg(repo_path(&state.config, &user, repo));
 
     let mut child = Some(cmd.spawn().unwrap());
     let mut stdout = child.as_mut().unwrap().stdout.take();
@@ -353,6 +407,12 @@ fn search_path(filename: &Path) -> Option<PathBuf> {
     None
 }
 
+/// Remotes set up before a rename keep working under the old username.
+async fn current_username(state: &AppState, user: &str) -> anyhow::Result<String> {
+    let current = model::user::resolve_redirect(&state.db, user).await?;
+    Ok(current.unwrap_or_else(|| user.to_owned()))
+}
+
 fn repo_path(config: &Config, user: &str, repo: &str) -> PathBuf {
     config.git.repository_path.join(user).join(repo)
 }
@@ -360,12 +420,15 @@ fn repo_path(config: &Config, user: &str, repo: &str) -> PathBuf {
 /// Parse an SSH exec command into a structured command type
 fn parse_ssh_command(command: &str) -> Result<SshCommand<'_>, &'static str> {
     // Try LFS auth first
-    if let Some(caps) = re!(
-        r#"^git-lfs-authenticate '?/?~([a-zA-Z0-9]+)/([\.\-a-zA-Z0-9]+\.git)'? (download|upload)$"#
-    )
-    .captures(command)
+    if let Some(caps) =
+        re!(r#"^git-lfs-authenticate '?/?~([^/' ]+)/([^/' ]+)'? (download|upload)$"#)
+            .captures(command)
     {
         let (_, [user, repo, operation]) = caps.extract();
+        if !validate::is_repo_path(user, repo) {
+            return Err("invalid repository path");
+        }
+
         return Ok(SshCommand::LfsAuth(LfsAuthRequest {
             user: user.to_owned(),
             repo: repo.to_owned(),
@@ -374,7 +437,7 @@ fn parse_ssh_command(command: &str) -> Result<SshCommand<'_>, &'static str> {
     }
 
     // Try standard git command
-    let caps = re!(r#"^([a-zA-Z\-]+) '/?~([a-zA-Z0-9]+)/([\.\-a-zA-Z0-9]+\.git)'$"#)
+    let caps = re!(r#"^([a-zA-Z\-]+) '/?~([^/']+)/([^/']+)'$"#)
         .captures(command)
         .ok_or("invalid command format")?;
 
@@ -383,6 +446,10 @@ fn parse_ssh_command(command: &str) -> Result<SshCommand<'_>, &'static str> {
         return Err("unsupported command");
     }
 
+    if !validate::is_repo_path(user, repo) {
+        return Err("invalid repository path");
+    }
+
     Ok(SshCommand::Git { bin, user, repo })
 }
 
@@ -402,7 +469,15 @@ async fn handle_lfs_auth(
         return ImmediateResponse::error(b"authentication failed\n");
     };
 
-    if username != request.user {
+    let user = match current_username(state, &request.user).await {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::error!("database error resolving username: {}", e);
+            return ImmediateResponse::error(b"internal error\n");
+        }
+    };
+
+    if username != user {
         return ImmediateResponse::error(b"repository access denied\n");
     }
 
@@ -425,7 +500,7 @@ async fn handle_lfs_auth(
             }
         };
 
-    let response = lfs_auth_response(state, &request.user, &request.repo, &token.token);
+    let response = lfs_auth_response(state, &user, &request.repo, &token.token);
     let mut payload = match serde_json::to_vec(&response) {
         Ok(p) => p,
         Err(e) => {
@@ -9,12 +9,14 @@ use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
 use crate::config::Config;
+use crate::ratelimit::RateLimiter;
 
 pub struct AppStateInner {
     pub db: PgPool,
     pub config: Config,
     pub cancel_token: CancellationToken,
     pub task_tracker: TaskTracker,
+    pub limiter: RateLimiter,
 }
 
 #[derive(Clone)]
@@ -44,3 +46,35 @@ impl FromRequestParts<AppState> for AppState {
         Ok(state.clone())
     }
 }
+
+#[cfg(test)]
+impl AppState {
+    /// State for tests against the docker-compose services, configured by
+    /// `config.example.toml` with the services reached through their
+    /// published ports. `MAIL_HOST` and `LDAP_URL` override where they are.
+    pub fn for_tests(db: PgPool) -> Self {
+        Self::for_tests_with(db, |_| ())
+    }
+
+    /// State for tests, with `configure` applied to the test configuration.
+    pub fn for_tests_with(db: PgPool, configure: impl FnOnce(&mut Config)) -> Self {
+        let mut config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+
+        if let Some(mail) = &mut config.mail {
+            mail.host = std::env::var("MAIL_HOST").unwrap_or_else(|_| "localhost".into());
+        }
+        if let Some(ldap) = &mut config.ldap {
+            ldap.url = std::env::var("LDAP_URL").unwrap_or_else(|_| "ldap://localhost:389".into());
+        }
+
+        configure(&mut config);
+
+        Self::new(AppStateInner {
+            db,
+            limiter: RateLimiter::new(config.auth.rate_limit.clone()),
+            config,
+            cancel_token: CancellationToken::new(),
+            task_tracker: TaskTracker::new(),
+        })
+    }
+}
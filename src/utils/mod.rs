@@ -49,3 +49,12 @@ pub async fn unique_string(
         }
     }
 }
+
+/// Compare secrets without leaking the position of the first difference.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -1,5 +1,12 @@
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::sync::LazyLock;
+
+use base64::engine::Engine;
+use serde::{Serialize, Serializer};
+
+use crate::state::AppState;
 
 // ---------------------------------------------------------------------------
 // Error types
@@ -8,6 +15,8 @@ use std::fmt;
 #[derive(Debug, Clone)]
 pub struct ValidationError {
     pub code: &'static str,
+    /// An explicit message, which takes precedence over the catalog. Also
+    /// set by `ValidationErrors::localize`.
     pub message: Option<String>,
     pub params: Vec<(&'static str, String)>,
 }
@@ -30,23 +39,47 @@ impl ValidationError {
         self.params.push((name, value.to_string()));
         self
     }
+
+    /// For validators that could not reach a verdict, such as when a database
+    /// lookup fails. The underlying error is logged rather than shown.
+    pub fn unavailable(err: impl fmt::Display) -> Self {
+        tracing::error!("validation lookup failed: {}", err);
+        Self::new("unavailable")
+    }
 }
 
 impl fmt::Display for ValidationError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(ref msg) = self.message {
-            write!(f, "{}", msg)
-        } else {
-            write!(f, "Validation error: {}", self.code)
-        }
+        write!(f, "{}", Catalog::fallback().message(self))
+    }
+}
+
+impl Serialize for ValidationError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let params: BTreeMap<&str, &str> = self
+            .params
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+
+        let mut state = serializer.serialize_struct("ValidationError", 3)?;
+        state.serialize_field("code", self.code)?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("params", &params)?;
+        state.end()
     }
 }
 
 impl std::error::Error for ValidationError {}
 
-#[derive(Debug, Default)]
+/// Serializes as a map from field path to a list of
+/// `{"code": ..., "message": ..., "params": {...}}` objects.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
 pub struct ValidationErrors {
-    errors: BTreeMap<&'static str, Vec<ValidationError>>,
+    errors: BTreeMap<Cow<'static, str>, Vec<ValidationError>>,
 }
 
 impl ValidationErrors {
@@ -54,15 +87,17 @@ impl ValidationErrors {
         Self::default()
     }
 
-    pub fn add(&mut self, field: &'static str, error: ValidationError) {
-        self.errors.entry(field).or_default().push(error);
+    pub fn add(&mut self, field: impl Into<Cow<'static, str>>, error: ValidationError) {
+        self.errors.entry(field.into()).or_default().push(error);
     }
 
     pub fn is_empty(&self) -> bool {
         self.errors.is_empty()
     }
 
-    pub fn field_errors(&self) -> &BTreeMap<&'static str, Vec<ValidationError>> {
+    /// Errors keyed by field. Errors from nested values use dotted paths
+    /// such as `files.0.filename`.
+    pub fn field_errors(&self) -> &BTreeMap<Cow<'static, str>, Vec<ValidationError>> {
         &self.errors
     }
 
@@ -70,12 +105,34 @@ impl ValidationErrors {
         if self.is_empty() { Ok(()) } else { Err(self) }
     }
 
-    pub fn merge_self(&mut self, field: &'static str, other: Result<(), ValidationErrors>) {
+    pub fn merge(&mut self, other: ValidationErrors) {
+        for (field, errs) in other.errors {
+            self.errors.entry(field).or_default().extend(errs);
+        }
+    }
+
+    /// Resolve every message through `catalog`, interpolating parameters.
+    /// Errors with an explicit message keep it.
+    pub fn localize(mut self, catalog: &Catalog) -> Self {
+        for errors in self.errors.values_mut() {
+            for error in errors {
+                error.message = Some(catalog.message(error));
+            }
+        }
+        self
+    }
+
+    /// Merge the errors of a nested value, prefixing their keys with `field`.
+    pub fn merge_self(
+        &mut self,
+        field: impl Into<Cow<'static, str>>,
+        other: Result<(), ValidationErrors>,
+    ) {
         if let Err(other) = other {
-            for (_, errs) in other.errors {
-                for e in errs {
-                    self.add(field, e);
-                }
+            let field = field.into();
+            for (key, errs) in other.errors {
+                let path = format!("{}.{}", field, key);
+                self.errors.entry(path.into()).or_default().extend(errs);
             }
         }
     }
@@ -94,6 +151,127 @@ impl fmt::Display for ValidationErrors {
 
 impl std::error::Error for ValidationErrors {}
 
+// ---------------------------------------------------------------------------
+// Message catalog
+// ---------------------------------------------------------------------------
+
+/// Catalogs compiled into the binary, by language. The first is the fallback.
+const LOCALES: &[(&str, &str)] = &[("en", include_str!("../locales/en.toml"))];
+
+static CATALOGS: LazyLock<Vec<(&str, Catalog)>> = LazyLock::new(|| {
+    LOCALES
+        .iter()
+        .map(|(locale, source)| {
+            let catalog = Catalog::parse(source)
+                .unwrap_or_else(|err| panic!("invalid message catalog {}: {}", locale, err));
+            (*locale, catalog)
+        })
+        .collect()
+});
+
+/// Validation messages for one locale, keyed by error code. Codes whose text
+/// depends on which parameters are present use keys like `length.min_max`,
+/// with a `_one` suffix for the form used when the last parameter is 1.
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn parse(source: &str) -> Result<Self, toml::de::Error> {
+        let table: toml::Table = toml::from_str(source)?;
+        let mut messages = HashMap::new();
+
+        for (key, value) in table {
+            match value {
+                toml::Value::String(message) => {
+                    messages.insert(key, message);
+                }
+                toml::Value::Table(variants) => {
+                    for (variant, value) in variants {
+                        if let toml::Value::String(message) = value {
+                            messages.insert(format!("{}.{}", key, variant), message);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { messages })
+    }
+
+    pub fn fallback() -> &'static Catalog {
+        &CATALOGS[0].1
+    }
+
+    /// The catalog for a language tag such as `en` or `en-US`.
+    pub fn get(locale: &str) -> Option<&'static Catalog> {
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+        CATALOGS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(language))
+            .map(|(_, catalog)| catalog)
+    }
+
+    /// Pick the best catalog for an `Accept-Language` header value.
+    pub fn negotiate(accept_language: Option<&str>) -> &'static Catalog {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((tag, quality))
+            })
+            .collect();
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges
+            .into_iter()
+            .find_map(|(tag, _)| Self::get(tag))
+            .unwrap_or_else(Self::fallback)
+    }
+
+    /// The text for an error: its explicit message if it has one, otherwise
+    /// the catalog entry for its code, with `{param}` placeholders filled in.
+    pub fn message(&self, error: &ValidationError) -> String {
+        let template = match &error.message {
+            Some(message) => message.as_str(),
+            None => self.lookup(error),
+        };
+
+        let mut message = template.to_owned();
+        for (name, value) in &error.params {
+            message = message.replace(&format!("{{{}}}", name), value);
+        }
+        message
+    }
+
+    fn lookup(&self, error: &ValidationError) -> &str {
+        let params: Vec<&str> = error.params.iter().map(|(name, _)| *name).collect();
+        let variant = format!("{}.{}", error.code, params.join("_"));
+
+        let mut keys = Vec::new();
+        if error.params.last().is_some_and(|(_, value)| value == "1") {
+            keys.push(format!("{}_one", variant));
+        }
+        keys.extend([
+            variant,
+            format!("{}.default", error.code),
+            error.code.to_owned(),
+            "default".to_owned(),
+        ]);
+
+        keys.iter()
+            .find_map(|key| self.messages.get(key))
+            .map_or(error.code, String::as_str)
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Core trait
 // ---------------------------------------------------------------------------
@@ -102,6 +280,40 @@ pub trait Validate {
     fn validate(&self) -> Result<(), ValidationErrors>;
 }
 
+/// Checks that need application state, such as uniqueness lookups in the
+/// database. Generated by the derive from `async_custom` validators, whose
+/// functions are called as `function(&field, &state).await` and return
+/// `Result<(), ValidationError>`. The state is [`AppState`] unless the type
+/// names another with `#[validate(state = "Type")]`.
+pub trait ValidateAsync<S = AppState> {
+    fn validate_async(
+        &self,
+        state: &S,
+    ) -> impl Future<Output = Result<(), ValidationErrors>> + Send;
+}
+
+// ---------------------------------------------------------------------------
+// HTML constraints
+// ---------------------------------------------------------------------------
+
+/// The HTML5 constraint attributes matching a field's validators, so that
+/// client-side hints agree with what the server enforces.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldConstraints {
+    pub required: bool,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub pattern: Option<&'static str>,
+    pub input_type: Option<&'static str>,
+}
+
+/// Generated by the derive for structs.
+pub trait Constraints {
+    fn constraints(field: &str) -> FieldConstraints;
+}
+
 // ---------------------------------------------------------------------------
 // ValidateLength
 // ---------------------------------------------------------------------------
@@ -529,6 +741,272 @@ impl<T: ValidateIp> ValidateIp for Option<T> {
     }
 }
 
+// ---------------------------------------------------------------------------
+// Domain validators
+// ---------------------------------------------------------------------------
+//
+// These encode the rules for names and keys that reach the filesystem, git
+// or the SSH server, so that every entry point agrees on them. They report
+// why a value was rejected, so unlike the validators above they return the
+// error rather than a bool.
+
+macro_rules! domain_validator {
+    ($trait:ident, $method:ident, $check:path) => {
+        pub trait $trait {
+            fn $method(&self) -> Result<(), ValidationError>;
+        }
+
+        impl $trait for str {
+            fn $method(&self) -> Result<(), ValidationError> {
+                $check(self)
+            }
+        }
+
+        impl $trait for String {
+            fn $method(&self) -> Result<(), ValidationError> {
+                self.as_str().$method()
+            }
+        }
+
+        impl<T: $trait + ?Sized> $trait for &T {
+            fn $method(&self) -> Result<(), ValidationError> {
+                (**self).$method()
+            }
+        }
+
+        impl<T: $trait> $trait for Option<T> {
+            fn $method(&self) -> Result<(), ValidationError> {
+                match self {
+                    Some(v) => v.$method(),
+                    None => Ok(()),
+                }
+            }
+        }
+    };
+}
+
+domain_validator!(ValidateUsername, validate_username, check_username);
+domain_validator!(ValidateRepoName, validate_repo_name, check_repo_name);
+domain_validator!(
+    ValidateGitRefName,
+    validate_git_ref_name,
+    check_git_ref_name
+);
+domain_validator!(ValidateFilename, validate_filename, check_filename);
+domain_validator!(
+    ValidateSshPublicKey,
+    validate_ssh_public_key,
+    check_ssh_public_key
+);
+
+/// Also used as the HTML `pattern` of username inputs.
+pub const USERNAME_PATTERN: &str = r"[a-zA-Z0-9][a-zA-Z0-9_\-]*";
+pub const USERNAME_MAX_LENGTH: usize = 39;
+
+/// Names that could be mistaken for the instance itself or its staff.
+const RESERVED_USERNAMES: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "api",
+    "conduit",
+    "git",
+    "help",
+    "hostmaster",
+    "mailer-daemon",
+    "meta",
+    "noreply",
+    "no-reply",
+    "postmaster",
+    "root",
+    "security",
+    "staff",
+    "support",
+    "system",
+    "webmaster",
+];
+
+/// Whether `name` has the shape of a username. Unlike [`check_username`]
+/// this accepts reserved names, since accounts may predate the list.
+pub fn is_username(name: &str) -> bool {
+    static RE: LazyLock<regex_lite::Regex> =
+        LazyLock::new(|| regex_lite::Regex::new(&format!("^{}$", USERNAME_PATTERN)).unwrap());
+
+    name.len() <= USERNAME_MAX_LENGTH && RE.is_match(name)
+}
+
+pub fn check_username(name: &str) -> Result<(), ValidationError> {
+    if name.is_empty() || name.len() > USERNAME_MAX_LENGTH {
+        return Err(ValidationError::new("length")
+            .add_param("min", 1)
+            .add_param("max", USERNAME_MAX_LENGTH));
+    }
+
+    if !is_username(name) {
+        return Err(ValidationError::new("username"));
+    }
+
+    if RESERVED_USERNAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+    {
+        return Err(ValidationError::new("reserved"));
+    }
+
+    Ok(())
+}
+
+/// Also used as the HTML `pattern` of repository name inputs. The `.git`
+/// suffix rule has no equivalent there and is only checked server-side.
+pub const REPO_NAME_PATTERN: &str = r"[a-zA-Z0-9_\-][a-zA-Z0-9._\-]*";
+pub const REPO_NAME_MAX_LENGTH: usize = 100;
+
+/// Whether `name` is a repository name, without the `.git` suffix used for
+/// the directory on disk.
+pub fn is_repo_name(name: &str) -> bool {
+    check_repo_name(name).is_ok()
+}
+
+pub fn check_repo_name(name: &str) -> Result<(), ValidationError> {
+    static RE: LazyLock<regex_lite::Regex> =
+        LazyLock::new(|| regex_lite::Regex::new(&format!("^{}$", REPO_NAME_PATTERN)).unwrap());
+
+    if name.is_empty() || name.len() > REPO_NAME_MAX_LENGTH {
+        return Err(ValidationError::new("length")
+            .add_param("min", 1)
+            .add_param("max", REPO_NAME_MAX_LENGTH));
+    }
+
+    if !RE.is_match(name) || name.ends_with(".git") {
+        return Err(ValidationError::new("repo_name"));
+    }
+
+    Ok(())
+}
+
+/// Whether `user` and `repo` name a repository as it appears in clone URLs,
+/// such as `~alice/dotfiles.git`.
+pub fn is_repo_path(user: &str, repo: &str) -> bool {
+    is_username(user) && repo.strip_suffix(".git").is_some_and(is_repo_name)
+}
+
+/// The rules of `git check-ref-format --allow-onelevel`, so that branch
+/// names like `main` are accepted alongside `refs/heads/main`.
+pub fn check_git_ref_name(name: &str) -> Result<(), ValidationError> {
+    let error = || ValidationError::new("git_ref_name");
+
+    if name.is_empty() || name == "@" || name.ends_with('.') {
+        return Err(error());
+    }
+
+    if name.contains("..") || name.contains("@{") {
+        return Err(error());
+    }
+
+    let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    if name.chars().any(forbidden) {
+        return Err(error());
+    }
+
+    // Also rejects a leading or trailing slash and `//`, which produce an
+    // empty component.
+    for component in name.split('/') {
+        if component.is_empty() || component.starts_with('.') || component.ends_with(".lock") {
+            return Err(error());
+        }
+    }
+
+    Ok(())
+}
+
+pub const FILENAME_MAX_LENGTH: usize = 255;
+
+/// A single path component that is safe to use as a file name on disk and
+/// in archives.
+pub fn check_filename(name: &str) -> Result<(), ValidationError> {
+    if name.is_empty() || name.len() > FILENAME_MAX_LENGTH {
+        return Err(ValidationError::new("length")
+            .add_param("min", 1)
+            .add_param("max", FILENAME_MAX_LENGTH));
+    }
+
+    let forbidden = |c: char| c.is_control() || c == '/' || c == '\\';
+    if name == "." || name == ".." || name.chars().any(forbidden) {
+        return Err(ValidationError::new("filename"));
+    }
+
+    Ok(())
+}
+
+/// The key types the SSH server accepts.
+pub const SSH_KEY_TYPES: &[&str] = &["ssh-ed25519"];
+
+/// A public key in OpenSSH `authorized_keys` format, such as
+/// `ssh-ed25519 AAAAC3... alice@laptop`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SshPublicKey<'a> {
+    pub key_type: &'a str,
+    /// The base64 encoded key blob.
+    pub encoded: &'a str,
+    pub comment: Option<&'a str>,
+}
+
+impl<'a> SshPublicKey<'a> {
+    /// Parses and verifies a key: the blob must decode and describe a key
+    /// of the declared, supported type.
+    pub fn parse(key: &'a str) -> Result<Self, ValidationError> {
+        let mut parts = key.split_whitespace();
+        let (Some(key_type), Some(encoded)) = (parts.next(), parts.next()) else {
+            return Err(ValidationError::new("ssh_key_format"));
+        };
+        let comment = parts.next();
+
+        if !SSH_KEY_TYPES.contains(&key_type) {
+            return Err(ValidationError::new("ssh_key_type").add_param("key_type", key_type));
+        }
+
+        let blob_error = || ValidationError::new("ssh_key_blob").add_param("key_type", key_type);
+        let blob = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| blob_error())?;
+
+        let mut rest = blob.as_slice();
+        let declared = read_ssh_string(&mut rest).ok_or_else(blob_error)?;
+        if declared != key_type.as_bytes() {
+            return Err(blob_error());
+        }
+
+        // An ed25519 key is a single 32 byte string.
+        let point = read_ssh_string(&mut rest).ok_or_else(blob_error)?;
+        if point.len() != 32 || !rest.is_empty() {
+            return Err(blob_error());
+        }
+
+        Ok(Self {
+            key_type,
+            encoded,
+            comment,
+        })
+    }
+}
+
+/// Reads an RFC 4251 `string`: a big-endian u32 length and that many bytes.
+fn read_ssh_string<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (len, rest) = data.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return None;
+    }
+
+    let (string, rest) = rest.split_at(len);
+    *data = rest;
+    Some(string)
+}
+
+pub fn check_ssh_public_key(key: &str) -> Result<(), ValidationError> {
+    SshPublicKey::parse(key).map(|_| ())
+}
+
 // ---------------------------------------------------------------------------
 // must_match helper (used by generated code)
 // ---------------------------------------------------------------------------
@@ -666,4 +1144,326 @@ mod tests {
         let err = form.validate().unwrap_err();
         assert!(err.field_errors().contains_key("bio"));
     }
+
+    #[derive(Validate)]
+    struct FileForm {
+        #[validate(length(min = 1, max = 255))]
+        filename: String,
+    }
+
+    #[derive(Validate)]
+    struct MultiFileForm {
+        #[validate(nested)]
+        primary: FileForm,
+
+        #[validate(each(nested))]
+        files: Vec<FileForm>,
+
+        #[validate(each(length(max = 10), non_control_character))]
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn nested_and_each_paths() {
+        let form = MultiFileForm {
+            primary: FileForm {
+                filename: "".into(),
+            },
+            files: vec![
+                FileForm {
+                    filename: "a.txt".into(),
+                },
+                FileForm {
+                    filename: "".into(),
+                },
+            ],
+            tags: vec!["rust".into(), "far too long a tag".into(), "tab\t".into()],
+        };
+        let err = form.validate().unwrap_err();
+        let keys: Vec<&str> = err.field_errors().keys().map(|k| k.as_ref()).collect();
+        assert_eq!(
+            keys,
+            ["files.1.filename", "primary.filename", "tags.1", "tags.2"]
+        );
+    }
+
+    #[derive(Validate)]
+    enum Body {
+        Paste {
+            #[validate(length(min = 1))]
+            content: String,
+        },
+        Key(#[validate(contains(pattern = "ssh-"))] String),
+        Ping,
+    }
+
+    #[test]
+    fn constraints() {
+        let username = TestForm::constraints("username");
+        assert!(username.required);
+        assert_eq!(username.min_length, Some(1));
+        assert_eq!(username.max_length, Some(100));
+
+        let email = TestForm::constraints("email");
+        assert!(email.required);
+        assert_eq!(email.input_type, Some("email"));
+
+        let website = TestForm::constraints("website");
+        assert!(!website.required);
+        assert_eq!(website.input_type, Some("url"));
+
+        assert!(TestForm::constraints("bio").required);
+        assert_eq!(TestForm::constraints("age").max, Some(150.0));
+        assert_eq!(
+            TestForm::constraints("unknown"),
+            FieldConstraints::default()
+        );
+    }
+
+    #[test]
+    fn catalog_messages() {
+        let form = TestForm {
+            username: "".into(),
+            email: "alice@example.com".into(),
+            password: "short".into(),
+            password_confirm: "short".into(),
+            age: 200,
+            website: None,
+            contact: "hello@world".into(),
+            bio: Some("Hi".into()),
+        };
+        let err = form.validate().unwrap_err().localize(Catalog::fallback());
+        let fields = err.field_errors();
+        assert_eq!(
+            fields["username"][0].to_string(),
+            "must be between 1 and 100 characters"
+        );
+        assert_eq!(
+            fields["password"][0].to_string(),
+            "must be at least 8 characters"
+        );
+        assert_eq!(fields["age"][0].to_string(), "must be between 0 and 150");
+
+        let locked_out = |minutes| ValidationError::new("locked_out").add_param("minutes", minutes);
+        assert_eq!(
+            locked_out(1).to_string(),
+            "too many failed attempts, try again in 1 minute"
+        );
+        assert_eq!(
+            locked_out(5).to_string(),
+            "too many failed attempts, try again in 5 minutes"
+        );
+    }
+
+    #[test]
+    fn serialize_errors() {
+        let mut errors = ValidationErrors::new();
+        errors.add(
+            "username",
+            ValidationError::new("length").add_param("max", 39),
+        );
+        errors.add("email", ValidationError::new("email_taken"));
+        errors.add(
+            "name",
+            ValidationError::new("taken").with_message("already registered"),
+        );
+        errors.add(
+            "password",
+            ValidationError::new("locked_out").add_param("minutes", 1),
+        );
+
+        // Explicit messages take precedence over the catalog.
+        let errors = errors.localize(Catalog::fallback());
+        let json = serde_json::to_value(&errors).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "email": [{
+                    "code": "email_taken",
+                    "message": "an account with this email already exists",
+                    "params": {},
+                }],
+                "name": [{"code": "taken", "message": "already registered", "params": {}}],
+                "password": [{
+                    "code": "locked_out",
+                    "message": "too many failed attempts, try again in 1 minute",
+                    "params": {"minutes": "1"},
+                }],
+                "username": [{
+                    "code": "length",
+                    "message": "must be at most 39 characters",
+                    "params": {"max": "39"},
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn negotiate_locale() {
+        let en = Catalog::fallback();
+        assert!(std::ptr::eq(Catalog::negotiate(None), en));
+        assert!(std::ptr::eq(
+            Catalog::negotiate(Some("xx;q=0.9, en-GB;q=0.8")),
+            en
+        ));
+    }
+
+    #[test]
+    fn enum_variants() {
+        assert!(Body::Ping.validate().is_ok());
+        assert!(Body::Key("ssh-ed25519 AAAA".into()).validate().is_ok());
+
+        let err = Body::Paste { content: "".into() }.validate().unwrap_err();
+        assert!(err.field_errors().contains_key("content"));
+
+        let err = Body::Key("rsa".into()).validate().unwrap_err();
+        assert!(err.field_errors().contains_key("0"));
+    }
+
+    #[test]
+    fn domain_validators() {
+        assert!(check_username("alice-01").is_ok());
+        assert!(check_username("-alice").is_err());
+        assert_eq!(check_username("Admin").unwrap_err().code, "reserved");
+        assert!(is_username("admin"));
+        assert_eq!(check_username(&"a".repeat(40)).unwrap_err().code, "length");
+
+        assert!(check_repo_name("dotfiles").is_ok());
+        assert!(check_repo_name("..").is_err());
+        assert!(check_repo_name("repo.git").is_err());
+        assert!(is_repo_path("alice", "my.repo.git"));
+        assert!(!is_repo_path("alice", "my.repo"));
+        assert!(!is_repo_path("alice", "...git"));
+
+        for name in ["main", "refs/heads/feature/x", "v1.0"] {
+            assert!(check_git_ref_name(name).is_ok(), "{}", name);
+        }
+        for name in [
+            "", "@", "a..b", "/main", "main/", "a//b", ".hidden", "x.lock", "a b", "a@{1}", "end.",
+        ] {
+            assert!(check_git_ref_name(name).is_err(), "{}", name);
+        }
+
+        assert!(check_filename("notes.md").is_ok());
+        for name in ["", ".", "..", "a/b", "a\\b", "a\0b"] {
+            assert!(check_filename(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[derive(Validate)]
+    struct Account {
+        #[validate(username)]
+        name: String,
+        #[validate(filename(code = "reserved"))]
+        file: Option<String>,
+        #[validate(length(max = 3, message = "keep it short"))]
+        tag: String,
+    }
+
+    #[test]
+    fn domain_attributes() {
+        let account = Account {
+            name: "root".into(),
+            file: Some("..".into()),
+            tag: "long".into(),
+        };
+        let err = account.validate().unwrap_err();
+        assert_eq!(err.field_errors()["name"][0].code, "reserved");
+        assert_eq!(err.field_errors()["file"][0].code, "reserved");
+        assert_eq!(
+            err.field_errors()["file"][0].to_string(),
+            "this name is reserved"
+        );
+        assert_eq!(err.field_errors()["tag"][0].code, "length");
+        assert_eq!(err.field_errors()["tag"][0].to_string(), "keep it short");
+
+        let name = Account::constraints("name");
+        assert!(name.required);
+        assert_eq!(name.max_length, Some(USERNAME_MAX_LENGTH));
+        assert_eq!(name.pattern, Some(USERNAME_PATTERN));
+
+        let file = Account::constraints("file");
+        assert!(!file.required);
+        assert_eq!(file.max_length, Some(FILENAME_MAX_LENGTH));
+    }
+
+    struct Taken(Vec<&'static str>);
+
+    async fn available(name: &str, taken: &Taken) -> Result<(), ValidationError> {
+        if taken.0.contains(&name) {
+            Err(ValidationError::new("taken"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[derive(Validate)]
+    #[validate(state = "Taken")]
+    struct Signup {
+        #[validate(length(min = 1))]
+        #[validate(async_custom(function = "available", code = "username_taken"))]
+        name: String,
+        #[validate(nested)]
+        team: Team,
+    }
+
+    #[derive(Validate)]
+    #[validate(state = "Taken")]
+    struct Team {
+        #[validate(async_custom(function = "available"))]
+        name: String,
+    }
+
+    #[test]
+    fn async_custom() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let taken = Taken(vec!["alice", "admins"]);
+        let signup = |name: &str, team: &str| Signup {
+            name: name.into(),
+            team: Team { name: team.into() },
+        };
+
+        let valid = signup("bob", "devs");
+        assert!(runtime.block_on(valid.validate_async(&taken)).is_ok());
+
+        let err = runtime
+            .block_on(signup("alice", "admins").validate_async(&taken))
+            .unwrap_err();
+        assert_eq!(
+            err.field_errors()["name"][0].to_string(),
+            "username is already taken"
+        );
+        assert_eq!(err.field_errors()["team.name"][0].code, "taken");
+
+        // Async validators only run in validate_async.
+        assert!(signup("alice", "admins").validate().is_ok());
+        assert!(signup("", "devs").validate().is_err());
+    }
+
+    #[test]
+    fn ssh_public_keys() {
+        let mut blob = Vec::new();
+        blob.extend_from_slice(&11u32.to_be_bytes());
+        blob.extend_from_slice(b"ssh-ed25519");
+        blob.extend_from_slice(&32u32.to_be_bytes());
+        blob.extend_from_slice(&[7; 32]);
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&blob);
+
+        let line = format!("ssh-ed25519 {} alice@laptop", encoded);
+        let key = SshPublicKey::parse(&line).unwrap();
+        assert_eq!(key.key_type, "ssh-ed25519");
+        assert_eq!(key.encoded, encoded);
+        assert_eq!(key.comment, Some("alice@laptop"));
+
+        let code = |key: &str| SshPublicKey::parse(key).unwrap_err().code;
+        assert_eq!(code("ssh-ed25519"), "ssh_key_format");
+        assert_eq!(code(&format!("ssh-rsa {}", encoded)), "ssh_key_type");
+        assert_eq!(code("ssh-ed25519 AAAA"), "ssh_key_blob");
+        assert_eq!(code("ssh-ed25519 not-base64!"), "ssh_key_blob");
+
+        let truncated = base64::engine::general_purpose::STANDARD.encode(&blob[..40]);
+        assert_eq!(code(&format!("ssh-ed25519 {}", truncated)), "ssh_key_blob");
+    }
 }